@@ -0,0 +1,141 @@
+//! How the renderer's snapshot of the devices holds up while the ingest updates them.
+//!
+//! Writer threads update the rotation of every device as fast as they can, while the main
+//! thread takes snapshots like the render loop does. The same load runs against the registry
+//! of the ingest, and against a single `RwLock` around all devices read with `try_read`, as the
+//! viewer did before, where a lost race is an empty frame.
+//!
+//! ```text
+//! cargo bench --bench snapshot
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::hint::black_box;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tcp_3d_viewer::ingest::{ClientData, ClientRotation, Clients};
+use tokio::sync::RwLock;
+
+/// Threads updating devices, each taking its share of them.
+const WRITERS: usize = 4;
+/// How long each case runs.
+const RUN_TIME: Duration = Duration::from_secs(1);
+
+/// What a registry has to offer the benchmark.
+trait Registry: Send + Sync + 'static {
+    fn update(&self, ip: IpAddr, angle: f32);
+    /// Take a snapshot, returning the number of sensors in it.
+    fn snapshot(&self) -> usize;
+}
+
+impl Registry for ClientData {
+    fn update(&self, ip: IpAddr, angle: f32) {
+        let client = self.get(&ip).expect("device is registered");
+        client.update(0, |state| state.rotation = ClientRotation { x: angle, y: 0.0, z: 0.0 });
+    }
+
+    fn snapshot(&self) -> usize {
+        Clients::snapshot(self).iter().map(|(_, client)| client.sensors.len()).sum()
+    }
+}
+
+/// The viewer's previous registry: all devices behind one lock.
+type Locked = Arc<RwLock<HashMap<IpAddr, BTreeMap<u8, [f32; 3]>>>>;
+
+impl Registry for Locked {
+    fn update(&self, ip: IpAddr, angle: f32) {
+        if let Some(sensors) = self.blocking_write().get_mut(&ip) {
+            sensors.insert(0, [angle, 0.0, 0.0]);
+        }
+    }
+
+    fn snapshot(&self) -> usize {
+        match self.try_read() {
+            Ok(clients) => clients.values().map(|sensors| sensors.len()).sum(),
+            Err(_) => 0,
+        }
+    }
+}
+
+fn main() {
+    println!(
+        "{:<10} {:>8} {:>14} {:>12} {:>12} {:>12} {:>8}",
+        "registry", "devices", "updates/s", "snapshots/s", "mean", "max", "empty"
+    );
+    for devices in [10, 100, 1000] {
+        let ips: Vec<_> = (0..devices).map(|i| IpAddr::V4(Ipv4Addr::from(u32::from(Ipv4Addr::new(127, 0, 3, 1)) + i as u32))).collect();
+
+        let registry = ClientData::default();
+        for ip in &ips {
+            registry.connect(*ip);
+        }
+        run("snapshot", registry, &ips);
+
+        let locked = Locked::default();
+        for ip in &ips {
+            locked.blocking_write().insert(*ip, BTreeMap::from([(0, [0.0; 3])]));
+        }
+        run("rwlock", locked, &ips);
+    }
+}
+
+/// Update all devices from the writer threads while taking snapshots, and print the figures.
+fn run<R: Registry + Clone>(name: &str, registry: R, ips: &[IpAddr]) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let updates = Arc::new(AtomicU64::new(0));
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|writer| {
+            let registry = registry.clone();
+            let ips: Vec<_> = ips.iter().copied().skip(writer).step_by(WRITERS).collect();
+            let stop = stop.clone();
+            let updates = updates.clone();
+            thread::spawn(move || {
+                let mut angle = 0.0f32;
+                while !stop.load(Ordering::Relaxed) {
+                    for ip in &ips {
+                        registry.update(*ip, angle);
+                    }
+                    angle += 0.01;
+                    updates.fetch_add(ips.len() as u64, Ordering::Relaxed);
+                }
+            })
+        })
+        .collect();
+
+    let start = Instant::now();
+    let mut snapshots = 0u32;
+    let mut empty = 0u32;
+    let mut total = Duration::ZERO;
+    let mut longest = Duration::ZERO;
+    while start.elapsed() < RUN_TIME {
+        let before = Instant::now();
+        let sensors = black_box(registry.snapshot());
+        let took = before.elapsed();
+        snapshots += 1;
+        total += took;
+        longest = longest.max(took);
+        if sensors < ips.len() {
+            empty += 1;
+        }
+    }
+    let elapsed = start.elapsed();
+    stop.store(true, Ordering::Relaxed);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    println!(
+        "{:<10} {:>8} {:>14.0} {:>12.0} {:>12?} {:>12?} {:>8}",
+        name,
+        ips.len(),
+        updates.load(Ordering::Relaxed) as f64 / elapsed.as_secs_f64(),
+        snapshots as f64 / elapsed.as_secs_f64(),
+        total / snapshots,
+        longest,
+        empty,
+    );
+}
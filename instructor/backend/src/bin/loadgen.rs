@@ -0,0 +1,577 @@
+//! Load generator for the viewer's device ingest.
+//!
+//! Opens many simulated device connections at once and reports every second how the ingest
+//! keeps up: frames and bytes sent, frames applied, how far behind the ingest is, and frames
+//! lost or corrupted on the way. By default the ingest runs in this process, on a port of its
+//! own, so what was sent can be compared with what the ingest applied. With `--server` the load
+//! goes to a running viewer instead, and only the sending side is reported.
+//!
+//! A share of the connections misbehaves, as set by `--mix`: slow links trickling frames a few
+//! bytes at a time and never reading what the viewer sends, garbage bytes between frames, and
+//! disconnects in the middle of a frame. Figures are reported per behaviour, since only
+//! well-behaved connections are expected to lose nothing. The viewer tells devices apart by
+//! their IP address, so each connection comes from its own loopback address, 127.0.3.1 and up.
+//!
+//! ```text
+//! xl-loadgen [--connections 100] [--rate 400] [--batch 16] [--duration 10]
+//!            [--encoding vectors|attitude|raw|compressed]
+//!            [--mix slow=0.1,garbage=0.05,disconnect=0.05] [--server 127.0.0.1:8080]
+//! ```
+
+use std::collections::{BTreeMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tcp_3d_viewer::ingest::{self, Client, ClientData};
+use tcp_3d_viewer::simulated;
+use tokio::io::AsyncWriteExt;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{sleep, Instant, MissedTickBehavior};
+use workshop_common::attitude::Attitude;
+use workshop_common::calibration::Calibration;
+use workshop_common::link;
+use workshop_common::protocol::{self, Frame, MAX_SAMPLES};
+use workshop_common::raw::{RawFormat, RawSample};
+use workshop_common::Sample;
+
+/// First of the loopback addresses connections come from.
+const FIRST_ADDRESS: Ipv4Addr = Ipv4Addr::new(127, 0, 3, 1);
+
+/// Format of the raw samples sent: ±2 g, 12 bits, like the LIS3DH in high resolution mode.
+const RAW_FORMAT: RawFormat = RawFormat {
+    scale: 2.0 / 32768.0,
+    resolution: 12,
+    calibration: Calibration::IDENTITY,
+};
+
+/// Bytes a slow connection writes at a time, and the pause after each.
+const SLOW_CHUNK: usize = 16;
+const SLOW_PAUSE: Duration = Duration::from_millis(5);
+
+/// Chance of garbage bytes before each frame of a garbage connection, and their most.
+const GARBAGE_CHANCE: f64 = 0.05;
+const MAX_GARBAGE: usize = 64;
+
+/// Time after which a disconnecting connection drops, picked at random between the two.
+const DISCONNECT_AFTER: (f32, f32) = (0.5, 3.0);
+
+/// Time to wait before connecting again.
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+/// Time the ingest gets to catch up once the load stops.
+const DRAIN_TIME: Duration = Duration::from_secs(2);
+
+/// How a connection behaves, besides sending its samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Behaviour {
+    /// Sends whole frames, and reads what the viewer sends.
+    Normal,
+    /// Trickles frames a few bytes at a time, and never reads what the viewer sends.
+    Slow,
+    /// Sends garbage bytes between some of its frames.
+    Garbage,
+    /// Drops the connection in the middle of a frame every few seconds, and connects again.
+    Disconnect,
+}
+
+impl Behaviour {
+    fn name(&self) -> &'static str {
+        match self {
+            Behaviour::Normal => "normal",
+            Behaviour::Slow => "slow",
+            Behaviour::Garbage => "garbage",
+            Behaviour::Disconnect => "disconnect",
+        }
+    }
+}
+
+/// Share of the connections behaving in each way other than [`Behaviour::Normal`].
+#[derive(Clone, Copy, Debug, Default)]
+struct Mix {
+    slow: f32,
+    garbage: f32,
+    disconnect: f32,
+}
+
+impl Mix {
+    /// Behaviour of connection `index` of `count`. The shares are rounded to whole connections,
+    /// taken from the start in the order slow, garbage, disconnect.
+    fn behaviour(&self, index: usize, count: usize) -> Behaviour {
+        let end = |share: f32| (share * count as f32).round() as usize;
+        if index < end(self.slow) {
+            Behaviour::Slow
+        } else if index < end(self.slow + self.garbage) {
+            Behaviour::Garbage
+        } else if index < end(self.slow + self.garbage + self.disconnect) {
+            Behaviour::Disconnect
+        } else {
+            Behaviour::Normal
+        }
+    }
+}
+
+impl FromStr for Mix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mix = Mix::default();
+        for entry in s.split(',').filter(|entry| !entry.is_empty()) {
+            let (name, share) = entry.split_once('=').ok_or_else(|| format!("expected name=share, got '{}'", entry))?;
+            let share: f32 = share.parse().map_err(|e| format!("share of {}: {}", name, e))?;
+            if !(0.0..=1.0).contains(&share) {
+                return Err(format!("share of {} must be between 0 and 1", name));
+            }
+            match name {
+                "slow" => mix.slow = share,
+                "garbage" => mix.garbage = share,
+                "disconnect" => mix.disconnect = share,
+                _ => return Err(format!("unknown behaviour '{}', expected slow, garbage or disconnect", name)),
+            }
+        }
+        if mix.slow + mix.garbage + mix.disconnect > 1.0 {
+            return Err(String::from("shares add up to more than 1"));
+        }
+        Ok(mix)
+    }
+}
+
+/// What the connections send their samples as, like the firmware's output modes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    /// Samples in g.
+    Vectors,
+    /// One orientation per batch.
+    Attitude,
+    /// Samples in counts, after their format.
+    Raw,
+    /// Samples in counts, delta encoded when that is smaller.
+    Compressed,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vectors" => Ok(Encoding::Vectors),
+            "attitude" => Ok(Encoding::Attitude),
+            "raw" => Ok(Encoding::Raw),
+            "compressed" => Ok(Encoding::Compressed),
+            _ => Err(format!("unknown encoding '{}', expected vectors, attitude, raw or compressed", s)),
+        }
+    }
+}
+
+/// The load every connection sends.
+#[derive(Clone, Copy, Debug)]
+struct Config {
+    server: SocketAddr,
+    /// Samples per second.
+    rate: f32,
+    /// Samples per frame.
+    batch: usize,
+    encoding: Encoding,
+    /// When to stop sending.
+    until: Instant,
+    /// Whether to remember when frames were sent, to measure the lag of the ingest.
+    track: bool,
+}
+
+/// What a connection sent, over all its connects. Shared with the report.
+#[derive(Debug, Default)]
+struct Sent {
+    /// Frames written whole.
+    frames: AtomicU64,
+    bytes: AtomicU64,
+    /// Number and send time of the frames the ingest may not have applied yet, oldest first.
+    pending: Mutex<VecDeque<(u64, Instant)>>,
+}
+
+impl Sent {
+    /// Count a frame about to be written.
+    fn frame(&self, len: usize, at: Instant, track: bool) {
+        let number = self.frames.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(len as u64, Ordering::Relaxed);
+        if track {
+            self.pending.lock().unwrap().push_back((number, at));
+        }
+    }
+
+    /// Time since the oldest frame the ingest did not read yet was sent, forgetting the frames
+    /// before it. `read` frames were read so far.
+    fn lag(&self, read: u64, now: Instant) -> Duration {
+        let mut pending = self.pending.lock().unwrap();
+        while pending.front().is_some_and(|(number, _)| *number < read) {
+            pending.pop_front();
+        }
+        pending.front().map_or(Duration::ZERO, |(_, at)| now - *at)
+    }
+}
+
+/// A simulated device.
+struct Connection {
+    index: usize,
+    behaviour: Behaviour,
+    address: Option<IpAddr>,
+    sent: Arc<Sent>,
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let count = option("--connections", 100usize);
+    let mix = option("--mix", Mix::default());
+    let duration = Duration::from_secs_f32(option("--duration", 10.0));
+    let batch = option("--batch", 16usize);
+    if !(1..=MAX_SAMPLES).contains(&batch) {
+        eprintln!("--batch must be between 1 and {}", MAX_SAMPLES);
+        std::process::exit(2);
+    }
+
+    // Without a server to send to, run the ingest here, where its counters can be read
+    let external = std::env::args().any(|arg| arg == "--server");
+    let (server, clients) = if external {
+        (option("--server", SocketAddr::from(([127, 0, 0, 1], ingest::PORT))), None)
+    } else {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.expect("Failed to bind TCP listener");
+        let server = listener.local_addr().expect("Listener has an address");
+        let clients = ClientData::default();
+        tokio::spawn(ingest::serve(listener, clients.clone(), None));
+        (server, Some(clients))
+    };
+
+    let config = Config {
+        server,
+        rate: option("--rate", 400.0),
+        batch,
+        encoding: option("--encoding", Encoding::Vectors),
+        until: Instant::now() + duration,
+        track: clients.is_some(),
+    };
+    println!(
+        "{} connections to {} sending {:?} at {} Hz in batches of {}, {:?}, for {:?}",
+        count, config.server, config.encoding, config.rate, config.batch, mix, duration
+    );
+
+    let connections: Vec<_> = (0..count)
+        .map(|index| {
+            let address = match server.ip() {
+                IpAddr::V4(ip) if ip.is_loopback() => Some(IpAddr::V4(Ipv4Addr::from(u32::from(FIRST_ADDRESS) + index as u32))),
+                _ => None,
+            };
+            Arc::new(Connection {
+                index,
+                behaviour: mix.behaviour(index, count),
+                address,
+                sent: Arc::default(),
+            })
+        })
+        .collect();
+    for connection in &connections {
+        tokio::spawn(run(connection.clone(), config));
+    }
+
+    let mut report = Report::new(connections, clients);
+    let mut ticks = tokio::time::interval(Duration::from_secs(1));
+    ticks.tick().await;
+    while Instant::now() < config.until {
+        ticks.tick().await;
+        report.update();
+        if let Some(clients) = &report.clients {
+            // Gives slow connections something not to read
+            ingest::request_calibration(clients);
+        }
+    }
+    if report.clients.is_some() {
+        println!("Load stopped, giving the ingest {:?} to catch up", DRAIN_TIME);
+        sleep(DRAIN_TIME).await;
+    }
+    if !report.summary() {
+        std::process::exit(1);
+    }
+}
+
+/// The value following `name` on the command line, or `default` if it is not given. Exits on
+/// values that do not parse.
+fn option<T: FromStr>(name: &str, default: T) -> T
+where
+    T::Err: std::fmt::Display,
+{
+    match std::env::args().skip_while(|arg| arg != name).nth(1) {
+        Some(value) => value.parse().unwrap_or_else(|e| {
+            eprintln!("Invalid {} '{}': {}", name, value, e);
+            std::process::exit(2);
+        }),
+        None => default,
+    }
+}
+
+/// Send until the load stops, connecting again whenever the connection fails or drops.
+async fn run(connection: Arc<Connection>, config: Config) {
+    let mut rng = StdRng::seed_from_u64(connection.index as u64);
+    // Connections start at different points of the motion, so they do not move in lockstep
+    let start = Instant::now() - Duration::from_secs_f32(connection.index as f32 * 0.37);
+    while Instant::now() < config.until {
+        match connect(config.server, connection.address).await {
+            Ok(stream) => {
+                if let Err(e) = play(&connection, config, start, stream, &mut rng).await {
+                    log::debug!("Connection {} failed: {}", connection.index, e);
+                }
+            }
+            Err(e) => log::debug!("Connection {} failed connecting to {}: {}", connection.index, config.server, e),
+        }
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Connect from `address` if given. Once binding failed for one connection it fails for all of
+/// them, so the others no longer try, and the ingest sees a single device.
+async fn connect(server: SocketAddr, address: Option<IpAddr>) -> std::io::Result<TcpStream> {
+    static BIND_FAILED: AtomicBool = AtomicBool::new(false);
+    let mut address = address.filter(|_| !BIND_FAILED.load(Ordering::Relaxed));
+    let bind = address.is_some();
+    let stream = simulated::connect_from(server, &mut address).await;
+    if bind && address.is_none() {
+        BIND_FAILED.store(true, Ordering::Relaxed);
+    }
+    stream
+}
+
+/// Send frames until the load stops, the connection fails or the behaviour drops it.
+async fn play(connection: &Connection, config: Config, start: Instant, stream: TcpStream, rng: &mut StdRng) -> std::io::Result<()> {
+    let behaviour = connection.behaviour;
+    let sent = &connection.sent;
+    let (mut reader, mut writer) = stream.into_split();
+    // Read and drop what the viewer sends, unless the connection is a slow reader
+    let drain = (behaviour != Behaviour::Slow).then(|| {
+        tokio::spawn(async move {
+            let _ = tokio::io::copy(&mut reader, &mut tokio::io::sink()).await;
+        })
+    });
+    let result = async {
+        write(&mut writer, behaviour, &protocol::MAGIC).await?;
+        let mut buf = [0u8; protocol::MAX_FRAME_LEN];
+        if matches!(config.encoding, Encoding::Raw | Encoding::Compressed) {
+            let len = Frame::RawFormat(RAW_FORMAT).encode(&mut buf).expect("buffer fits any frame");
+            sent.frame(len, Instant::now(), config.track);
+            write(&mut writer, behaviour, &buf[..len]).await?;
+        }
+
+        let period = Duration::from_secs_f32(1.0 / config.rate);
+        let mut ticks = tokio::time::interval(period * config.batch as u32);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let drop_at = Instant::now() + Duration::from_secs_f32(rng.gen_range(DISCONNECT_AFTER.0..DISCONNECT_AFTER.1));
+        loop {
+            let now = ticks.tick().await;
+            if now >= config.until {
+                return Ok(());
+            }
+            let len = encode(config, (now - start).as_secs_f32(), period.as_secs_f32(), &mut buf);
+            match behaviour {
+                Behaviour::Garbage if rng.gen_bool(GARBAGE_CHANCE) => {
+                    let garbage: Vec<u8> = (0..rng.gen_range(1..=MAX_GARBAGE)).map(|_| rng.gen()).collect();
+                    writer.write_all(&garbage).await?;
+                }
+                Behaviour::Disconnect if now >= drop_at => {
+                    // Half a frame, which the ingest never gets to apply
+                    writer.write_all(&buf[..len / 2]).await?;
+                    return Ok(());
+                }
+                _ => {}
+            }
+            sent.frame(len, now, config.track);
+            write(&mut writer, behaviour, &buf[..len]).await?;
+        }
+    }
+    .await;
+    if let Some(drain) = drain {
+        drain.abort();
+    }
+    result
+}
+
+/// Write `bytes`, a few at a time on slow connections.
+async fn write(writer: &mut OwnedWriteHalf, behaviour: Behaviour, bytes: &[u8]) -> std::io::Result<()> {
+    if behaviour != Behaviour::Slow {
+        return writer.write_all(bytes).await;
+    }
+    for chunk in bytes.chunks(SLOW_CHUNK) {
+        writer.write_all(chunk).await?;
+        sleep(SLOW_PAUSE).await;
+    }
+    Ok(())
+}
+
+/// Encode the frame for the batch of samples ending `t` seconds into the motion, `period`
+/// seconds apart, returning its length.
+fn encode(config: Config, t: f32, period: f32, buf: &mut [u8]) -> usize {
+    let samples = (0..config.batch).map(|i| {
+        let t = t - (config.batch - 1 - i) as f32 * period;
+        let [x, y, z] = simulated::tilt(t);
+        Sample::new(x, y, z)
+    });
+    // Counts with the bits below the resolution cleared, like the sensor reports them
+    let mask = !((1i16 << (16 - RAW_FORMAT.resolution)) - 1);
+    let count = |g: f32| (g / RAW_FORMAT.scale).round() as i16 & mask;
+    let raw = || {
+        samples.clone().map(|s| RawSample {
+            x: count(s.x),
+            y: count(s.y),
+            z: count(s.z),
+        })
+    };
+    let frame = match config.encoding {
+        Encoding::Vectors => Frame::Samples(samples.clone().collect()),
+        Encoding::Attitude => Frame::Attitude(Attitude::from_sample(&samples.clone().next_back().expect("batch is not empty"))),
+        Encoding::Raw => Frame::RawSamples(raw().collect()),
+        Encoding::Compressed => link::raw_samples(raw().collect(), true),
+    };
+    frame.encode(buf).expect("buffer fits any frame")
+}
+
+/// Figures of the connections behaving one way.
+#[derive(Clone, Copy, Debug, Default)]
+struct Totals {
+    connections: usize,
+    /// Frames and bytes sent.
+    frames: u64,
+    bytes: u64,
+    /// Frames the ingest applied, and frames it failed to decode.
+    applied: u64,
+    invalid: u64,
+    /// Longest time a frame waited to be read, over the connections.
+    lag: Duration,
+}
+
+/// Periodic and final figures, per behaviour.
+struct Report {
+    connections: Vec<Arc<Connection>>,
+    /// The ingest running in this process, if any.
+    clients: Option<ClientData>,
+    /// The device the ingest keeps for each connection, once it connected.
+    devices: Vec<Option<Arc<Client>>>,
+    started: Instant,
+    previous: (Instant, BTreeMap<Behaviour, Totals>),
+    /// Longest lag seen so far.
+    worst: BTreeMap<Behaviour, Duration>,
+}
+
+impl Report {
+    fn new(connections: Vec<Arc<Connection>>, clients: Option<ClientData>) -> Self {
+        let now = Instant::now();
+        Self {
+            devices: vec![None; connections.len()],
+            connections,
+            clients,
+            started: now,
+            previous: (now, BTreeMap::new()),
+            worst: BTreeMap::new(),
+        }
+    }
+
+    /// Current figures of every behaviour present.
+    fn totals(&mut self) -> BTreeMap<Behaviour, Totals> {
+        if let Some(clients) = &self.clients {
+            for (connection, device) in self.connections.iter().zip(&mut self.devices) {
+                if device.is_none() {
+                    *device = connection.address.and_then(|ip| clients.get(&ip));
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let mut totals = BTreeMap::<Behaviour, Totals>::new();
+        for (connection, device) in self.connections.iter().zip(&self.devices) {
+            let total = totals.entry(connection.behaviour).or_default();
+            total.connections += 1;
+            total.frames += connection.sent.frames.load(Ordering::Relaxed);
+            total.bytes += connection.sent.bytes.load(Ordering::Relaxed);
+            let Some(stats) = device.as_ref().map(|device| &device.stats) else { continue };
+            let applied = stats.frames.load(Ordering::Relaxed);
+            let invalid = stats.invalid.load(Ordering::Relaxed);
+            total.applied += applied;
+            total.invalid += invalid;
+            // Garbage can make the ingest read any number of frames, so their send times say
+            // nothing about how far behind it is
+            let lag = connection.sent.lag(applied + invalid, now);
+            if connection.behaviour != Behaviour::Garbage {
+                total.lag = total.lag.max(lag);
+            }
+        }
+        totals
+    }
+
+    /// Print the figures since the previous update.
+    fn update(&mut self) {
+        let now = Instant::now();
+        let totals = self.totals();
+        let (then, previous) = std::mem::replace(&mut self.previous, (now, totals.clone()));
+        let seconds = (now - then).as_secs_f64();
+        let elapsed = (now - self.started).as_secs();
+        for (behaviour, total) in &totals {
+            let before = previous.get(behaviour).copied().unwrap_or_default();
+            let mut line = format!(
+                "[{:>4}s] {:<10} x{:<5} sent {:>8.0} frames/s {:>7.2} MB/s",
+                elapsed,
+                behaviour.name(),
+                total.connections,
+                (total.frames - before.frames) as f64 / seconds,
+                (total.bytes - before.bytes) as f64 / seconds / 1e6,
+            );
+            if self.clients.is_some() {
+                line += &format!(
+                    ", applied {:>8.0} frames/s, backlog {:>6}, invalid {:>5}",
+                    (total.applied - before.applied) as f64 / seconds,
+                    total.frames.saturating_sub(total.applied + total.invalid),
+                    total.invalid,
+                );
+                if *behaviour != Behaviour::Garbage {
+                    line += &format!(", lag {:>8.1?}", total.lag);
+                    let worst = self.worst.entry(*behaviour).or_default();
+                    *worst = (*worst).max(total.lag);
+                }
+            }
+            println!("{}", line);
+        }
+    }
+
+    /// Print the figures of the whole run, returning whether well-behaved connections lost
+    /// nothing.
+    fn summary(&mut self) -> bool {
+        let totals = self.totals();
+        let seconds = (Instant::now() - self.started).as_secs_f64();
+        println!("Summary over {:.1} s:", seconds);
+        let mut clean = true;
+        for (behaviour, total) in &totals {
+            let mut line = format!(
+                "  {:<10} x{:<5} sent {} frames, {:.2} MB",
+                behaviour.name(),
+                total.connections,
+                total.frames,
+                total.bytes as f64 / 1e6,
+            );
+            if self.clients.is_some() {
+                let dropped = total.frames.saturating_sub(total.applied);
+                line += &format!(", applied {}, dropped {}, corrupted {}", total.applied, dropped, total.invalid);
+                if let Some(worst) = self.worst.get(behaviour) {
+                    line += &format!(", worst lag {:.1?}", worst);
+                }
+                if *behaviour == Behaviour::Normal && (dropped > 0 || total.invalid > 0) {
+                    clean = false;
+                }
+            }
+            println!("{}", line);
+        }
+        if !clean {
+            println!("Well-behaved connections lost frames");
+        }
+        clean
+    }
+}
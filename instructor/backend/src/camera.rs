@@ -0,0 +1,153 @@
+//! The viewer's camera, orbiting a target point, and kept between runs in a text file of one
+//! setting per line.
+//!
+//! ```text
+//! target 0 0 0
+//! yaw 0
+//! pitch 0
+//! distance 30
+//! orthographic false
+//! ```
+
+use cgmath::{Deg, InnerSpace, Matrix4, Point3, Rad, Vector3};
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+/// Vertical field of view of the perspective projection.
+const FIELD_OF_VIEW: Deg<f32> = Deg(45.0);
+/// Closest and furthest the camera gets to its target.
+const DISTANCE_RANGE: (f32, f32) = (1.0, 200.0);
+/// How far beyond the target the scene is drawn.
+const DEPTH: f32 = 100.0;
+/// Share of the view height a focused shape fills.
+const FOCUS_FILL: f32 = 0.6;
+/// Closest the camera gets to looking straight down or up, so it always knows where up is.
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    /// Point the camera looks at, orbits and zooms towards
+    pub target: Point3<f32>,
+    /// Angle around the vertical axis in radians, zero looking along -z
+    pub yaw: f32,
+    /// Angle above the horizontal in radians
+    pub pitch: f32,
+    /// Distance from the target
+    pub distance: f32,
+    /// Whether to draw without perspective, keeping the size shapes have at the target
+    pub orthographic: bool,
+}
+
+impl Default for Camera {
+    /// Looking at the grid of devices head on, from where it fits the window.
+    fn default() -> Self {
+        Self {
+            target: Point3::new(0.0, 0.0, 0.0),
+            yaw: 0.0,
+            pitch: 0.0,
+            distance: 30.0,
+            orthographic: false,
+        }
+    }
+}
+
+impl Camera {
+    fn eye(&self) -> Point3<f32> {
+        let (yaw_sin, yaw_cos) = self.yaw.sin_cos();
+        let (pitch_sin, pitch_cos) = self.pitch.sin_cos();
+        self.target + Vector3::new(yaw_sin * pitch_cos, pitch_sin, yaw_cos * pitch_cos) * self.distance
+    }
+
+    /// Height of the view at the target.
+    fn view_height(&self) -> f32 {
+        self.distance * height_per_distance()
+    }
+
+    pub fn view_proj(&self, aspect: f32) -> Matrix4<f32> {
+        let view = Matrix4::look_at_rh(self.eye(), self.target, Vector3::unit_y());
+        let far = self.distance + DEPTH;
+        let proj = if self.orthographic {
+            let top = self.view_height() / 2.0;
+            cgmath::ortho(-top * aspect, top * aspect, -top, top, 0.1, far)
+        } else {
+            cgmath::perspective(FIELD_OF_VIEW, aspect, 0.1, far)
+        };
+        proj * view
+    }
+
+    /// Turn the camera `yaw` radians around the target, and `pitch` radians up over it.
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = (self.yaw + yaw) % std::f32::consts::TAU;
+        self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    /// Move the camera to `factor` times its distance from the target.
+    pub fn zoom(&mut self, factor: f32) {
+        self.distance = (self.distance * factor).clamp(DISTANCE_RANGE.0, DISTANCE_RANGE.1);
+    }
+
+    /// Move the camera and its target sideways, by `right` and `up` in heights of the view at
+    /// the target.
+    pub fn pan(&mut self, right: f32, up: f32) {
+        let forward = (self.target - self.eye()).normalize();
+        let right_axis = forward.cross(Vector3::unit_y()).normalize();
+        let up_axis = right_axis.cross(forward);
+        self.target += (right_axis * right + up_axis * up) * self.view_height();
+    }
+
+    /// Look at `target` head on, from where a shape of `size` across fills most of the view.
+    pub fn focus(&mut self, target: Point3<f32>, size: f32) {
+        self.target = target;
+        self.yaw = 0.0;
+        self.pitch = 0.0;
+        self.distance = (size / FOCUS_FILL / height_per_distance()).clamp(DISTANCE_RANGE.0, DISTANCE_RANGE.1);
+    }
+
+    /// Back to the default view, keeping the projection.
+    pub fn reset(&mut self) {
+        *self = Self { orthographic: self.orthographic, ..Self::default() };
+    }
+
+    /// The camera saved at `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut camera = Self::default();
+        for line in fs::read_to_string(path)?.lines() {
+            let invalid = || Error::new(ErrorKind::InvalidData, format!("invalid line: {}", line));
+            let mut words = line.split_whitespace();
+            let setting = words.next().unwrap_or("");
+            let values: Vec<&str> = words.collect();
+            let number = |index: usize| -> io::Result<f32> {
+                let value: f32 = values.get(index).ok_or_else(invalid)?.parse().map_err(|_| invalid())?;
+                value.is_finite().then_some(value).ok_or_else(invalid)
+            };
+            match setting {
+                "target" => camera.target = Point3::new(number(0)?, number(1)?, number(2)?),
+                "yaw" => camera.yaw = number(0)?,
+                "pitch" => camera.pitch = number(0)?.clamp(-MAX_PITCH, MAX_PITCH),
+                "distance" => camera.distance = number(0)?.clamp(DISTANCE_RANGE.0, DISTANCE_RANGE.1),
+                "orthographic" => camera.orthographic = values.first().ok_or_else(invalid)?.parse().map_err(|_| invalid())?,
+                "" => {}
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(camera)
+    }
+
+    /// Save the camera to `path`, to be read back by [`Camera::load`].
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let Point3 { x, y, z } = self.target;
+        fs::write(
+            path,
+            format!(
+                "target {} {} {}\nyaw {}\npitch {}\ndistance {}\northographic {}\n",
+                x, y, z, self.yaw, self.pitch, self.distance, self.orthographic
+            ),
+        )
+    }
+}
+
+/// How much higher the view gets for each unit further from the camera.
+fn height_per_distance() -> f32 {
+    2.0 * (Rad::from(FIELD_OF_VIEW).0 / 2.0).tan()
+}
@@ -0,0 +1,458 @@
+//! Devices connecting over TCP, and the state the viewer keeps of them.
+
+use arc_swap::ArcSwap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Notify};
+use workshop_common::attitude::Attitude;
+use workshop_common::calibration::CalibrationStatus;
+use workshop_common::health::{Health, SensorStatus};
+use workshop_common::motion::MotionEvent;
+use workshop_common::protocol::{self, Frame, Header};
+
+use crate::renderer::Shape;
+
+/// Port devices connect to
+pub const PORT: u16 = 8080;
+
+#[derive(Debug, Clone)]
+pub struct ClientRotation {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct SensorState {
+    pub shape: Shape,
+    pub rotation: ClientRotation,
+    /// Last motion event reported by the sensor, and when it arrived
+    pub last_event: Option<(MotionEvent, std::time::Instant)>,
+    /// Last health report, sent by the device when it connects
+    pub health: Option<Health>,
+    /// Last calibration progress report, and when it arrived
+    pub calibration: Option<(CalibrationStatus, std::time::Instant)>,
+}
+
+/// A device and the sensors it has reported so far.
+#[derive(Debug, Clone)]
+pub struct ClientState {
+    /// Shape of sensor 0, further sensors get the shapes following it
+    shape_index: usize,
+    /// Mesh assigned to the device, shown for all its sensors instead of the shapes
+    mesh: Option<usize>,
+    pub sensors: BTreeMap<u8, SensorState>,
+}
+
+impl ClientState {
+    fn new(shape_index: usize, mesh: Option<usize>) -> Self {
+        let mut client = Self {
+            shape_index,
+            mesh,
+            sensors: BTreeMap::new(),
+        };
+        client.sensor(0);
+        client
+    }
+
+    /// State of the given sensor, added if the device did not report it before.
+    fn sensor(&mut self, index: u8) -> &mut SensorState {
+        let shape = match self.mesh {
+            Some(mesh) => Shape::Mesh(mesh),
+            None => Shape::from_index((self.shape_index + index as usize) % Shape::count()),
+        };
+        self.sensors.entry(index).or_insert_with(|| SensorState {
+            shape,
+            rotation: ClientRotation { x: 0.0, y: 0.0, z: 0.0 },
+            last_event: None,
+            health: None,
+            calibration: None,
+        })
+    }
+}
+
+/// Frames read from a device, over all its connections.
+#[derive(Debug, Default)]
+pub struct ClientStats {
+    /// Frames decoded and applied
+    pub frames: AtomicU64,
+    /// Frames that failed to decode
+    pub invalid: AtomicU64,
+}
+
+impl ClientStats {
+    fn record(&self, valid: bool) {
+        let counter = if valid { &self.frames } else { &self.invalid };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A device, kept after it disconnects so it gets the same shapes when it connects again.
+#[derive(Debug)]
+pub struct Client {
+    /// Replaced as a whole on every update, so readers always see a consistent state
+    state: ArcSwap<ClientState>,
+    pub stats: ClientStats,
+    /// Frames to send to the device, while it is connected
+    commands: Mutex<Option<mpsc::UnboundedSender<Frame>>>,
+    /// Signalled on every update, shared by all devices
+    changed: Arc<Notify>,
+}
+
+impl Client {
+    fn new(shape_index: usize, mesh: Option<usize>, changed: Arc<Notify>) -> Self {
+        Self {
+            state: ArcSwap::from_pointee(ClientState::new(shape_index, mesh)),
+            stats: ClientStats::default(),
+            commands: Mutex::new(None),
+            changed,
+        }
+    }
+
+    /// The latest state of the device.
+    pub fn state(&self) -> Arc<ClientState> {
+        self.state.load_full()
+    }
+
+    /// Apply `update` to a copy of the state of `sensor`, and publish the copy. Updates from
+    /// several connections of the device retry, so `update` may run more than once.
+    pub fn update(&self, sensor: u8, update: impl Fn(&mut SensorState)) {
+        self.state.rcu(|state| {
+            let mut state = ClientState::clone(state);
+            update(state.sensor(sensor));
+            state
+        });
+        self.changed.notify_one();
+    }
+}
+
+/// Devices by IP address. Readers never block: the map is copied when a device connects for
+/// the first time, and each device publishes its state on its own.
+#[derive(Debug, Default)]
+pub struct Clients {
+    map: ArcSwap<HashMap<IpAddr, Arc<Client>>>,
+    changed: Arc<Notify>,
+    /// Index of the [`Shape::Mesh`] of each device assigned one
+    meshes: HashMap<IpAddr, usize>,
+}
+
+impl Clients {
+    /// No devices yet, those in `meshes` to be shown as the mesh given for them when they
+    /// connect.
+    pub fn with_meshes(meshes: HashMap<IpAddr, usize>) -> Self {
+        Self { meshes, ..Self::default() }
+    }
+
+    pub fn get(&self, ip: &IpAddr) -> Option<Arc<Client>> {
+        self.map.load().get(ip).cloned()
+    }
+
+    /// The device at `ip`, added with its mesh or else the shape picked by [`shape_index`] if it
+    /// did not connect before. Also returns whether it was added.
+    pub fn connect(&self, ip: IpAddr) -> (Arc<Client>, bool) {
+        if let Some(client) = self.get(&ip) {
+            return (client, false);
+        }
+        let shape_index = shape_index(ip);
+        let mesh = self.meshes.get(&ip).copied();
+        let client = Arc::new(Client::new(shape_index, mesh, self.changed.clone()));
+        self.map.rcu(|map| {
+            let mut map = HashMap::clone(map);
+            map.entry(ip).or_insert_with(|| client.clone());
+            map
+        });
+        self.changed.notify_one();
+        let added = self.get(&ip).expect("device was just added");
+        let is_new = Arc::ptr_eq(&added, &client);
+        (added, is_new)
+    }
+
+    /// Wait until a device was added or updated since the previous call returned. Changes while
+    /// nobody waits are remembered, and any number of them wakes a single call.
+    pub async fn changed(&self) {
+        self.changed.notified().await;
+    }
+
+    /// The latest state of every device, ordered by IP address.
+    pub fn snapshot(&self) -> Vec<(IpAddr, Arc<ClientState>)> {
+        let mut clients: Vec<_> = self.map.load().iter().map(|(ip, client)| (*ip, client.state())).collect();
+        clients.sort_by_key(|(ip, _)| *ip);
+        clients
+    }
+}
+
+pub type ClientData = Arc<Clients>;
+
+/// Index into [`Shape::ALL`] of the shape of sensor 0 of the device at `ip`. An FNV-1a hash
+/// of the address, so a device keeps its shape across reconnects and restarts, and devices
+/// spread evenly over the shapes.
+pub fn shape_index(ip: IpAddr) -> usize {
+    let octets = match ip {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    let hash = octets.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, octet| {
+        (hash ^ *octet as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    (hash % Shape::count() as u64) as usize
+}
+
+/// Accept devices on `listener`, recording their streams to `record` if given.
+pub async fn serve(listener: TcpListener, clients: ClientData, record: Option<PathBuf>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let clients = clients.clone();
+                let record = record.clone();
+                tokio::spawn(async move {
+                    handle_client(stream, addr, clients, record).await;
+                });
+            }
+            Err(e) => {
+                log::error!("Failed to accept connection: {}", e);
+            }
+        }
+    }
+}
+
+async fn handle_client(stream: TcpStream, addr: SocketAddr, clients: ClientData, record: Option<PathBuf>) {
+    log::info!("New connection from: {}", addr);
+
+    // Use IP address for consistent client identification across reconnections
+    let client_ip = addr.ip();
+
+    // Get or create shape for this IP
+    let (client, is_new) = clients.connect(client_ip);
+    let shape = client.state().sensors[&0].shape;
+    if is_new {
+        log::info!("Client {} assigned new shape: {:?}", addr, shape);
+    } else {
+        // Reuse existing shape for this IP
+        log::info!("Client {} reconnected (IP: {}), reusing existing shape: {:?}", addr, client_ip, shape);
+    }
+    {
+        let map = clients.map.load();
+        log::info!("Total clients in HashMap: {} - IPs: {:?}", map.len(), map.keys().collect::<Vec<_>>());
+    }
+
+    let (mut reader, writer) = stream.into_split();
+    if let Err(e) = read_stream(&mut reader, writer, addr, &client, record).await {
+        log::info!("Client {} disconnected: {}", addr, e);
+        // We keep the client data even after disconnection
+    }
+}
+
+/// Read samples and events from a client until the connection fails.
+async fn read_stream(stream: &mut OwnedReadHalf, writer: OwnedWriteHalf, addr: SocketAddr, client: &Client, record: Option<PathBuf>) -> std::io::Result<()> {
+    let mut magic = [0u8; protocol::MAGIC.len()];
+    stream.read_exact(&mut magic).await?;
+
+    if magic != protocol::MAGIC {
+        // Legacy client streaming 3 f32 values (12 bytes total) per sample. What we just read
+        // is the start of the first sample.
+        log::info!("Client {} uses the legacy sample stream", addr);
+        let mut buffer = [0u8; protocol::SAMPLE_LEN];
+        buffer[..magic.len()].copy_from_slice(&magic);
+        stream.read_exact(&mut buffer[magic.len()..]).await?;
+        loop {
+            update_rotation(client, addr, 0, Attitude::from_sample(&protocol::decode_legacy_sample(&buffer)), &buffer);
+            client.stats.record(true);
+            stream.read_exact(&mut buffer).await?;
+        }
+    }
+
+    // Only devices speaking the framed protocol understand commands
+    let (commands, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(write_commands(writer, addr, receiver));
+    *client.commands.lock().unwrap() = Some(commands.clone());
+
+    let result = read_frames(stream, addr, client, record).await;
+    // Unless the device already reconnected, stop accepting commands for it. Dropping the last
+    // sender also ends `write_commands`, which closes the socket.
+    let mut current = client.commands.lock().unwrap();
+    if current.as_ref().is_some_and(|current| current.same_channel(&commands)) {
+        *current = None;
+    }
+    result
+}
+
+/// Read the frames following the magic until the connection fails.
+async fn read_frames(stream: &mut OwnedReadHalf, addr: SocketAddr, client: &Client, record: Option<PathBuf>) -> std::io::Result<()> {
+    let mut recording = match record {
+        Some(dir) => Some(start_recording(&dir, addr)?),
+        None => None,
+    };
+
+    let mut payload = vec![0u8; u16::MAX as usize];
+    // Sensor the following frames are from
+    let mut sensor = 0;
+    // How to scale the raw samples of each sensor, sent once per connection
+    let mut formats = HashMap::new();
+    loop {
+        let mut header = [0u8; protocol::HEADER_LEN];
+        stream.read_exact(&mut header).await?;
+        let encoded_header = header;
+        let header = Header::decode(header);
+        let payload = &mut payload[..header.len as usize];
+        stream.read_exact(payload).await?;
+        if let Some(recording) = &mut recording {
+            recording.write_all(&encoded_header)?;
+            recording.write_all(payload)?;
+        }
+
+        let frame = Frame::decode(header.tag, payload);
+        let valid = frame.is_ok();
+        match frame {
+            Ok(Frame::Samples(samples)) => {
+                // Only the latest orientation is shown
+                if let Some(sample) = samples.last() {
+                    update_rotation(client, addr, sensor, Attitude::from_sample(sample), payload);
+                }
+            }
+            Ok(Frame::Motion(event)) => {
+                log::info!("Client {} sensor {} reported {}", addr, sensor, event);
+                let now = std::time::Instant::now();
+                client.update(sensor, |state| state.last_event = Some((event, now)));
+            }
+            Ok(Frame::Health(health)) => {
+                log_health(addr, sensor, client.state().sensors.get(&sensor).and_then(|s| s.health.as_ref()), &health);
+                client.update(sensor, |state| state.health = Some(health));
+            }
+            Ok(Frame::Sensor(index)) => {
+                sensor = index;
+            }
+            Ok(Frame::Calibration(status)) => {
+                match status {
+                    CalibrationStatus::Done(calibration) => log::info!("Client {} sensor {} calibrated: {:?}", addr, sensor, calibration),
+                    CalibrationStatus::Failed => log::warn!("Client {} sensor {} calibration failed", addr, sensor),
+                    CalibrationStatus::InProgress { .. } => log::info!("Client {} sensor {} {}", addr, sensor, status),
+                }
+                let now = std::time::Instant::now();
+                client.update(sensor, |state| state.calibration = Some((status, now)));
+            }
+            Ok(Frame::Attitude(attitude)) => {
+                update_rotation(client, addr, sensor, attitude, payload);
+            }
+            Ok(Frame::RawFormat(format)) => {
+                log::info!("Client {} sensor {} sends raw samples at ±{} g, {} bits", addr, sensor, format.full_scale(), format.resolution);
+                formats.insert(sensor, format);
+            }
+            Ok(Frame::RawSamples(samples) | Frame::DeltaSamples(samples)) => match formats.get(&sensor) {
+                Some(format) => {
+                    if let Some(sample) = samples.last() {
+                        update_rotation(client, addr, sensor, Attitude::from_sample(&format.to_sample(sample)), payload);
+                    }
+                }
+                None => log::warn!("Client {} sensor {} sent raw samples before their format, skipping", addr, sensor),
+            },
+            Ok(Frame::Calibrate) => {
+                log::warn!("Client {} sent a calibration request, which only the viewer sends", addr);
+            }
+            Err(protocol::Error::UnknownTag(tag)) => {
+                log::debug!("Client {} sent unknown frame type {:#04x}, skipping", addr, tag);
+            }
+            Err(e) => {
+                log::warn!("Client {} sent invalid frame: {:?} - raw bytes: {:02x?}", addr, e, payload);
+            }
+        }
+        // Counted once applied, so readers of the stats see the state it led to
+        client.stats.record(valid);
+    }
+}
+
+/// Create a file in `dir` for the stream of the device, starting with the magic already read.
+fn start_recording(dir: &Path, addr: SocketAddr) -> std::io::Result<BufWriter<File>> {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let path = dir.join(format!("{}-{}.xlp", addr.ip(), since_epoch.as_secs()));
+    log::info!("Recording client {} to {}", addr, path.display());
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&protocol::MAGIC)?;
+    Ok(file)
+}
+
+/// Send frames queued for the device until the connection fails.
+async fn write_commands(mut writer: OwnedWriteHalf, addr: SocketAddr, mut commands: mpsc::UnboundedReceiver<Frame>) {
+    let mut buf = [0u8; protocol::MAX_FRAME_LEN];
+    while let Some(frame) = commands.recv().await {
+        let len = match frame.encode(&mut buf) {
+            Ok(len) => len,
+            Err(e) => {
+                log::error!("Failed to encode {:?} for client {}: {:?}", frame, addr, e);
+                continue;
+            }
+        };
+        if let Err(e) = writer.write_all(&buf[..len]).await {
+            log::info!("Failed to send {:?} to client {}: {}", frame, addr, e);
+            return;
+        }
+    }
+}
+
+/// Ask every connected device to calibrate its sensors, or to cancel calibrations in progress.
+pub fn request_calibration(clients: &ClientData) {
+    for (ip, client) in clients.map.load().iter() {
+        if let Some(commands) = &*client.commands.lock().unwrap() {
+            if commands.send(Frame::Calibrate).is_ok() {
+                log::info!("Requested calibration of {}", ip);
+            }
+        }
+    }
+}
+
+/// Log the self-test result when first reported, and sensor status changes after that.
+fn log_health(addr: SocketAddr, sensor: u8, previous: Option<&Health>, health: &Health) {
+    match health.status {
+        _ if previous.map(|p| p.status) == Some(health.status) => {}
+        SensorStatus::Ok if previous.is_some() => log::info!("Client {} sensor {} recovered", addr, sensor),
+        SensorStatus::Ok => {}
+        SensorStatus::Recovering { attempt } => log::warn!("Client {} sensor {} not responding, recovery attempt {}", addr, sensor, attempt),
+        SensorStatus::Fault => log::error!("Client {} sensor {} failed", addr, sensor),
+    }
+
+    let self_test = &health.self_test;
+    if previous.map(|p| &p.self_test) == Some(self_test) {
+        return;
+    }
+    if self_test.passed() {
+        log::info!("Client {} sensor {} passed the accelerometer self-test", addr, sensor);
+    } else {
+        log::warn!("Client {} sensor {} failed the accelerometer self-test: WHO_AM_I {:#04x}, ST0 {:?} within limits {:?}, ST1 {:?} within limits {:?}",
+            addr,
+            sensor,
+            self_test.who_am_i,
+            self_test.st0,
+            self_test.axes_within_limits(&self_test.st0),
+            self_test.st1,
+            self_test.axes_within_limits(&self_test.st1));
+    }
+}
+
+/// Show the sensor with the given attitude. Samples are turned into one by the same code the
+/// device uses, so both give the same orientation.
+fn update_rotation(client: &Client, addr: SocketAddr, sensor: u8, attitude: Attitude, raw: &[u8]) {
+    let (roll, pitch, yaw) = attitude.euler();
+
+    // Check for invalid values
+    if !roll.is_finite() || !pitch.is_finite() || !yaw.is_finite() {
+        log::warn!("Client {} sensor {} sent invalid attitude: {:?} - raw bytes: {:02x?}",
+            addr, sensor, attitude, raw);
+        return;
+    }
+
+    // The sensor lies flat facing the viewer: its x axis points right, y into the screen and z up
+    let rotation = ClientRotation { x: roll, y: yaw, z: -pitch };
+
+    // Update rotation
+    client.update(sensor, |state| state.rotation = rotation.clone());
+
+    log::trace!("Updated rotation for {} sensor {}: ({:.3}, {:.3}, {:.3})", addr, sensor, rotation.x, rotation.y, rotation.z);
+}
@@ -0,0 +1,7 @@
+//! The viewer's device ingest and rendering, shared by `tcp-3d-viewer` and `xl-loadgen`.
+
+pub mod camera;
+pub mod ingest;
+pub mod mesh;
+pub mod renderer;
+pub mod simulated;
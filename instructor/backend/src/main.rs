@@ -1,263 +1,441 @@
-use cgmath::Vector3;
-use std::collections::HashMap;
+use cgmath::{EuclideanSpace, Point3, Vector3};
+use std::io::ErrorKind;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::io::AsyncReadExt;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tcp_3d_viewer::camera::Camera;
+use tcp_3d_viewer::ingest::{self, ClientData, Clients, SensorState, PORT};
+use tcp_3d_viewer::mesh::{self, Mesh, MeshConfig};
+use tcp_3d_viewer::renderer::{self, Renderer, ShapeInstance};
+use tokio::net::TcpListener;
+use workshop_common::calibration::CalibrationStatus;
+use workshop_common::health::{Health, SensorStatus};
 use winit::{
-    event::{Event, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
+    application::ApplicationHandler,
+    dpi::PhysicalPosition,
+    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, StartCause, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy},
+    keyboard::{Key, ModifiersState, NamedKey},
+    window::{Window, WindowId},
 };
 
-mod renderer;
-use renderer::{Renderer, Shape, ShapeInstance};
-
-#[derive(Debug, Clone)]
-struct ClientRotation {
-    x: f32,
-    y: f32,
-    z: f32,
-}
-
-type ClientData = Arc<RwLock<HashMap<std::net::IpAddr, (Shape, ClientRotation)>>>;
+mod test_clients;
+
+/// How long a motion event stays visible in the label
+const EVENT_DISPLAY_TIME: Duration = Duration::from_secs(2);
+/// Where the camera is kept between runs, in the per-user config directory, unless given
+/// with `--camera`
+const CAMERA_FILE: &str = "tcp-3d-viewer/camera.txt";
+/// Camera turn for each arrow key press, in radians
+const ORBIT_STEP: f32 = std::f32::consts::PI / 12.0;
+/// Camera turn for dragging across the height of the window, in radians
+const ORBIT_DRAG: f32 = std::f32::consts::PI;
+/// Change in camera distance for each scroll line or zoom key press
+const ZOOM_STEP: f32 = 1.1;
+/// Scrolled pixels counted as one line, for touchpads
+const PIXELS_PER_LINE: f32 = 40.0;
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
     let test_mode = std::env::args().any(|arg| arg == "--test");
+    // Directory to save the stream of every connection in, for the compression benchmark
+    let record = std::env::args().skip_while(|arg| arg != "--record").nth(1).map(PathBuf::from);
+
+    // Meshes to show for devices instead of the built-in shapes, see the mesh module
+    let mesh_config = match std::env::args().skip_while(|arg| arg != "--meshes").nth(1) {
+        Some(path) => mesh::load_config(Path::new(&path)).unwrap_or_else(|e| panic!("--meshes {}: {}", path, e)),
+        None => MeshConfig::default(),
+    };
+    for (ip, mesh) in &mesh_config.devices {
+        let mesh = &mesh_config.meshes[*mesh];
+        log::info!("Showing {} as {} ({} triangles)", ip, mesh.name, mesh.indices.len() / 3);
+    }
 
     // Shared data between TCP server and renderer
-    let clients: ClientData = Arc::new(RwLock::new(HashMap::new()));
+    let clients = ClientData::new(Clients::with_meshes(mesh_config.devices));
 
-    if test_mode {
-        log::info!("Running in test mode with 10 simulated clients");
+    let listener = TcpListener::bind(("0.0.0.0", PORT))
+        .await
+        .expect("Failed to bind TCP listener");
+    log::info!("TCP server listening on 0.0.0.0:{}", PORT);
+    tokio::spawn(ingest::serve(listener, clients.clone(), record));
 
-        // Create 10 test clients with different shapes
-        {
-            let mut clients_guard = clients.write().await;
+    if test_mode {
+        let count = std::env::args()
+            .skip_while(|arg| arg != "--clients")
+            .nth(1)
+            .map(|count| count.parse().expect("--clients takes a number"))
+            .unwrap_or(10);
+        let scripts = match std::env::args().skip_while(|arg| arg != "--scripts").nth(1) {
+            Some(list) => list
+                .split(',')
+                .map(|name| name.parse().unwrap_or_else(|e: String| panic!("{}", e)))
+                .collect(),
+            None => test_clients::Script::ALL.to_vec(),
+        };
+        log::info!("Running in test mode with {} simulated clients playing {:?}", count, scripts);
+        test_clients::spawn(count, &scripts, SocketAddr::from(([127, 0, 0, 1], PORT)));
+    }
 
-            for i in 0..10 {
-                // Create fake IP addresses: 192.168.0.1 through 192.168.0.10
-                let ip: std::net::IpAddr = format!("192.168.0.{}", i + 1).parse().unwrap();
-                let shape = Shape::from_index(i);
-                clients_guard.insert(ip, (shape, ClientRotation { x: 0.0, y: 0.0, z: 0.0 }));
-            }
-            log::info!("Created {} test clients", clients_guard.len());
+    // Frames are only drawn when something changed, and at most this often
+    let max_fps = std::env::args()
+        .skip_while(|arg| arg != "--max-fps")
+        .nth(1)
+        .map(|fps| {
+            fps.parse()
+                .ok()
+                .filter(|fps: &f32| *fps > 0.0)
+                .expect("--max-fps takes a positive number")
+        })
+        .unwrap_or(60.0);
+    // Direction the light comes from, as x,y,z with y up and z towards the viewer
+    let light_direction = match std::env::args().skip_while(|arg| arg != "--light").nth(1) {
+        Some(direction) => {
+            let axes: Vec<f32> = direction
+                .split(',')
+                .map(|axis| axis.parse().expect("--light takes x,y,z"))
+                .collect();
+            let [x, y, z] = axes[..] else {
+                panic!("--light takes x,y,z");
+            };
+            Vector3::new(x, y, z)
         }
-
-        // Spawn task to slowly rotate all test shapes
-        let clients_rotate = clients.clone();
-        tokio::spawn(async move {
-            let mut angle: f32 = 0.0;
-            loop {
-                tokio::time::sleep(tokio::time::Duration::from_millis(16)).await; // ~60fps
-                angle += 0.02; // Slow rotation speed
-
-                let mut clients_guard = clients_rotate.write().await;
-                for (_ip, (_shape, rotation)) in clients_guard.iter_mut() {
-                    rotation.y = angle;
-                }
-            }
-        });
-    } else {
-        // Spawn TCP server in normal mode
-        let clients_tcp = clients.clone();
-        tokio::spawn(async move {
-            tcp_server(clients_tcp).await;
-        });
-    }
+        None => renderer::DEFAULT_LIGHT_DIRECTION,
+    };
+
+    // The camera is restored from, and saved to, this file. Without a config directory it is
+    // not kept at all.
+    let camera_path = std::env::args()
+        .skip_while(|arg| arg != "--camera")
+        .nth(1)
+        .map(PathBuf::from)
+        .or_else(|| config_dir().map(|dir| dir.join(CAMERA_FILE)));
+    let camera = match camera_path.as_deref().map(|path| (path, Camera::load(path))) {
+        Some((_, Ok(camera))) => camera,
+        Some((path, Err(e))) if e.kind() != ErrorKind::NotFound => {
+            log::warn!("Ignoring the camera saved in {}: {}", path.display(), e);
+            Camera::default()
+        }
+        _ => Camera::default(),
+    };
 
     // Run the rendering loop
-    run_renderer(clients).await;
+    let viewer = Viewer {
+        clients,
+        window: None,
+        renderer: None,
+        light_direction,
+        meshes: mesh_config.meshes,
+        camera,
+        camera_path,
+        drag: None,
+        cursor: None,
+        modifiers: ModifiersState::empty(),
+        redraw_at: None,
+    };
+    run_renderer(viewer, max_fps).await;
 }
 
-async fn tcp_server(clients: ClientData) {
-    let listener = TcpListener::bind("0.0.0.0:8080")
-        .await
-        .expect("Failed to bind TCP listener");
+/// The per-user config directory: `$XDG_CONFIG_HOME`, `~/.config` or, on Windows, `%APPDATA%`.
+fn config_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    var("XDG_CONFIG_HOME")
+        .or_else(|| var("HOME").map(|home| home.join(".config")))
+        .or_else(|| var("APPDATA"))
+}
 
-    log::info!("TCP server listening on 0.0.0.0:8080");
+/// Asks the event loop to draw a frame.
+#[derive(Debug)]
+struct Redraw;
 
+/// Ask for a redraw whenever a device changed, at most `max_fps` times a second.
+async fn forward_changes(clients: ClientData, proxy: EventLoopProxy<Redraw>, max_fps: f32) {
+    let frame_time = Duration::from_secs_f32(1.0 / max_fps);
     loop {
-        match listener.accept().await {
-            Ok((stream, addr)) => {
-                let clients = clients.clone();
-                tokio::spawn(async move {
-                    handle_client(stream, addr, clients).await;
-                });
-            }
-            Err(e) => {
-                log::error!("Failed to accept connection: {}", e);
-            }
+        clients.changed().await;
+        if proxy.send_event(Redraw).is_err() {
+            // The event loop exited
+            return;
         }
+        tokio::time::sleep(frame_time).await;
     }
 }
 
-async fn handle_client(mut stream: TcpStream, addr: SocketAddr, clients: ClientData) {
-    log::info!("New connection from: {}", addr);
-
-    // Use IP address for consistent client identification across reconnections
-    let client_ip = addr.ip();
-
-    // Get or create shape for this IP
-    {
-        let mut clients_guard = clients.write().await;
-
-        if let Some((shape, _)) = clients_guard.get(&client_ip) {
-            // Reuse existing shape for this IP
-            log::info!("Client {} reconnected (IP: {}), reusing existing shape: {:?}", addr, client_ip, shape);
-            log::info!("Total clients in HashMap: {} - IPs: {:?}",
-                clients_guard.len(),
-                clients_guard.keys().collect::<Vec<_>>());
-        } else {
-            // Create new random shape for new IP
-            use rand::Rng;
-            let mut rng = rand::thread_rng();
-            let shape = Shape::from_index(rng.gen_range(0..Shape::count()));
-
-            clients_guard.insert(
-                client_ip,
-                (shape.clone(), ClientRotation { x: 0.0, y: 0.0, z: 0.0 })
-            );
-            log::info!("Client {} assigned new shape: {:?}", addr, shape);
-            log::info!("Total clients in HashMap: {} - IPs: {:?}",
-                clients_guard.len(),
-                clients_guard.keys().collect::<Vec<_>>());
+/// The window, drawing the devices when they change rather than continuously.
+struct Viewer {
+    clients: ClientData,
+    window: Option<Arc<Window>>,
+    renderer: Option<Renderer>,
+    light_direction: Vector3<f32>,
+    meshes: Vec<Mesh>,
+    camera: Camera,
+    /// Where the camera is saved on exit, if anywhere
+    camera_path: Option<PathBuf>,
+    /// Mouse button held down to orbit or pan the camera
+    drag: Option<MouseButton>,
+    /// Last position of the mouse pointer in the window
+    cursor: Option<PhysicalPosition<f64>>,
+    modifiers: ModifiersState,
+    /// When a label expires and the frame has to be drawn again without new data
+    redraw_at: Option<Instant>,
+}
+
+impl ApplicationHandler<Redraw> for Viewer {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_some() {
+            return;
         }
+        let window = Arc::new(
+            event_loop.create_window(
+                Window::default_attributes()
+                    .with_title("TCP-Controlled 3D Shapes")
+            ).expect("Failed to create window")
+        );
+        let mut renderer = pollster::block_on(Renderer::new(window.clone()));
+        renderer.set_light_direction(self.light_direction);
+        renderer.add_meshes(&self.meshes);
+        renderer.set_camera(self.camera);
+        self.renderer = Some(renderer);
+        self.window = Some(window);
     }
 
-    // Buffer for reading 3 f32 values (12 bytes total)
-    let mut buffer = [0u8; 12];
+    fn new_events(&mut self, _event_loop: &ActiveEventLoop, cause: StartCause) {
+        if let StartCause::ResumeTimeReached { .. } = cause {
+            self.request_redraw();
+        }
+    }
 
-    loop {
-        match stream.read_exact(&mut buffer).await {
-            Ok(_) => {
-                // Parse 3 little-endian f32 values
-                let x = f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
-                let y = f32::from_le_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
-                let z = f32::from_le_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]);
-
-                // Check for invalid values
-                if !x.is_finite() || !y.is_finite() || !z.is_finite() {
-                    log::warn!("Client {} sent invalid rotation: ({}, {}, {}) - raw bytes: {:02x?}",
-                        addr, x, y, z, &buffer);
-                    continue;
-                }
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, _event: Redraw) {
+        self.request_redraw();
+    }
 
-                // Update rotation
-                let mut clients_guard = clients.write().await;
-                if let Some(client) = clients_guard.get_mut(&client_ip) {
-                    client.1 = ClientRotation { x, y, z };
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _window_id: WindowId, event: WindowEvent) {
+        let Some(renderer) = &mut self.renderer else {
+            return;
+        };
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::Resized(physical_size) => {
+                renderer.resize(physical_size);
+                self.request_redraw();
+            }
+            WindowEvent::KeyboardInput {
+                event: KeyEvent { logical_key: Key::Character(key), state: ElementState::Pressed, repeat: false, .. },
+                ..
+            } if key.eq_ignore_ascii_case("c") => ingest::request_calibration(&self.clients),
+            WindowEvent::KeyboardInput { event: KeyEvent { logical_key, state: ElementState::Pressed, .. }, .. } => {
+                self.key_pressed(logical_key);
+            }
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers.state(),
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.drag = match state {
+                    ElementState::Pressed => Some(button),
+                    ElementState::Released => None,
+                };
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let last = self.cursor.replace(position);
+                if let (Some(button), Some(last)) = (self.drag, last) {
+                    // In heights of the window, so dragging feels the same at any size
+                    let height = renderer.size.height.max(1) as f32;
+                    let dx = (position.x - last.x) as f32 / height;
+                    let dy = (position.y - last.y) as f32 / height;
+                    match button {
+                        MouseButton::Left if !self.modifiers.shift_key() => self.camera.orbit(-dx * ORBIT_DRAG, dy * ORBIT_DRAG),
+                        MouseButton::Left | MouseButton::Right | MouseButton::Middle => self.camera.pan(-dx, dy),
+                        _ => return,
+                    }
+                    self.camera_changed();
                 }
-
-                log::trace!("Updated rotation for {}: ({:.3}, {:.3}, {:.3})", addr, x, y, z);
             }
-            Err(e) => {
-                log::info!("Client {} disconnected: {}", addr, e);
-                // We keep the client data even after disconnection
-                break;
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => lines,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
+                self.camera.zoom(ZOOM_STEP.powf(-lines));
+                self.camera_changed();
             }
+            WindowEvent::RedrawRequested => {
+                // Update instances based on client data
+                let (instances, redraw_at) = instances(&self.clients);
+                self.redraw_at = redraw_at;
+                renderer.update(instances);
+
+                match renderer.render() {
+                    Ok(_) => {}
+                    Err(wgpu::SurfaceError::Lost) => renderer.resize(renderer.size),
+                    Err(wgpu::SurfaceError::OutOfMemory) => event_loop.exit(),
+                    Err(e) => eprintln!("Render error: {:?}", e),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        let Some(path) = &self.camera_path else {
+            return;
+        };
+        let saved = path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| self.camera.save(path));
+        if let Err(e) = saved {
+            log::warn!("Failed to save the camera to {}: {}", path.display(), e);
         }
     }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        // Sleep until new data, input or a label expiring
+        event_loop.set_control_flow(match self.redraw_at {
+            Some(at) => ControlFlow::WaitUntil(at),
+            None => ControlFlow::Wait,
+        });
+    }
 }
 
-async fn run_renderer(clients: ClientData) {
-    let event_loop = EventLoop::new().expect("Failed to create event loop");
-    let window = Arc::new(
-        event_loop.create_window(
-            winit::window::Window::default_attributes()
-                .with_title("TCP-Controlled 3D Shapes")
-        ).expect("Failed to create window")
-    );
-
-    let mut renderer = pollster::block_on(Renderer::new(window.clone()));
-
-    event_loop
-        .run(move |event, target| {
-            target.set_control_flow(ControlFlow::Poll);
-
-            match event {
-                Event::WindowEvent {
-                    ref event,
-                    window_id,
-                } if window_id == window.id() => match event {
-                    WindowEvent::CloseRequested => target.exit(),
-                    WindowEvent::Resized(physical_size) => {
-                        renderer.resize(*physical_size);
-                    }
-                    WindowEvent::RedrawRequested => {
-                        // Update instances based on client data
-                        let instances = {
-                            if let Ok(clients_guard) = clients.try_read() {
-                                let num_clients = clients_guard.len();
-                                log::trace!("Rendering {} clients", num_clients);
-
-                                // Calculate scale based on number of sensors
-                                // More sensors = smaller objects to fit in view
-                                let base_scale = 5.0; // Larger base scale to fill viewport better
-                                let scale = if num_clients == 0 {
-                                    base_scale
-                                } else {
-                                    (base_scale / (num_clients as f32).sqrt()).max(0.5).min(base_scale)
-                                };
-
-                                // Sort by IP address to ensure stable iteration order
-                                let mut sorted_clients: Vec<_> = clients_guard.iter().collect();
-                                sorted_clients.sort_by_key(|(ip, _)| *ip);
-
-                                sorted_clients
-                                    .iter()
-                                    .enumerate()
-                                    .map(|(index, (ip, (shape, rotation)))| {
-                                        // Calculate grid dimensions for 16:10 aspect ratio
-                                        let aspect_ratio = 16.0 / 10.0;
-                                        let rows = ((num_clients as f32) / aspect_ratio).sqrt().ceil() as i32;
-                                        let cols = ((num_clients as f32) / rows as f32).ceil() as i32;
-
-                                        let row = (index as i32) / cols;
-                                        let col = (index as i32) % cols;
-
-                                        // Spacing should be proportional to scale to prevent overlap
-                                        let spacing = scale * 2.0;
-                                        let x = (col as f32 - (cols - 1) as f32 / 2.0) * spacing;
-                                        let y = ((rows - 1) as f32 / 2.0 - row as f32) * spacing;
-
-                                        log::trace!("Instance {}: IP={}, shape={:?}, pos=({:.1},{:.1},{:.1}), rot=({:.2},{:.2},{:.2})",
-                                            index, ip, shape, x, y, 0.0, rotation.x, rotation.y, rotation.z);
-
-                                        ShapeInstance {
-                                            shape: shape.clone(),
-                                            position: Vector3::new(x, y, 0.0),
-                                            rotation: Vector3::new(rotation.x, rotation.y, rotation.z),
-                                            scale,
-                                            label: ip.to_string(),
-                                        }
-                                    })
-                                    .collect()
-                            } else {
-                                // If we can't acquire the lock, skip this frame
-                                vec![]
-                            }
-                        };
-
-                        renderer.update(instances);
-                        
-                        match renderer.render() {
-                            Ok(_) => {}
-                            Err(wgpu::SurfaceError::Lost) => renderer.resize(renderer.size),
-                            Err(wgpu::SurfaceError::OutOfMemory) => target.exit(),
-                            Err(e) => eprintln!("Render error: {:?}", e),
-                        }
-                    }
-                    _ => {}
-                },
-                Event::AboutToWait => {
-                    window.request_redraw();
+impl Viewer {
+    fn request_redraw(&self) {
+        if let Some(window) = &self.window {
+            window.request_redraw();
+        }
+    }
+
+    /// Move the camera with the keyboard: the arrow keys orbit, + and - zoom, R resets the view,
+    /// O switches between perspective and orthographic, and 1 to 9 and 0 focus on the first ten
+    /// devices.
+    fn key_pressed(&mut self, key: Key) {
+        match key.as_ref() {
+            Key::Named(NamedKey::ArrowLeft) => self.camera.orbit(-ORBIT_STEP, 0.0),
+            Key::Named(NamedKey::ArrowRight) => self.camera.orbit(ORBIT_STEP, 0.0),
+            Key::Named(NamedKey::ArrowUp) => self.camera.orbit(0.0, ORBIT_STEP),
+            Key::Named(NamedKey::ArrowDown) => self.camera.orbit(0.0, -ORBIT_STEP),
+            Key::Character("+" | "=") => self.camera.zoom(1.0 / ZOOM_STEP),
+            Key::Character("-") => self.camera.zoom(ZOOM_STEP),
+            Key::Character("r" | "R") => self.camera.reset(),
+            Key::Character("o" | "O") => {
+                self.camera.orthographic = !self.camera.orthographic;
+                log::info!("{} projection", if self.camera.orthographic { "Orthographic" } else { "Perspective" });
+            }
+            Key::Character(digit @ ("0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9")) => {
+                // 1 is the first device, 0 the tenth, in the order they are shown
+                let device = (digit.parse::<usize>().unwrap() + 9) % 10;
+                let count = self.clients.snapshot().len();
+                if device >= count {
+                    return;
                 }
-                _ => {}
+                let (center, _, spacing) = cell(device, count);
+                self.camera.focus(Point3::from_vec(center), spacing);
             }
+            _ => return,
+        }
+        self.camera_changed();
+    }
+
+    fn camera_changed(&mut self) {
+        if let Some(renderer) = &mut self.renderer {
+            renderer.set_camera(self.camera);
+        }
+        self.request_redraw();
+    }
+}
+
+async fn run_renderer(mut viewer: Viewer, max_fps: f32) {
+    let event_loop = EventLoop::<Redraw>::with_user_event().build().expect("Failed to create event loop");
+    tokio::spawn(forward_changes(viewer.clients.clone(), event_loop.create_proxy(), max_fps));
+    event_loop.run_app(&mut viewer).expect("Event loop failed");
+}
+
+/// The shapes to draw for the devices, and when the frame changes without new data, if ever.
+fn instances(clients: &ClientData) -> (Vec<ShapeInstance>, Option<Instant>) {
+    // Sorted by IP address to ensure stable iteration order
+    let sorted_clients = clients.snapshot();
+    let now = Instant::now();
+    // Labels showing an event or calibration result change when it expires
+    let redraw_at = sorted_clients
+        .iter()
+        .flat_map(|(_, client)| client.sensors.values())
+        .flat_map(|state| {
+            let calibration = state.calibration.filter(|(status, _)| !matches!(status, CalibrationStatus::InProgress { .. }));
+            [state.last_event.map(|(_, at)| at), calibration.map(|(_, at)| at)]
+        })
+        .flatten()
+        .map(|at| at + EVENT_DISPLAY_TIME)
+        .filter(|expiry| *expiry > now)
+        .min();
+    let num_clients = sorted_clients.len();
+    log::trace!("Rendering {} clients", num_clients);
+
+    let instances = sorted_clients
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (ip, client))| {
+            let (center, scale, spacing) = cell(index, num_clients);
+            let (x, y) = (center.x, center.y);
+
+            // Sensors of one device share its grid cell, side by side
+            let num_sensors = client.sensors.len();
+            let sensor_scale = scale / num_sensors as f32;
+            let sensor_spacing = spacing / num_sensors as f32;
+
+            client.sensors.iter().enumerate().map(move |(slot, (sensor, state))| {
+                let SensorState { shape, rotation, last_event, health, calibration } = state;
+                let x = x + (slot as f32 - (num_sensors - 1) as f32 / 2.0) * sensor_spacing;
+                let name = if num_sensors > 1 {
+                    format!("{} #{}", ip, sensor)
+                } else {
+                    ip.to_string()
+                };
+
+                log::trace!("Instance {}: IP={}, sensor={}, shape={:?}, pos=({:.1},{:.1},{:.1}), rot=({:.2},{:.2},{:.2})",
+                    index, ip, sensor, shape, x, y, 0.0, rotation.x, rotation.y, rotation.z);
+
+                ShapeInstance {
+                    shape: *shape,
+                    position: Vector3::new(x, y, 0.0),
+                    rotation: Vector3::new(rotation.x, rotation.y, rotation.z),
+                    scale: sensor_scale,
+                    label: match (last_event, health, calibration) {
+                        (_, _, Some((status @ CalibrationStatus::InProgress { .. }, _))) => format!("{}\n{}", name, status),
+                        (_, _, Some((status, at))) if now - *at < EVENT_DISPLAY_TIME => format!("{}\n{}", name, status),
+                        (_, Some(Health { status: SensorStatus::Fault, .. }), _) => format!("{}\nsensor fault", name),
+                        (_, Some(Health { status: SensorStatus::Recovering { .. }, .. }), _) => format!("{}\nrecovering sensor", name),
+                        (_, Some(health), _) if !health.self_test.passed() => format!("{}\nself-test failed", name),
+                        (Some((event, at)), _, _) if now - *at < EVENT_DISPLAY_TIME => format!("{}\n{}", name, event),
+                        _ => name,
+                    },
+                }
+            })
         })
-        .expect("Event loop failed");
+        .collect();
+    (instances, redraw_at)
+}
+
+/// Center of the grid cell of device `index` out of `count`, the scale of its shape, and the
+/// width of the cell.
+fn cell(index: usize, count: usize) -> (Vector3<f32>, f32, f32) {
+    // Calculate scale based on number of sensors
+    // More sensors = smaller objects to fit in view
+    let base_scale = 5.0; // Larger base scale to fill viewport better
+    let scale = if count == 0 {
+        base_scale
+    } else {
+        (base_scale / (count as f32).sqrt()).max(0.5).min(base_scale)
+    };
+
+    // Calculate grid dimensions for 16:10 aspect ratio
+    let aspect_ratio = 16.0 / 10.0;
+    let rows = ((count as f32) / aspect_ratio).sqrt().ceil() as i32;
+    let cols = ((count as f32) / rows as f32).ceil() as i32;
+
+    let row = (index as i32) / cols;
+    let col = (index as i32) % cols;
+
+    // Spacing should be proportional to scale to prevent overlap
+    let spacing = scale * 2.0;
+    let x = (col as f32 - (cols - 1) as f32 / 2.0) * spacing;
+    let y = ((rows - 1) as f32 / 2.0 - row as f32) * spacing;
+    (Vector3::new(x, y, 0.0), scale, spacing)
 }
@@ -0,0 +1,373 @@
+//! Meshes loaded from disk, shown for the devices assigned to them instead of a built-in shape.
+//!
+//! Devices are assigned a mesh in a text file listing one device per line, by its IP address,
+//! followed by the mesh file relative to the list. Lines starting with `#` are comments.
+//!
+//! ```text
+//! # Boards in their printed enclosures
+//! 192.168.1.20 enclosure.stl
+//! 192.168.1.21 enclosure.stl
+//! 192.168.1.30 meshes/sensor-pod.glb
+//! ```
+//!
+//! OBJ, STL and glTF files are read, told apart by their extension. Meshes are centered and
+//! scaled to the size of the built-in shapes, so they can be modelled in any unit.
+
+use cgmath::{Matrix4, Point3, Transform};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Error, ErrorKind};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Triangles of a mesh file.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    /// File name, to tell meshes apart in the logs
+    pub name: String,
+    pub positions: Vec<[f32; 3]>,
+    /// Three indices into `positions` for each triangle
+    pub indices: Vec<u32>,
+}
+
+/// The meshes of a mesh list, and the devices assigned each.
+#[derive(Debug, Default)]
+pub struct MeshConfig {
+    /// Each mesh file once, however many devices share it
+    pub meshes: Vec<Mesh>,
+    /// Index into `meshes` of the mesh of each device listed
+    pub devices: HashMap<IpAddr, usize>,
+}
+
+/// Read the mesh list at `path`, and every mesh file it names.
+pub fn load_config(path: &Path) -> io::Result<MeshConfig> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut config = MeshConfig::default();
+    let mut loaded: HashMap<PathBuf, usize> = HashMap::new();
+    for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |message: String| {
+            Error::new(ErrorKind::InvalidData, format!("{}:{}: {}", path.display(), number + 1, message))
+        };
+        let (ip, file) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| invalid("expected an IP address and a mesh file".into()))?;
+        let ip: IpAddr = ip.parse().map_err(|e| invalid(format!("{}: {}", ip, e)))?;
+        let file = dir.join(file.trim());
+        let mesh = match loaded.get(&file) {
+            Some(&mesh) => mesh,
+            None => {
+                let mesh = load(&file).map_err(|e| invalid(format!("{}: {}", file.display(), e)))?;
+                config.meshes.push(mesh);
+                loaded.insert(file, config.meshes.len() - 1);
+                config.meshes.len() - 1
+            }
+        };
+        if config.devices.insert(ip, mesh).is_some() {
+            return Err(invalid(format!("{} is assigned a mesh twice", ip)));
+        }
+    }
+    Ok(config)
+}
+
+/// Read the OBJ, STL or glTF file at `path`, centered on the origin and scaled to fit a unit
+/// cube.
+pub fn load(path: &Path) -> io::Result<Mesh> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    let (positions, indices) = match extension.as_str() {
+        "obj" => load_obj(path)?,
+        "stl" => load_stl(path)?,
+        "gltf" | "glb" => load_gltf(path)?,
+        _ => return Err(Error::new(ErrorKind::InvalidInput, "expected an .obj, .stl, .gltf or .glb file")),
+    };
+    if indices.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "no triangles"));
+    }
+    if let Some(&index) = indices.iter().find(|&&index| index as usize >= positions.len()) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("vertex {} out of range", index)));
+    }
+    let name = path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    Ok(Mesh { name, positions: fit(positions), indices })
+}
+
+/// `positions` moved and scaled for their bounding box to be centered on the origin, its
+/// longest side 1.
+fn fit(mut positions: Vec<[f32; 3]>) -> Vec<[f32; 3]> {
+    let mut min = [f32::INFINITY; 3];
+    let mut max = [f32::NEG_INFINITY; 3];
+    for position in &positions {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(position[axis]);
+            max[axis] = max[axis].max(position[axis]);
+        }
+    }
+    let size = (0..3).map(|axis| max[axis] - min[axis]).fold(0.0, f32::max);
+    let scale = if size > 0.0 { 1.0 / size } else { 1.0 };
+    for position in &mut positions {
+        for axis in 0..3 {
+            position[axis] = (position[axis] - (min[axis] + max[axis]) / 2.0) * scale;
+        }
+    }
+    positions
+}
+
+fn load_obj(path: &Path) -> io::Result<(Vec<[f32; 3]>, Vec<u32>)> {
+    // Materials are not shown, so a missing .mtl file does not matter
+    let (models, _) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+    for model in models {
+        let offset = positions.len() as u32;
+        positions.extend(model.mesh.positions.chunks_exact(3).map(|p| [p[0], p[1], p[2]]));
+        indices.extend(model.mesh.indices.iter().map(|index| offset + index));
+    }
+    Ok((positions, indices))
+}
+
+fn load_stl(path: &Path) -> io::Result<(Vec<[f32; 3]>, Vec<u32>)> {
+    let mesh = stl_io::read_stl(&mut BufReader::new(File::open(path)?))?;
+    let positions = mesh.vertices.into_iter().map(<[f32; 3]>::from).collect();
+    let indices = mesh.faces.iter().flat_map(|face| face.vertices.map(|index| index as u32)).collect();
+    Ok((positions, indices))
+}
+
+/// The triangles of every mesh in the default scene, or the first scene if there is no
+/// default, placed as the nodes of the scene place them.
+fn load_gltf(path: &Path) -> io::Result<(Vec<[f32; 3]>, Vec<u32>)> {
+    let invalid = |e: gltf::Error| Error::new(ErrorKind::InvalidData, e);
+    let gltf = gltf::Gltf::open(path).map_err(invalid)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let buffers = gltf
+        .buffers()
+        .map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Bin => {
+                gltf.blob.clone().ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing binary chunk"))
+            }
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                Err(Error::new(ErrorKind::Unsupported, "embedded buffers, save as .glb or with a separate .bin"))
+            }
+            gltf::buffer::Source::Uri(uri) => fs::read(dir.join(uri)),
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+    let mut nodes: Vec<_> = match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+        Some(scene) => scene.nodes().map(|node| (node, Matrix4::from_scale(1.0))).collect(),
+        None => Vec::new(),
+    };
+    while let Some((node, parent)) = nodes.pop() {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+                let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+                let Some(read_positions) = reader.read_positions() else {
+                    continue;
+                };
+                let offset = positions.len() as u32;
+                positions.extend(read_positions.map(|p| {
+                    let p = transform.transform_point(Point3::from(p));
+                    [p.x, p.y, p.z]
+                }));
+                let count = positions.len() as u32 - offset;
+                match reader.read_indices() {
+                    Some(read_indices) => indices.extend(read_indices.into_u32().map(|index| offset + index)),
+                    None => indices.extend(offset..offset + count - count % 3),
+                }
+            }
+        }
+        nodes.extend(node.children().map(|child| (child, transform)));
+    }
+    Ok((positions, indices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory of its own for each test, removed afterwards.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("tcp-3d-viewer-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        /// Write `contents` to `name` in the directory, returning its path.
+        fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+            let path = self.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A tetrahedron with its corner at (10, 0, 0) and sides of 2 along the axes.
+    const TETRAHEDRON: &str = "\
+v 10 0 0
+v 12 0 0
+v 10 2 0
+v 10 0 2
+f 1 3 2
+f 1 2 4
+f 1 4 3
+f 2 3 4
+";
+
+    const TRIANGLE: &str = "\
+solid triangle
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 4 0 0
+      vertex 0 2 0
+    endloop
+  endfacet
+endsolid triangle
+";
+
+    /// A glTF triangle with an index past its three vertices, the buffer in `triangle.bin`.
+    const OUT_OF_RANGE_GLTF: &str = r#"{
+  "asset": { "version": "2.0" },
+  "scene": 0,
+  "scenes": [{ "nodes": [0] }],
+  "nodes": [{ "mesh": 0 }],
+  "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+  "buffers": [{ "uri": "triangle.bin", "byteLength": 44 }],
+  "bufferViews": [
+    { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+    { "buffer": 0, "byteOffset": 36, "byteLength": 6 }
+  ],
+  "accessors": [
+    { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0] },
+    { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+  ]
+}"#;
+
+    fn out_of_range_buffer() -> Vec<u8> {
+        let positions = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let indices = [0u16, 1, 5, 0];
+        let mut buffer: Vec<u8> = positions.iter().flat_map(|p| p.to_le_bytes()).collect();
+        buffer.extend(indices.iter().flat_map(|i| i.to_le_bytes()));
+        buffer
+    }
+
+    /// The corners of each triangle of `mesh`.
+    fn triangles(mesh: &Mesh) -> Vec<[[f32; 3]; 3]> {
+        mesh.indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]].map(|index| mesh.positions[index as usize]))
+            .collect()
+    }
+
+    fn assert_positions(actual: &[[f32; 3]], expected: &[[f32; 3]]) {
+        assert_eq!(actual.len(), expected.len(), "{:?} is not {:?}", actual, expected);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((0..3).all(|axis| (a[axis] - e[axis]).abs() < 1e-6), "{:?} is not {:?}", actual, expected);
+        }
+    }
+
+    fn error(result: io::Result<MeshConfig>) -> String {
+        result.expect_err("expected an error").to_string()
+    }
+
+    #[test]
+    fn fit_centers_and_scales() {
+        let positions = fit(vec![[10.0, 0.0, 0.0], [14.0, 2.0, 1.0], [12.0, 1.0, -1.0]]);
+        // Centered on (12, 1, 0), the longest side of 4 scaled to 1
+        assert_positions(&positions, &[[-0.5, -0.25, 0.0], [0.5, 0.25, 0.25], [0.0, 0.0, -0.25]]);
+    }
+
+    #[test]
+    fn fit_a_single_point() {
+        assert_positions(&fit(vec![[3.0, -2.0, 1.0]; 3]), &[[0.0; 3]; 3]);
+    }
+
+    #[test]
+    fn load_obj() {
+        let dir = TempDir::new("load-obj");
+        let mesh = load(&dir.write("tetrahedron.OBJ", TETRAHEDRON)).unwrap();
+        assert_eq!(mesh.name, "tetrahedron.OBJ");
+        let [a, b, c, d] = [[-0.5, -0.5, -0.5], [0.5, -0.5, -0.5], [-0.5, 0.5, -0.5], [-0.5, -0.5, 0.5]];
+        let expected = [[a, c, b], [a, b, d], [a, d, c], [b, c, d]];
+        let triangles = triangles(&mesh);
+        assert_eq!(triangles.len(), expected.len());
+        for (triangle, expected) in triangles.iter().zip(&expected) {
+            assert_positions(triangle, expected);
+        }
+    }
+
+    #[test]
+    fn load_stl() {
+        let dir = TempDir::new("load-stl");
+        let mesh = load(&dir.write("triangle.stl", TRIANGLE)).unwrap();
+        assert_eq!(mesh.indices, [0, 1, 2]);
+        assert_positions(&mesh.positions, &[[-0.5, -0.25, 0.0], [0.5, -0.25, 0.0], [-0.5, 0.25, 0.0]]);
+    }
+
+    #[test]
+    fn load_rejects_bad_meshes() {
+        let dir = TempDir::new("load-bad");
+        let unknown = load(&dir.write("mesh.ply", TETRAHEDRON)).unwrap_err();
+        assert_eq!(unknown.kind(), ErrorKind::InvalidInput);
+        let empty = load(&dir.write("empty.obj", "v 0 0 0\n")).unwrap_err();
+        assert_eq!(empty.to_string(), "no triangles");
+        dir.write("triangle.bin", out_of_range_buffer());
+        let out_of_range = load(&dir.write("triangle.gltf", OUT_OF_RANGE_GLTF)).unwrap_err();
+        assert_eq!(out_of_range.to_string(), "vertex 5 out of range");
+    }
+
+    #[test]
+    fn config_paths_relative_to_the_list() {
+        let dir = TempDir::new("config-paths");
+        dir.write("meshes/tetrahedron.obj", TETRAHEDRON);
+        dir.write("triangle.stl", TRIANGLE);
+        let list = "\
+# Comments and blank lines are skipped
+
+192.168.1.20 meshes/tetrahedron.obj
+192.168.1.21\ttriangle.stl
+fe80::1 meshes/tetrahedron.obj
+";
+        let config = load_config(&dir.write("meshes.txt", list)).unwrap();
+        let names: Vec<_> = config.meshes.iter().map(|mesh| mesh.name.as_str()).collect();
+        // Each file is loaded once, however many devices share it
+        assert_eq!(names, ["tetrahedron.obj", "triangle.stl"]);
+        let device = |ip: &str| config.devices[&ip.parse::<IpAddr>().unwrap()];
+        assert_eq!(config.devices.len(), 3);
+        assert_eq!(device("192.168.1.20"), 0);
+        assert_eq!(device("192.168.1.21"), 1);
+        assert_eq!(device("fe80::1"), 0);
+    }
+
+    #[test]
+    fn config_errors() {
+        let dir = TempDir::new("config-errors");
+        dir.write("tetrahedron.obj", TETRAHEDRON);
+        let missing = error(load_config(&dir.write("missing.txt", "# Devices\n192.168.1.20\n")));
+        assert!(missing.ends_with("missing.txt:2: expected an IP address and a mesh file"), "{}", missing);
+        let bad_ip = error(load_config(&dir.write("bad-ip.txt", "192.168.1.300 tetrahedron.obj\n")));
+        assert!(bad_ip.ends_with("bad-ip.txt:1: 192.168.1.300: invalid IP address syntax"), "{}", bad_ip);
+        let twice = "192.168.1.20 tetrahedron.obj\n192.168.1.21 tetrahedron.obj\n192.168.1.20 tetrahedron.obj\n";
+        let twice = error(load_config(&dir.write("twice.txt", twice)));
+        assert!(twice.ends_with("twice.txt:3: 192.168.1.20 is assigned a mesh twice"), "{}", twice);
+        let no_file = error(load_config(&dir.write("no-file.txt", "192.168.1.20 missing.obj\n")));
+        assert!(no_file.contains("no-file.txt:1: ") && no_file.contains("missing.obj"), "{}", no_file);
+        assert_eq!(load_config(&dir.0.join("none.txt")).unwrap_err().kind(), ErrorKind::NotFound);
+    }
+}
@@ -1,9 +1,11 @@
 use bytemuck::{Pod, Zeroable};
-use cgmath::{Matrix4, Point3, Rad, Vector3, Vector4, Deg};
+use cgmath::{InnerSpace, Matrix4, Rad, Vector3, Vector4, Zero};
 use glyphon::{
     Attrs, Buffer, Cache, Color, Family, FontSystem, Metrics, Resolution, Shaping, SwashCache,
     TextArea, TextAtlas, TextBounds, TextRenderer, Viewport,
 };
+use crate::camera::Camera;
+use crate::mesh::Mesh;
 use std::collections::HashMap;
 use std::sync::Arc;
 use wgpu::util::DeviceExt;
@@ -13,7 +15,7 @@ use winit::window::Window;
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 struct Vertex {
     position: [f32; 3],
-    color: [f32; 3],
+    normal: [f32; 3],
 }
 
 impl Vertex {
@@ -29,7 +31,7 @@ impl Vertex {
                 },
                 wgpu::VertexAttribute {
                     offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
+                    shader_location: 6,
                     format: wgpu::VertexFormat::Float32x3,
                 },
             ],
@@ -37,43 +39,79 @@ impl Vertex {
     }
 }
 
+/// Placement and color of one shape, read per instance by the vertex shader.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    color: [f32; 3],
+}
+
+impl InstanceRaw {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        1 => Float32x4,
+        2 => Float32x4,
+        3 => Float32x4,
+        4 => Float32x4,
+        5 => Float32x3,
+    ];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
 struct UniformData {
     view_proj: [[f32; 4]; 4],
-    model: [[f32; 4]; 4],
+    /// Direction the light comes from, in world space
+    light_direction: [f32; 3],
+    /// Share of the color shown on faces turned away from the light
+    ambient: f32,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Shape {
     Cube,
     Pyramid,
     Torus,
     Cylinder,
+    Sphere,
     Cone,
     Octahedron,
     Prism,
     HexPrism,
     Diamond,
+    /// A mesh loaded from disk, by its index in the meshes given to [`Renderer::add_meshes`]
+    Mesh(usize),
 }
 
 impl Shape {
+    /// Every built-in shape, once each, in the order devices are assigned them
+    pub const ALL: [Shape; 10] = [
+        Shape::Cube,
+        Shape::Pyramid,
+        Shape::Torus,
+        Shape::Cylinder,
+        Shape::Sphere,
+        Shape::Cone,
+        Shape::Octahedron,
+        Shape::Prism,
+        Shape::HexPrism,
+        Shape::Diamond,
+    ];
+
     pub fn count() -> usize {
-        10
+        Self::ALL.len()
     }
 
     pub fn from_index(index: usize) -> Shape {
-        match index % 10 {
-            0 => Shape::Cube,
-            1 => Shape::Pyramid,
-            2 => Shape::Torus,
-            3 => Shape::Cylinder,
-            5 => Shape::Cone,
-            6 => Shape::Octahedron,
-            7 => Shape::Prism,
-            8 => Shape::HexPrism,
-            _ => Shape::Diamond,
-        }
+        Self::ALL[index % Self::ALL.len()]
     }
 
     /// Returns a fixed dark color for this shape (RGB values 0.0-1.0)
@@ -83,11 +121,13 @@ impl Shape {
             Shape::Pyramid => [0.2, 0.6, 0.2],    // Dark green
             Shape::Torus => [0.2, 0.2, 0.7],      // Dark blue
             Shape::Cylinder => [0.6, 0.6, 0.2],   // Dark yellow
+            Shape::Sphere => [0.6, 0.2, 0.5],     // Dark magenta
             Shape::Cone => [0.2, 0.6, 0.6],       // Dark cyan
             Shape::Octahedron => [0.7, 0.4, 0.2], // Dark orange
             Shape::Prism => [0.4, 0.2, 0.6],      // Dark purple
             Shape::HexPrism => [0.2, 0.5, 0.4],   // Dark teal
             Shape::Diamond => [0.5, 0.3, 0.2],    // Dark brown
+            Shape::Mesh(_) => [0.5, 0.5, 0.55],   // Gray, like a printed enclosure
         }
     }
 }
@@ -103,11 +143,137 @@ pub struct ShapeInstance {
 
 struct ShapeGeometry {
     vertices: Vec<Vertex>,
-    indices: Vec<u16>,
-    edge_indices: Vec<u16>,
+    indices: Vec<u32>,
+    edge_indices: Vec<u32>,
 }
 
-const MAX_INSTANCES: usize = 64;
+/// A position rounded by [`corner`], to find the triangles sharing a corner.
+type Corner = [i32; 3];
+
+/// Faces meeting at a larger angle than this, in radians, get a sharp edge between them.
+const CREASE_ANGLE: f32 = std::f32::consts::FRAC_PI_4;
+
+impl ShapeGeometry {
+    /// Geometry of the triangles `indices` into `positions`, with normals for lighting. Each
+    /// triangle gets vertices of its own, their normals averaged over the triangles around the
+    /// corner meeting it at less than [`CREASE_ANGLE`], so curved surfaces shade smoothly while
+    /// edges stay sharp. `positions` come first, for the `edge_indices` of the outline.
+    ///
+    /// The triangles of a shape may be wound either way, as long as they all agree. Normals
+    /// point out of the shape regardless.
+    fn new(positions: Vec<[f32; 3]>, mut indices: Vec<u32>, edge_indices: Vec<u32>) -> Self {
+        let position = |index: u32| Vector3::from(positions[index as usize]);
+        // Counter-clockwise triangles seen from outside enclose a positive volume
+        let volume: f32 = indices
+            .chunks_exact(3)
+            .map(|t| position(t[0]).dot(position(t[1]).cross(position(t[2]))))
+            .sum();
+        if volume < 0.0 {
+            for triangle in indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+        // Corners computed in different ways, like the seams of round shapes, still meet
+        let key = |index: u32| corner(positions[index as usize]);
+
+        // Area weighted, so small triangles do not skew the average
+        let faces: Vec<Vector3<f32>> = indices
+            .chunks_exact(3)
+            .map(|t| (position(t[1]) - position(t[0])).cross(position(t[2]) - position(t[0])))
+            .collect();
+        let mut around: HashMap<Corner, Vec<usize>> = HashMap::new();
+        for (face, triangle) in indices.chunks_exact(3).enumerate() {
+            for &index in triangle {
+                around.entry(key(index)).or_default().push(face);
+            }
+        }
+
+        let mut vertices: Vec<Vertex> = positions
+            .iter()
+            .map(|&position| Vertex { position, normal: [0.0; 3] })
+            .collect();
+        let mut lit_indices = Vec::with_capacity(indices.len());
+        let min_cos = CREASE_ANGLE.cos();
+        for (face, triangle) in indices.chunks_exact(3).enumerate() {
+            let own = unit(faces[face]);
+            for &index in triangle {
+                let normal = around[&key(index)]
+                    .iter()
+                    .map(|&other| faces[other])
+                    .filter(|other| unit(*other).dot(own) >= min_cos)
+                    .fold(Vector3::zero(), |sum, normal| sum + normal);
+                lit_indices.push(vertices.len() as u32);
+                vertices.push(Vertex {
+                    position: positions[index as usize],
+                    normal: unit(normal).into(),
+                });
+            }
+        }
+
+        Self { vertices, indices: lit_indices, edge_indices }
+    }
+
+    /// Geometry of a loaded mesh, outlined along its open edges and where its faces meet at
+    /// more than [`CREASE_ANGLE`], the edges the built-in shapes outline.
+    fn from_mesh(mesh: &Mesh) -> Self {
+        let positions = &mesh.positions;
+        let position = |index: u32| Vector3::from(positions[index as usize]);
+        let key = |index: u32| corner(positions[index as usize]);
+
+        let faces: Vec<Vector3<f32>> = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|t| unit((position(t[1]) - position(t[0])).cross(position(t[2]) - position(t[0]))))
+            .collect();
+        // Side `i` of a triangle runs from its corner `indices[i]` to the next
+        let next = |side: usize| side - side % 3 + (side + 1) % 3;
+        let ends = |side: usize| [mesh.indices[side], mesh.indices[next(side)]];
+
+        // The sides of the triangles along each edge
+        let mut sides: HashMap<(Corner, Corner), Vec<usize>> = HashMap::new();
+        for side in 0..mesh.indices.len() {
+            let [a, b] = ends(side).map(key);
+            sides.entry((a.min(b), a.max(b))).or_default().push(side);
+        }
+        let min_cos = CREASE_ANGLE.cos();
+        let edge_indices = sides
+            .into_values()
+            .filter(|sides| match sides[..] {
+                [first, second] => faces[first / 3].dot(faces[second / 3]) < min_cos,
+                _ => true,
+            })
+            .flat_map(|sides| ends(sides[0]))
+            .collect();
+
+        Self::new(positions.clone(), mesh.indices.clone(), edge_indices)
+    }
+}
+
+/// `position` rounded to a 10000th, so corners computed in different ways still meet.
+fn corner(position: [f32; 3]) -> Corner {
+    position.map(|c| (c * 1e4).round() as i32)
+}
+
+/// `v` scaled to unit length, or zero for degenerate triangles.
+fn unit(v: Vector3<f32>) -> Vector3<f32> {
+    if v.magnitude2() > 0.0 {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+/// Direction the light comes from by default: above, left of and behind the camera.
+pub const DEFAULT_LIGHT_DIRECTION: Vector3<f32> = Vector3::new(-0.4, 0.6, 0.7);
+
+/// Share of the color shown on faces turned away from the light.
+const AMBIENT: f32 = 0.3;
+
+/// Instances the instance buffer holds at first, it grows as needed.
+const INITIAL_INSTANCE_CAPACITY: usize = 64;
+
+/// Vertices, triangle indices and their count, edge indices and their count.
+type ShapeBuffers = (wgpu::Buffer, wgpu::Buffer, u32, wgpu::Buffer, u32);
 
 pub struct Renderer {
     surface: wgpu::Surface<'static>,
@@ -117,13 +283,19 @@ pub struct Renderer {
     pub size: winit::dpi::PhysicalSize<u32>,
     render_pipeline: wgpu::RenderPipeline,
     wireframe_pipeline: wgpu::RenderPipeline,
-    shape_buffers: HashMap<Shape, (wgpu::Buffer, wgpu::Buffer, u32, wgpu::Buffer, u32)>,
+    shape_buffers: HashMap<Shape, ShapeBuffers>,
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
-    uniform_alignment: u32,
+    /// Instances of all shapes, grouped by shape
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    light_direction: Vector3<f32>,
+    camera: Camera,
     depth_texture: wgpu::TextureView,
     instances: Vec<ShapeInstance>,
     // Text rendering
+    /// Shaped text of the labels shown, by label
+    label_buffers: HashMap<String, Buffer>,
     font_system: FontSystem,
     swash_cache: SwashCache,
     glyphon_cache: Cache,
@@ -190,27 +362,25 @@ impl Renderer {
             source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
         });
 
-        // Calculate aligned uniform size (must be multiple of 256 for dynamic uniform buffers)
-        let uniform_size = std::mem::size_of::<UniformData>() as u32;
-        let uniform_alignment = 256u32; // wgpu requires 256-byte alignment for dynamic offsets
-        let aligned_uniform_size = (uniform_size + uniform_alignment - 1) & !(uniform_alignment - 1);
-
+        let uniform_size = std::mem::size_of::<UniformData>() as u64;
         let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Uniform Buffer"),
-            size: (aligned_uniform_size as usize * MAX_INSTANCES) as u64,
+            size: uniform_size,
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
+        let instance_buffer = Self::create_instance_buffer(&device, INITIAL_INSTANCE_CAPACITY);
+
         let uniform_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: true,
-                        min_binding_size: wgpu::BufferSize::new(uniform_size as u64),
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(uniform_size),
                     },
                     count: None,
                 }],
@@ -221,11 +391,7 @@ impl Renderer {
             layout: &uniform_bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: &uniform_buffer,
-                    offset: 0,
-                    size: wgpu::BufferSize::new(uniform_size as u64),
-                }),
+                resource: uniform_buffer.as_entire_binding(),
             }],
             label: Some("uniform_bind_group"),
         });
@@ -243,7 +409,7 @@ impl Renderer {
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
                 compilation_options: Default::default(),
             },
             fragment: Some(wgpu::FragmentState {
@@ -288,7 +454,7 @@ impl Renderer {
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
+                buffers: &[Vertex::desc(), InstanceRaw::desc()],
                 compilation_options: Default::default(),
             },
             fragment: Some(wgpu::FragmentState {
@@ -332,35 +498,9 @@ impl Renderer {
         let mut shape_buffers = HashMap::new();
 
         // Add all shapes
-        for shape_type in &[
-            Shape::Cube, Shape::Pyramid, Shape::Torus, Shape::Cylinder,
-            Shape::Cone, Shape::Octahedron, Shape::Prism,
-            Shape::HexPrism, Shape::Diamond,
-        ] {
-            let geometry = Self::create_shape_geometry(shape_type);
-
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", shape_type)),
-                contents: bytemuck::cast_slice(&geometry.vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", shape_type)),
-                contents: bytemuck::cast_slice(&geometry.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            let edge_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Edge Buffer", shape_type)),
-                contents: bytemuck::cast_slice(&geometry.edge_indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            shape_buffers.insert(
-                shape_type.clone(),
-                (vertex_buffer, index_buffer, geometry.indices.len() as u32, edge_buffer, geometry.edge_indices.len() as u32),
-            );
+        for shape in Shape::ALL {
+            let geometry = Self::create_shape_geometry(&shape);
+            shape_buffers.insert(shape, Self::create_shape_buffers(&device, shape, &geometry));
         }
 
         // Initialize text rendering
@@ -393,9 +533,13 @@ impl Renderer {
             shape_buffers,
             uniform_buffer,
             uniform_bind_group,
-            uniform_alignment: aligned_uniform_size,
+            instance_buffer,
+            instance_capacity: INITIAL_INSTANCE_CAPACITY,
+            light_direction: DEFAULT_LIGHT_DIRECTION.normalize(),
+            camera: Camera::default(),
             depth_texture,
             instances: Vec::new(),
+            label_buffers: HashMap::new(),
             font_system,
             swash_cache,
             glyphon_cache,
@@ -405,6 +549,15 @@ impl Renderer {
         }
     }
 
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
     fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
@@ -427,53 +580,77 @@ impl Renderer {
         depth_texture.create_view(&wgpu::TextureViewDescriptor::default())
     }
 
+    /// The vertex, index and edge buffers of `geometry`, with the number of indices and edges.
+    fn create_shape_buffers(device: &wgpu::Device, shape: Shape, geometry: &ShapeGeometry) -> ShapeBuffers {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", shape)),
+            contents: bytemuck::cast_slice(&geometry.vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", shape)),
+            contents: bytemuck::cast_slice(&geometry.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let edge_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Edge Buffer", shape)),
+            contents: bytemuck::cast_slice(&geometry.edge_indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        (vertex_buffer, index_buffer, geometry.indices.len() as u32, edge_buffer, geometry.edge_indices.len() as u32)
+    }
+
     fn create_shape_geometry(shape: &Shape) -> ShapeGeometry {
-        let color = shape.color();
         match shape {
-            Shape::Cube => Self::create_cube(color),
-            Shape::Pyramid => Self::create_pyramid(color),
-            Shape::Torus => Self::create_torus(16, 8, 0.5, 0.2, color),
-            Shape::Cylinder => Self::create_cylinder(16, color),
-            Shape::Cone => Self::create_cone(16, color),
-            Shape::Octahedron => Self::create_octahedron(color),
-            Shape::Prism => Self::create_prism(color),
-            Shape::HexPrism => Self::create_hex_prism(color),
-            Shape::Diamond => Self::create_diamond(color),
+            Shape::Cube => Self::create_cube(),
+            Shape::Pyramid => Self::create_pyramid(),
+            Shape::Torus => Self::create_torus(16, 8, 0.5, 0.2),
+            Shape::Cylinder => Self::create_cylinder(16),
+            Shape::Sphere => Self::create_sphere(16, 12),
+            Shape::Cone => Self::create_cone(16),
+            Shape::Octahedron => Self::create_octahedron(),
+            Shape::Prism => Self::create_prism(),
+            Shape::HexPrism => Self::create_hex_prism(),
+            Shape::Diamond => Self::create_diamond(),
+            Shape::Mesh(_) => unreachable!("meshes are registered by add_meshes"),
         }
     }
 
-    fn create_cube(color: [f32; 3]) -> ShapeGeometry {
+    fn create_cube() -> ShapeGeometry {
         let vertices = vec![
             // Front face
-            Vertex { position: [-0.5, -0.5, 0.5], color },
-            Vertex { position: [0.5, -0.5, 0.5], color },
-            Vertex { position: [0.5, 0.5, 0.5], color },
-            Vertex { position: [-0.5, 0.5, 0.5], color },
+            [-0.5, -0.5, 0.5],
+            [0.5, -0.5, 0.5],
+            [0.5, 0.5, 0.5],
+            [-0.5, 0.5, 0.5],
             // Back face
-            Vertex { position: [-0.5, -0.5, -0.5], color },
-            Vertex { position: [0.5, -0.5, -0.5], color },
-            Vertex { position: [0.5, 0.5, -0.5], color },
-            Vertex { position: [-0.5, 0.5, -0.5], color },
+            [-0.5, -0.5, -0.5],
+            [0.5, -0.5, -0.5],
+            [0.5, 0.5, -0.5],
+            [-0.5, 0.5, -0.5],
             // Top face
-            Vertex { position: [-0.5, 0.5, 0.5], color },
-            Vertex { position: [0.5, 0.5, 0.5], color },
-            Vertex { position: [0.5, 0.5, -0.5], color },
-            Vertex { position: [-0.5, 0.5, -0.5], color },
+            [-0.5, 0.5, 0.5],
+            [0.5, 0.5, 0.5],
+            [0.5, 0.5, -0.5],
+            [-0.5, 0.5, -0.5],
             // Bottom face
-            Vertex { position: [-0.5, -0.5, 0.5], color },
-            Vertex { position: [0.5, -0.5, 0.5], color },
-            Vertex { position: [0.5, -0.5, -0.5], color },
-            Vertex { position: [-0.5, -0.5, -0.5], color },
+            [-0.5, -0.5, 0.5],
+            [0.5, -0.5, 0.5],
+            [0.5, -0.5, -0.5],
+            [-0.5, -0.5, -0.5],
             // Right face
-            Vertex { position: [0.5, -0.5, 0.5], color },
-            Vertex { position: [0.5, -0.5, -0.5], color },
-            Vertex { position: [0.5, 0.5, -0.5], color },
-            Vertex { position: [0.5, 0.5, 0.5], color },
+            [0.5, -0.5, 0.5],
+            [0.5, -0.5, -0.5],
+            [0.5, 0.5, -0.5],
+            [0.5, 0.5, 0.5],
             // Left face
-            Vertex { position: [-0.5, -0.5, 0.5], color },
-            Vertex { position: [-0.5, -0.5, -0.5], color },
-            Vertex { position: [-0.5, 0.5, -0.5], color },
-            Vertex { position: [-0.5, 0.5, 0.5], color },
+            [-0.5, -0.5, 0.5],
+            [-0.5, -0.5, -0.5],
+            [-0.5, 0.5, -0.5],
+            [-0.5, 0.5, 0.5],
         ];
 
         let indices = vec![
@@ -495,10 +672,10 @@ impl Renderer {
             0, 4,  1, 5,  2, 6,  3, 7,
         ];
 
-        ShapeGeometry { vertices, indices, edge_indices }
+        ShapeGeometry::new(vertices, indices, edge_indices)
     }
 
-    fn create_sphere(segments: u32, rings: u32, color: [f32; 3]) -> ShapeGeometry {
+    fn create_sphere(segments: u32, rings: u32) -> ShapeGeometry {
         let mut vertices = Vec::new();
         let mut indices = Vec::new();
 
@@ -516,10 +693,7 @@ impl Renderer {
                 let y = cos_theta;
                 let z = sin_phi * sin_theta;
 
-                vertices.push(Vertex {
-                    position: [x * 0.5, y * 0.5, z * 0.5],
-                    color,
-                });
+                vertices.push([x * 0.5, y * 0.5, z * 0.5]);
             }
         }
 
@@ -528,13 +702,13 @@ impl Renderer {
                 let current = ring * (segments + 1) + segment;
                 let next = current + segments + 1;
 
-                indices.push(current as u16);
-                indices.push(next as u16);
-                indices.push(current as u16 + 1);
+                indices.push(current);
+                indices.push(next);
+                indices.push(current + 1);
 
-                indices.push(current as u16 + 1);
-                indices.push(next as u16);
-                indices.push(next as u16 + 1);
+                indices.push(current + 1);
+                indices.push(next);
+                indices.push(next + 1);
             }
         }
 
@@ -547,32 +721,32 @@ impl Renderer {
                 let next_segment = current + 1;
 
                 // Vertical edge
-                edge_indices.push(current as u16);
-                edge_indices.push(next_ring as u16);
+                edge_indices.push(current);
+                edge_indices.push(next_ring);
 
                 // Horizontal edge
-                edge_indices.push(current as u16);
-                edge_indices.push(next_segment as u16);
+                edge_indices.push(current);
+                edge_indices.push(next_segment);
             }
         }
 
-        ShapeGeometry { vertices, indices, edge_indices }
+        ShapeGeometry::new(vertices, indices, edge_indices)
     }
 
-    fn create_pyramid(color: [f32; 3]) -> ShapeGeometry {
+    fn create_pyramid() -> ShapeGeometry {
         let vertices = vec![
             // Base
-            Vertex { position: [-0.5, -0.5, -0.5], color },
-            Vertex { position: [0.5, -0.5, -0.5], color },
-            Vertex { position: [0.5, -0.5, 0.5], color },
-            Vertex { position: [-0.5, -0.5, 0.5], color },
+            [-0.5, -0.5, -0.5],
+            [0.5, -0.5, -0.5],
+            [0.5, -0.5, 0.5],
+            [-0.5, -0.5, 0.5],
             // Apex
-            Vertex { position: [0.0, 0.5, 0.0], color },
+            [0.0, 0.5, 0.0],
             // Side vertices (duplicates for face normals)
-            Vertex { position: [-0.5, -0.5, -0.5], color },
-            Vertex { position: [0.5, -0.5, -0.5], color },
-            Vertex { position: [0.5, -0.5, 0.5], color },
-            Vertex { position: [-0.5, -0.5, 0.5], color },
+            [-0.5, -0.5, -0.5],
+            [0.5, -0.5, -0.5],
+            [0.5, -0.5, 0.5],
+            [-0.5, -0.5, 0.5],
         ];
 
         let indices = vec![
@@ -594,10 +768,10 @@ impl Renderer {
             0, 4,  1, 4,  2, 4,  3, 4,
         ];
 
-        ShapeGeometry { vertices, indices, edge_indices }
+        ShapeGeometry::new(vertices, indices, edge_indices)
     }
 
-    fn create_torus(major_segments: u32, minor_segments: u32, major_radius: f32, minor_radius: f32, color: [f32; 3]) -> ShapeGeometry {
+    fn create_torus(major_segments: u32, minor_segments: u32, major_radius: f32, minor_radius: f32) -> ShapeGeometry {
         let mut vertices = Vec::new();
         let mut indices = Vec::new();
 
@@ -615,10 +789,7 @@ impl Renderer {
                 let y = minor_radius * sin_phi;
                 let z = (major_radius + minor_radius * cos_phi) * sin_theta;
 
-                vertices.push(Vertex {
-                    position: [x, y, z],
-                    color,
-                });
+                vertices.push([x, y, z]);
             }
         }
 
@@ -627,13 +798,13 @@ impl Renderer {
                 let current = i * (minor_segments + 1) + j;
                 let next = current + minor_segments + 1;
 
-                indices.push(current as u16);
-                indices.push(next as u16);
-                indices.push(current as u16 + 1);
+                indices.push(current);
+                indices.push(next);
+                indices.push(current + 1);
 
-                indices.push(current as u16 + 1);
-                indices.push(next as u16);
-                indices.push(next as u16 + 1);
+                indices.push(current + 1);
+                indices.push(next);
+                indices.push(next + 1);
             }
         }
 
@@ -646,19 +817,19 @@ impl Renderer {
                 let next_minor = current + 1;
 
                 // Edge along major circle
-                edge_indices.push(current as u16);
-                edge_indices.push(next_major as u16);
+                edge_indices.push(current);
+                edge_indices.push(next_major);
 
                 // Edge along minor circle
-                edge_indices.push(current as u16);
-                edge_indices.push(next_minor as u16);
+                edge_indices.push(current);
+                edge_indices.push(next_minor);
             }
         }
 
-        ShapeGeometry { vertices, indices, edge_indices }
+        ShapeGeometry::new(vertices, indices, edge_indices)
     }
 
-    fn create_cylinder(segments: u32, color: [f32; 3]) -> ShapeGeometry {
+    fn create_cylinder(segments: u32) -> ShapeGeometry {
         let mut vertices = Vec::new();
         let mut indices = Vec::new();
 
@@ -669,62 +840,50 @@ impl Renderer {
             let z = angle.sin() * 0.5;
 
             // Top circle
-            vertices.push(Vertex {
-                position: [x, 0.5, z],
-                color,
-            });
+            vertices.push([x, 0.5, z]);
             // Bottom circle
-            vertices.push(Vertex {
-                position: [x, -0.5, z],
-                color,
-            });
+            vertices.push([x, -0.5, z]);
         }
 
         // Create cylinder sides
         for i in 0..segments {
             let idx = i * 2;
-            indices.push(idx as u16);
-            indices.push(idx as u16 + 1);
-            indices.push(idx as u16 + 2);
+            indices.push(idx);
+            indices.push(idx + 1);
+            indices.push(idx + 2);
 
-            indices.push(idx as u16 + 1);
-            indices.push(idx as u16 + 3);
-            indices.push(idx as u16 + 2);
+            indices.push(idx + 1);
+            indices.push(idx + 3);
+            indices.push(idx + 2);
         }
 
         // Add center vertices for caps
-        let top_center = vertices.len() as u16;
-        vertices.push(Vertex {
-            position: [0.0, 0.5, 0.0],
-            color,
-        });
+        let top_center = vertices.len() as u32;
+        vertices.push([0.0, 0.5, 0.0]);
 
-        let bottom_center = vertices.len() as u16;
-        vertices.push(Vertex {
-            position: [0.0, -0.5, 0.0],
-            color,
-        });
+        let bottom_center = vertices.len() as u32;
+        vertices.push([0.0, -0.5, 0.0]);
 
         // Create top and bottom caps
         for i in 0..segments {
             // Top cap
             indices.push(top_center);
-            indices.push((i * 2) as u16);
-            indices.push(((i + 1) * 2) as u16);
+            indices.push(i * 2  );
+            indices.push((i + 1) * 2  );
 
             // Bottom cap
             indices.push(bottom_center);
-            indices.push(((i + 1) * 2 + 1) as u16);
-            indices.push((i * 2 + 1) as u16);
+            indices.push((i + 1) * 2 + 1  );
+            indices.push(i * 2 + 1  );
         }
 
         // Create edge indices for the cylinder wireframe
         let mut edge_indices = Vec::new();
         for i in 0..segments {
-            let top = (i * 2) as u16;
-            let bottom = (i * 2 + 1) as u16;
-            let next_top = (((i + 1) % segments) * 2) as u16;
-            let next_bottom = (((i + 1) % segments) * 2 + 1) as u16;
+            let top = i * 2 ;
+            let bottom = i * 2 + 1 ;
+            let next_top = ((i + 1) % segments) * 2 ;
+            let next_bottom = ((i + 1) % segments) * 2 + 1 ;
 
             // Top rim circle
             edge_indices.push(top);
@@ -746,49 +905,40 @@ impl Renderer {
             edge_indices.push(bottom);
         }
 
-        ShapeGeometry { vertices, indices, edge_indices }
+        ShapeGeometry::new(vertices, indices, edge_indices)
     }
 
-    fn create_cone(segments: u32, color: [f32; 3]) -> ShapeGeometry {
+    fn create_cone(segments: u32) -> ShapeGeometry {
         let mut vertices = Vec::new();
         let mut indices = Vec::new();
 
         // Apex
-        vertices.push(Vertex {
-            position: [0.0, 0.5, 0.0],
-            color,
-        });
+        vertices.push([0.0, 0.5, 0.0]);
 
         // Base circle
         for i in 0..=segments {
             let angle = i as f32 * 2.0 * std::f32::consts::PI / segments as f32;
             let x = angle.cos() * 0.5;
             let z = angle.sin() * 0.5;
-            vertices.push(Vertex {
-                position: [x, -0.5, z],
-                color,
-            });
+            vertices.push([x, -0.5, z]);
         }
 
         // Base center
-        let base_center = vertices.len() as u16;
-        vertices.push(Vertex {
-            position: [0.0, -0.5, 0.0],
-            color,
-        });
+        let base_center = vertices.len() as u32;
+        vertices.push([0.0, -0.5, 0.0]);
 
         // Cone sides
         for i in 0..segments {
             indices.push(0); // apex
-            indices.push((i + 2) as u16);
-            indices.push((i + 1) as u16);
+            indices.push(i + 2  );
+            indices.push(i + 1  );
         }
 
         // Base cap
         for i in 0..segments {
             indices.push(base_center);
-            indices.push((i + 1) as u16);
-            indices.push((i + 2) as u16);
+            indices.push(i + 1  );
+            indices.push(i + 2  );
         }
 
         // Edge indices
@@ -796,27 +946,27 @@ impl Renderer {
         for i in 0..segments {
             // Edge from apex to base
             edge_indices.push(0);
-            edge_indices.push((i + 1) as u16);
+            edge_indices.push(i + 1  );
 
             // Base rim
-            edge_indices.push((i + 1) as u16);
-            edge_indices.push((((i + 1) % segments) + 1) as u16);
+            edge_indices.push(i + 1  );
+            edge_indices.push(((i + 1) % segments) + 1  );
         }
 
-        ShapeGeometry { vertices, indices, edge_indices }
+        ShapeGeometry::new(vertices, indices, edge_indices)
     }
 
-    fn create_octahedron(color: [f32; 3]) -> ShapeGeometry {
+    fn create_octahedron() -> ShapeGeometry {
         let vertices = vec![
             // Top apex
-            Vertex { position: [0.0, 0.5, 0.0], color },
+            [0.0, 0.5, 0.0],
             // Middle square
-            Vertex { position: [0.5, 0.0, 0.0], color },
-            Vertex { position: [0.0, 0.0, 0.5], color },
-            Vertex { position: [-0.5, 0.0, 0.0], color },
-            Vertex { position: [0.0, 0.0, -0.5], color },
+            [0.5, 0.0, 0.0],
+            [0.0, 0.0, 0.5],
+            [-0.5, 0.0, 0.0],
+            [0.0, 0.0, -0.5],
             // Bottom apex
-            Vertex { position: [0.0, -0.5, 0.0], color },
+            [0.0, -0.5, 0.0],
         ];
 
         let indices = vec![
@@ -841,20 +991,20 @@ impl Renderer {
             5, 1, 5, 2, 5, 3, 5, 4,
         ];
 
-        ShapeGeometry { vertices, indices, edge_indices }
+        ShapeGeometry::new(vertices, indices, edge_indices)
     }
 
-    fn create_prism(color: [f32; 3]) -> ShapeGeometry {
+    fn create_prism() -> ShapeGeometry {
         // Triangular prism
         let vertices = vec![
             // Top triangle
-            Vertex { position: [0.0, 0.5, 0.4], color },
-            Vertex { position: [-0.4, 0.5, -0.3], color },
-            Vertex { position: [0.4, 0.5, -0.3], color },
+            [0.0, 0.5, 0.4],
+            [-0.4, 0.5, -0.3],
+            [0.4, 0.5, -0.3],
             // Bottom triangle
-            Vertex { position: [0.0, -0.5, 0.4], color },
-            Vertex { position: [-0.4, -0.5, -0.3], color },
-            Vertex { position: [0.4, -0.5, -0.3], color },
+            [0.0, -0.5, 0.4],
+            [-0.4, -0.5, -0.3],
+            [0.4, -0.5, -0.3],
         ];
 
         let indices = vec![
@@ -877,10 +1027,10 @@ impl Renderer {
             0, 3, 1, 4, 2, 5,
         ];
 
-        ShapeGeometry { vertices, indices, edge_indices }
+        ShapeGeometry::new(vertices, indices, edge_indices)
     }
 
-    fn create_hex_prism(color: [f32; 3]) -> ShapeGeometry {
+    fn create_hex_prism() -> ShapeGeometry {
         let mut vertices = Vec::new();
         let mut indices = Vec::new();
 
@@ -891,54 +1041,42 @@ impl Renderer {
             let z = angle.sin() * 0.4;
 
             // Top vertex
-            vertices.push(Vertex {
-                position: [x, 0.5, z],
-                color,
-            });
+            vertices.push([x, 0.5, z]);
             // Bottom vertex
-            vertices.push(Vertex {
-                position: [x, -0.5, z],
-                color,
-            });
+            vertices.push([x, -0.5, z]);
         }
 
         // Top center
-        let top_center = vertices.len() as u16;
-        vertices.push(Vertex {
-            position: [0.0, 0.5, 0.0],
-            color,
-        });
+        let top_center = vertices.len() as u32;
+        vertices.push([0.0, 0.5, 0.0]);
 
         // Bottom center
-        let bottom_center = vertices.len() as u16;
-        vertices.push(Vertex {
-            position: [0.0, -0.5, 0.0],
-            color,
-        });
+        let bottom_center = vertices.len() as u32;
+        vertices.push([0.0, -0.5, 0.0]);
 
         // Top cap
         for i in 0..6 {
             let next = (i + 1) % 6;
             indices.push(top_center);
-            indices.push((i * 2) as u16);
-            indices.push((next * 2) as u16);
+            indices.push((i * 2) as u32);
+            indices.push((next * 2) as u32);
         }
 
         // Bottom cap
         for i in 0..6 {
             let next = (i + 1) % 6;
             indices.push(bottom_center);
-            indices.push((next * 2 + 1) as u16);
-            indices.push((i * 2 + 1) as u16);
+            indices.push((next * 2 + 1) as u32);
+            indices.push((i * 2 + 1) as u32);
         }
 
         // Sides
         for i in 0..6 {
             let next = (i + 1) % 6;
-            let top1 = (i * 2) as u16;
-            let bottom1 = (i * 2 + 1) as u16;
-            let top2 = (next * 2) as u16;
-            let bottom2 = (next * 2 + 1) as u16;
+            let top1 = (i * 2) as u32;
+            let bottom1 = (i * 2 + 1) as u32;
+            let top2 = (next * 2) as u32;
+            let bottom2 = (next * 2 + 1) as u32;
 
             indices.push(top1);
             indices.push(bottom1);
@@ -953,10 +1091,10 @@ impl Renderer {
         let mut edge_indices = Vec::new();
         for i in 0..6 {
             let next = (i + 1) % 6;
-            let top1 = (i * 2) as u16;
-            let bottom1 = (i * 2 + 1) as u16;
-            let top2 = (next * 2) as u16;
-            let bottom2 = (next * 2 + 1) as u16;
+            let top1 = (i * 2) as u32;
+            let bottom1 = (i * 2 + 1) as u32;
+            let top2 = (next * 2) as u32;
+            let bottom2 = (next * 2 + 1) as u32;
 
             // Top hexagon
             edge_indices.push(top1);
@@ -971,21 +1109,21 @@ impl Renderer {
             edge_indices.push(bottom1);
         }
 
-        ShapeGeometry { vertices, indices, edge_indices }
+        ShapeGeometry::new(vertices, indices, edge_indices)
     }
 
-    fn create_diamond(color: [f32; 3]) -> ShapeGeometry {
+    fn create_diamond() -> ShapeGeometry {
         // Double pyramid (bipyramid)
         let vertices = vec![
             // Top apex
-            Vertex { position: [0.0, 0.6, 0.0], color },
+            [0.0, 0.6, 0.0],
             // Middle square
-            Vertex { position: [0.4, 0.0, 0.0], color },
-            Vertex { position: [0.0, 0.0, 0.4], color },
-            Vertex { position: [-0.4, 0.0, 0.0], color },
-            Vertex { position: [0.0, 0.0, -0.4], color },
+            [0.4, 0.0, 0.0],
+            [0.0, 0.0, 0.4],
+            [-0.4, 0.0, 0.0],
+            [0.0, 0.0, -0.4],
             // Bottom apex
-            Vertex { position: [0.0, -0.6, 0.0], color },
+            [0.0, -0.6, 0.0],
         ];
 
         let indices = vec![
@@ -1010,7 +1148,7 @@ impl Renderer {
             5, 1, 5, 2, 5, 3, 5, 4,
         ];
 
-        ShapeGeometry { vertices, indices, edge_indices }
+        ShapeGeometry::new(vertices, indices, edge_indices)
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -1023,6 +1161,27 @@ impl Renderer {
         }
     }
 
+    /// Register `meshes` as [`Shape::Mesh`], numbered in order, replacing any given before.
+    pub fn add_meshes(&mut self, meshes: &[Mesh]) {
+        self.shape_buffers.retain(|shape, _| !matches!(shape, Shape::Mesh(_)));
+        for (index, mesh) in meshes.iter().enumerate() {
+            let geometry = ShapeGeometry::from_mesh(mesh);
+            let shape = Shape::Mesh(index);
+            let buffers = Self::create_shape_buffers(&self.device, shape, &geometry);
+            self.shape_buffers.insert(shape, buffers);
+        }
+    }
+
+    /// Light the shapes from `direction`, in world space: x to the right, y up and z towards
+    /// the camera.
+    pub fn set_light_direction(&mut self, direction: Vector3<f32>) {
+        self.light_direction = unit(direction);
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
+    }
+
     pub fn update(&mut self, instances: Vec<ShapeInstance>) {
         // Store instances for rendering
         self.instances = instances;
@@ -1036,16 +1195,21 @@ impl Renderer {
 
         // Set up view projection matrix
         let aspect = self.config.width as f32 / self.config.height as f32;
-        let camera_view = Matrix4::look_at_rh(
-            Point3::new(0.0, 0.0, 30.0),
-            Point3::new(0.0, 0.0, 0.0),
-            Vector3::unit_y(),
+        let view_proj = self.camera.view_proj(aspect);
+
+        self.queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[UniformData {
+                view_proj: view_proj.into(),
+                light_direction: self.light_direction.into(),
+                ambient: AMBIENT,
+            }]),
         );
-        let proj = cgmath::perspective(Deg(45.0), aspect, 0.1, 100.0);
-        let view_proj = proj * camera_view;
 
-        // Write all uniform data BEFORE creating the render pass
-        for (index, instance) in self.instances.iter().enumerate() {
+        // Group the instances by shape, so each shape is drawn in one call
+        let mut by_shape: HashMap<&Shape, Vec<InstanceRaw>> = HashMap::new();
+        for instance in &self.instances {
             let translation = Matrix4::from_translation(instance.position);
             let rotation = Matrix4::from_angle_x(Rad(instance.rotation.x))
                 * Matrix4::from_angle_y(Rad(instance.rotation.y))
@@ -1053,45 +1217,50 @@ impl Renderer {
             let scale = Matrix4::from_scale(instance.scale);
             let model = translation * rotation * scale;
 
-            let uniform_data = UniformData {
-                view_proj: view_proj.into(),
+            by_shape.entry(&instance.shape).or_default().push(InstanceRaw {
                 model: model.into(),
-            };
-
-            let offset = (index as u64) * (self.uniform_alignment as u64);
-            self.queue.write_buffer(
-                &self.uniform_buffer,
-                offset,
-                bytemuck::cast_slice(&[uniform_data]),
-            );
+                color: instance.shape.color(),
+            });
+        }
+        let mut instance_data = Vec::with_capacity(self.instances.len());
+        let mut batches = Vec::with_capacity(by_shape.len());
+        for (shape, instances) in by_shape {
+            let start = instance_data.len() as u32;
+            instance_data.extend(instances);
+            batches.push((*shape, start..instance_data.len() as u32));
+        }
+
+        if instance_data.len() > self.instance_capacity {
+            self.instance_capacity = instance_data.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(&self.device, self.instance_capacity);
+        }
+        if !instance_data.is_empty() {
+            self.queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instance_data));
         }
 
-        // Prepare text buffers for labels
-        let mut text_buffers: Vec<Buffer> = Vec::new();
+        // Shape the text of new labels, and drop the labels no longer shown
         let width = self.config.width as f32;
         let height = self.config.height as f32;
-
+        let mut label_buffers = HashMap::with_capacity(self.instances.len());
         for instance in &self.instances {
-            // Project 3D position to screen coordinates
-            let pos = instance.position;
-            let clip_pos = view_proj * Vector4::new(pos.x, pos.y, pos.z, 1.0);
-            let ndc_x = clip_pos.x / clip_pos.w;
-            let ndc_y = clip_pos.y / clip_pos.w;
-            let _screen_x = (ndc_x + 1.0) * 0.5 * width;
-            let _screen_y = (1.0 - ndc_y) * 0.5 * height;
-
-            // Create text buffer for this label
-            let mut buffer = Buffer::new(&mut self.font_system, Metrics::new(16.0, 18.0));
-            buffer.set_size(&mut self.font_system, Some(200.0), Some(30.0));
-            buffer.set_text(
-                &mut self.font_system,
-                &instance.label,
-                Attrs::new().family(Family::SansSerif),
-                Shaping::Advanced,
-            );
-            buffer.shape_until_scroll(&mut self.font_system, false);
-            text_buffers.push(buffer);
+            if label_buffers.contains_key(&instance.label) {
+                continue;
+            }
+            let buffer = self.label_buffers.remove(&instance.label).unwrap_or_else(|| {
+                let mut buffer = Buffer::new(&mut self.font_system, Metrics::new(16.0, 18.0));
+                buffer.set_size(&mut self.font_system, Some(200.0), Some(40.0));
+                buffer.set_text(
+                    &mut self.font_system,
+                    &instance.label,
+                    Attrs::new().family(Family::SansSerif),
+                    Shaping::Advanced,
+                );
+                buffer.shape_until_scroll(&mut self.font_system, false);
+                buffer
+            });
+            label_buffers.insert(instance.label.clone(), buffer);
         }
+        self.label_buffers = label_buffers;
 
         // Update viewport resolution
         self.viewport.update(
@@ -1106,18 +1275,21 @@ impl Renderer {
         let text_areas: Vec<TextArea> = self
             .instances
             .iter()
-            .zip(text_buffers.iter())
-            .map(|(instance, buffer)| {
+            .filter_map(|instance| {
                 // Project a point below the shape (accounting for scale) to position text
                 let text_world_y = instance.position.y - instance.scale * 0.8;
                 let clip_pos = view_proj * Vector4::new(instance.position.x, text_world_y, instance.position.z, 1.0);
+                if clip_pos.w <= 0.0 {
+                    // Behind the camera
+                    return None;
+                }
                 let ndc_x = clip_pos.x / clip_pos.w;
                 let ndc_y = clip_pos.y / clip_pos.w;
                 let screen_x = (ndc_x + 1.0) * 0.5 * width;
                 let screen_y = (1.0 - ndc_y) * 0.5 * height;
 
-                TextArea {
-                    buffer,
+                Some(TextArea {
+                    buffer: &self.label_buffers[&instance.label],
                     left: screen_x - 50.0,
                     top: screen_y,
                     scale: 1.0,
@@ -1129,7 +1301,7 @@ impl Renderer {
                     },
                     default_color: Color::rgb(255, 255, 255),
                     custom_glyphs: &[],
-                }
+                })
             })
             .collect();
 
@@ -1180,35 +1352,32 @@ impl Renderer {
                 timestamp_writes: None,
             });
 
+            render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+
             // First pass: render filled shapes
             render_pass.set_pipeline(&self.render_pipeline);
 
-            for (index, instance) in self.instances.iter().enumerate() {
-                let dynamic_offset = (index as u32) * self.uniform_alignment;
-                render_pass.set_bind_group(0, &self.uniform_bind_group, &[dynamic_offset]);
-
-                // Draw the appropriate shape
+            for (shape, instances) in &batches {
+                // Draw all instances of the shape
                 if let Some((vertex_buffer, index_buffer, index_count, _, _)) =
-                    self.shape_buffers.get(&instance.shape) {
+                    self.shape_buffers.get(shape) {
                     render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                    render_pass.draw_indexed(0..*index_count, 0, 0..1);
+                    render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    render_pass.draw_indexed(0..*index_count, 0, instances.clone());
                 }
             }
 
             // Second pass: render wireframe outlines
             render_pass.set_pipeline(&self.wireframe_pipeline);
 
-            for (index, instance) in self.instances.iter().enumerate() {
-                let dynamic_offset = (index as u32) * self.uniform_alignment;
-                render_pass.set_bind_group(0, &self.uniform_bind_group, &[dynamic_offset]);
-
+            for (shape, instances) in &batches {
                 // Draw wireframe using edge indices
                 if let Some((vertex_buffer, _, _, edge_buffer, edge_count)) =
-                    self.shape_buffers.get(&instance.shape) {
+                    self.shape_buffers.get(shape) {
                     render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-                    render_pass.set_index_buffer(edge_buffer.slice(..), wgpu::IndexFormat::Uint16);
-                    render_pass.draw_indexed(0..*edge_count, 0, 0..1);
+                    render_pass.set_index_buffer(edge_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    render_pass.draw_indexed(0..*edge_count, 0, instances.clone());
                 }
             }
 
@@ -1224,3 +1393,107 @@ impl Renderer {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A unit cube centered on the origin, two counter-clockwise triangles per side.
+    fn cube() -> Mesh {
+        let positions = (0..8)
+            .map(|i| [0, 1, 2].map(|axis| if i & (1 << axis) != 0 { 0.5 } else { -0.5 }))
+            .collect();
+        #[rustfmt::skip]
+        let indices = vec![
+            0, 2, 3, 0, 3, 1, // -z
+            4, 5, 7, 4, 7, 6, // +z
+            0, 1, 5, 0, 5, 4, // -y
+            2, 6, 7, 2, 7, 3, // +y
+            0, 4, 6, 0, 6, 2, // -x
+            1, 3, 7, 1, 7, 5, // +x
+        ];
+        Mesh { name: "cube".into(), positions, indices }
+    }
+
+    /// The outlined edges, each with its ends in order.
+    fn edges(geometry: &ShapeGeometry, mesh: &Mesh) -> Vec<[[f32; 3]; 2]> {
+        let mut edges: Vec<_> = geometry
+            .edge_indices
+            .chunks_exact(2)
+            .map(|edge| {
+                let [a, b] = [edge[0], edge[1]].map(|index| mesh.positions[index as usize]);
+                if corner(a) < corner(b) { [a, b] } else { [b, a] }
+            })
+            .collect();
+        edges.sort_by_key(|[a, b]| (corner(*a), corner(*b)));
+        edges
+    }
+
+    /// Assert every vertex has a unit normal pointing away from the center of the cube, along
+    /// one axis.
+    fn assert_outward_normals(geometry: &ShapeGeometry) {
+        assert_eq!(geometry.indices.len(), 36);
+        for &index in &geometry.indices {
+            let Vertex { position, normal } = geometry.vertices[index as usize];
+            let normal = Vector3::from(normal);
+            assert!((normal.magnitude() - 1.0).abs() < 1e-6, "{:?}", normal);
+            let axes = normal.map(|c| c.abs().round());
+            assert_eq!(axes.x + axes.y + axes.z, 1.0, "{:?}", normal);
+            assert!(normal.dot(Vector3::from(position)) > 0.0, "{:?} at {:?}", normal, position);
+        }
+    }
+
+    #[test]
+    fn mesh_outlined_at_creases() {
+        let mesh = cube();
+        let geometry = ShapeGeometry::from_mesh(&mesh);
+        // The 12 edges of the cube, not the diagonals splitting its sides
+        let edges = edges(&geometry, &mesh);
+        assert_eq!(edges.len(), 12);
+        for [a, b] in edges {
+            assert_eq!((0..3).filter(|&axis| a[axis] != b[axis]).count(), 1, "{:?} to {:?}", a, b);
+        }
+        assert_outward_normals(&geometry);
+    }
+
+    #[test]
+    fn mesh_normals_point_out_either_winding() {
+        let mut mesh = cube();
+        for triangle in mesh.indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+        assert_outward_normals(&ShapeGeometry::from_mesh(&mesh));
+    }
+
+    #[test]
+    fn mesh_outlined_along_open_edges() {
+        // A square of two triangles, outlined around but not along the diagonal
+        let mesh = Mesh {
+            name: "square".into(),
+            positions: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+            indices: vec![0, 1, 2, 0, 2, 3],
+        };
+        let edges = edges(&ShapeGeometry::from_mesh(&mesh), &mesh);
+        let expected = [
+            [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]],
+            [[0.0, 1.0, 0.0], [1.0, 1.0, 0.0]],
+            [[1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+        ];
+        assert_eq!(edges, expected);
+    }
+
+    #[test]
+    fn mesh_seams_are_not_outlined() {
+        // The square again, its triangles not sharing vertices but meeting along the diagonal
+        let mesh = Mesh {
+            name: "seam".into(),
+            positions: vec![
+                [0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0],
+            ],
+            indices: vec![0, 1, 2, 3, 4, 5],
+        };
+        assert_eq!(edges(&ShapeGeometry::from_mesh(&mesh), &mesh).len(), 4);
+    }
+}
@@ -1,33 +1,50 @@
 struct VertexInput {
     @location(0) position: vec3<f32>,
-    @location(1) color: vec3<f32>,
+    @location(6) normal: vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(1) model_0: vec4<f32>,
+    @location(2) model_1: vec4<f32>,
+    @location(3) model_2: vec4<f32>,
+    @location(4) model_3: vec4<f32>,
+    @location(5) color: vec3<f32>,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec3<f32>,
+    @location(1) normal: vec3<f32>,
 }
 
 struct Uniforms {
     view_proj: mat4x4<f32>,
-    model: mat4x4<f32>,
+    // Direction the light comes from, in world space
+    light_direction: vec3<f32>,
+    // Share of the color shown on faces turned away from the light
+    ambient: f32,
 }
 
 @group(0) @binding(0)
 var<uniform> uniforms: Uniforms;
 
 @vertex
-fn vs_main(in: VertexInput) -> VertexOutput {
+fn vs_main(in: VertexInput, instance: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
-    let world_position = uniforms.model * vec4<f32>(in.position, 1.0);
+    let model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let world_position = model * vec4<f32>(in.position, 1.0);
     out.clip_position = uniforms.view_proj * world_position;
-    out.color = in.color;
+    out.color = instance.color;
+    // Shapes are scaled uniformly, so the model matrix keeps normals perpendicular
+    out.normal = (model * vec4<f32>(in.normal, 0.0)).xyz;
     return out;
 }
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
+    let diffuse = max(dot(normalize(in.normal), uniforms.light_direction), 0.0);
+    let light = uniforms.ambient + (1.0 - uniforms.ambient) * diffuse;
+    return vec4<f32>(in.color * light, 1.0);
 }
 
 @fragment
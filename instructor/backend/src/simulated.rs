@@ -0,0 +1,36 @@
+//! What the simulated devices of test mode and `xl-loadgen` have in common.
+
+use std::f32::consts::PI;
+use std::net::{IpAddr, SocketAddr};
+
+use tokio::net::{TcpSocket, TcpStream};
+
+/// Connect to `server` from `address` if given. Some systems only have 127.0.0.1 on the
+/// loopback interface, so when binding fails the address is dropped and the system picks one
+/// instead, for this and all following connections made with the same `address`.
+pub async fn connect_from(server: SocketAddr, address: &mut Option<IpAddr>) -> std::io::Result<TcpStream> {
+    let socket = match server {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if let Some(ip) = *address {
+        if let Err(e) = socket.bind(SocketAddr::new(ip, 0)) {
+            log::warn!("Failed binding to {}, connecting from the default address: {}", ip, e);
+            *address = None;
+        }
+    }
+    socket.connect(server).await
+}
+
+/// Gravity as measured by a sensor rolled about x and then pitched about y, in radians.
+pub fn gravity(roll: f32, pitch: f32) -> [f32; 3] {
+    let (sr, cr) = roll.sin_cos();
+    let (sp, cp) = pitch.sin_cos();
+    [-sp, sr * cp, cr * cp]
+}
+
+/// Gravity as measured by a sensor slowly tilting back and forth about x and y, `t` seconds
+/// into the motion.
+pub fn tilt(t: f32) -> [f32; 3] {
+    gravity((2.0 * PI * t / 6.0).sin(), 0.8 * (2.0 * PI * t / 9.0).sin())
+}
@@ -0,0 +1,167 @@
+//! Simulated devices for `--test` mode.
+//!
+//! Each client connects to the viewer's own TCP server and speaks the framed protocol like a
+//! board does, so test mode goes through the same ingest path as real devices. Clients play a
+//! [`Script`] each, taken in turn from the scripts selected on the command line. The viewer tells
+//! devices apart by their IP address, so each client connects from its own loopback address,
+//! 127.0.2.1 and up.
+
+use std::f32::consts::PI;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::io::AsyncWriteExt;
+use tcp_3d_viewer::simulated::{connect_from, gravity, tilt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, Instant, MissedTickBehavior};
+use workshop_common::protocol::{self, Frame};
+use workshop_common::Sample;
+
+/// First of the loopback addresses test clients connect from.
+const FIRST_ADDRESS: Ipv4Addr = Ipv4Addr::new(127, 0, 2, 1);
+
+/// Sample rate of the simulated sensors.
+const SAMPLE_RATE: f32 = 100.0;
+/// Samples sent in each frame, a frame every 40 ms.
+const BATCH_LEN: usize = 4;
+
+/// Noise every script adds, in g.
+const NOISE: f32 = 0.01;
+
+/// Motion, and connection trouble, a test client goes through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Script {
+    /// Slowly tilting back and forth about x and y.
+    Tilt,
+    /// Lying flat, with bursts of strong shaking.
+    Shake,
+    /// Turning over and over about x and y.
+    Tumble,
+    /// Lying still at an angle, with heavy noise.
+    Noise,
+    /// Tilting, but silent for a while every few seconds with the connection open.
+    Dropout,
+    /// Tilting, and closing the connection every few seconds to connect again.
+    Reconnect,
+}
+
+impl Script {
+    pub const ALL: [Script; 6] = [Script::Tilt, Script::Shake, Script::Tumble, Script::Noise, Script::Dropout, Script::Reconnect];
+
+    /// Acceleration in g at `t` seconds into the script, without the noise.
+    fn acceleration(&self, t: f32) -> [f32; 3] {
+        match self {
+            Script::Tilt | Script::Dropout | Script::Reconnect => tilt(t),
+            Script::Shake => {
+                let [x, y, z] = gravity(0.0, 0.0);
+                // Shaking for 1.5 s every 4 s
+                if t % 4.0 < 1.5 {
+                    [x + 1.5 * (2.0 * PI * 6.0 * t).sin(), y + 0.8 * (2.0 * PI * 5.0 * t + 1.0).sin(), z]
+                } else {
+                    [x, y, z]
+                }
+            }
+            Script::Tumble => gravity(0.8 * t, 0.5 * t),
+            Script::Noise => gravity(0.35, -0.2),
+        }
+    }
+
+    /// Amplitude of the noise, in g.
+    fn noise(&self) -> f32 {
+        match self {
+            Script::Noise => 0.15,
+            _ => NOISE,
+        }
+    }
+
+    /// Whether the client stays silent at `t` seconds into the connection.
+    fn silent(&self, t: f32) -> bool {
+        // Silent for 2 s of every 7 s
+        *self == Script::Dropout && t % 7.0 >= 5.0
+    }
+
+    /// Whether the client closes the connection at `t` seconds into it.
+    fn disconnects(&self, t: f32) -> bool {
+        *self == Script::Reconnect && t >= 5.0
+    }
+}
+
+impl std::str::FromStr for Script {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tilt" => Ok(Script::Tilt),
+            "shake" => Ok(Script::Shake),
+            "tumble" => Ok(Script::Tumble),
+            "noise" => Ok(Script::Noise),
+            "dropout" => Ok(Script::Dropout),
+            "reconnect" => Ok(Script::Reconnect),
+            _ => Err(format!("unknown script '{}', expected tilt, shake, tumble, noise, dropout or reconnect", s)),
+        }
+    }
+}
+
+/// Start `count` clients connecting to `server`, playing `scripts` in turn.
+pub fn spawn(count: usize, scripts: &[Script], server: SocketAddr) {
+    for index in 0..count {
+        let script = scripts[index % scripts.len()];
+        log::info!("Test client {} plays {:?}", index, script);
+        tokio::spawn(run(index, script, server));
+    }
+}
+
+/// Play `script`, connecting again whenever the connection fails or the script closes it.
+async fn run(index: usize, script: Script, server: SocketAddr) {
+    let mut address = match server.ip() {
+        IpAddr::V4(ip) if ip.is_loopback() => Some(IpAddr::V4(Ipv4Addr::from(u32::from(FIRST_ADDRESS) + index as u32))),
+        _ => None,
+    };
+    let mut rng = StdRng::seed_from_u64(index as u64);
+    // Clients start at different points of the script, so they do not move in lockstep
+    let start = Instant::now() - Duration::from_secs_f32(index as f32 * 1.3);
+    loop {
+        match connect_from(server, &mut address).await {
+            Ok(stream) => {
+                if let Err(e) = play(script, start, stream, &mut rng).await {
+                    log::debug!("Test client {} disconnected: {}", index, e);
+                }
+            }
+            Err(e) => log::debug!("Test client {} failed connecting to {}: {}", index, server, e),
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Send the samples of `script` until the connection fails or the script closes it. Commands
+/// from the viewer are not read, test clients have nothing to calibrate.
+async fn play(script: Script, start: Instant, mut stream: TcpStream, rng: &mut StdRng) -> std::io::Result<()> {
+    stream.write_all(&protocol::MAGIC).await?;
+    let connected = Instant::now();
+    let period = Duration::from_secs_f32(1.0 / SAMPLE_RATE);
+    let mut ticks = tokio::time::interval(period * BATCH_LEN as u32);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut buf = [0u8; protocol::MAX_FRAME_LEN];
+    loop {
+        let now = ticks.tick().await;
+        let since_connect = (now - connected).as_secs_f32();
+        if script.disconnects(since_connect) {
+            return Ok(());
+        }
+        if script.silent(since_connect) {
+            continue;
+        }
+
+        let samples = (0..BATCH_LEN)
+            .map(|i| {
+                let t = (now - start).as_secs_f32() - (BATCH_LEN - 1 - i) as f32 * period.as_secs_f32();
+                let [x, y, z] = script.acceleration(t).map(|a| a + script.noise() * rng.gen_range(-1.0..1.0));
+                Sample::new(x, y, z)
+            })
+            .collect();
+        let len = Frame::Samples(samples).encode(&mut buf).expect("buffer fits any frame");
+        stream.write_all(&buf[..len]).await?;
+    }
+}
@@ -0,0 +1,149 @@
+//! Size of delta encoded sample batches compared to the plain encodings.
+//!
+//! Runs on a few synthetic traces, and on recordings made with `tcp-3d-viewer --record <dir>`
+//! passed as arguments:
+//!
+//! ```text
+//! cargo bench --bench compression -- recordings/*.xlp
+//! ```
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use heapless::Vec;
+use workshop_common::delta;
+use workshop_common::protocol::{self, Frame, Header, HEADER_LEN, MAX_SAMPLES, RAW_SAMPLE_LEN, SAMPLE_LEN};
+use workshop_common::raw::RawSample;
+
+type Batch = Vec<RawSample, MAX_SAMPLES>;
+
+/// Counts per g of samples recorded in g, as a ±2 g sensor with 16 bits would give.
+const COUNTS_PER_G: f32 = 16384.0;
+
+fn main() {
+    let mut traces = vec![
+        ("lis3dh still, 12 bit", synthetic(12, 0.0, 0.002)),
+        ("lis3dh tilting, 12 bit", synthetic(12, 0.3, 0.002)),
+        ("lsm6dso still, 16 bit", synthetic(16, 0.0, 0.002)),
+        ("lsm6dso shaking, 16 bit", synthetic(16, 8.0, 0.05)),
+    ];
+    for path in std::env::args().skip(1).filter(|arg| !arg.starts_with("--")) {
+        match std::fs::read(&path) {
+            Ok(data) => traces.push((leak(path), recorded(&data))),
+            Err(e) => eprintln!("Skipping {}: {}", path, e),
+        }
+    }
+
+    println!("{:<40} {:>8} {:>10} {:>10} {:>8} {:>8} {:>10}", "trace", "samples", "raw B", "delta B", "vs raw", "vs f32", "per sample");
+    for (name, batches) in &traces {
+        report(name, batches);
+    }
+}
+
+fn report(name: &str, batches: &[Batch]) {
+    let samples: usize = batches.iter().map(|b| b.len()).sum();
+    if samples == 0 {
+        println!("{:<40} no samples", name);
+        return;
+    }
+    let float = batches.iter().map(|b| HEADER_LEN + b.len() * SAMPLE_LEN).sum::<usize>();
+    let raw = batches.iter().map(|b| HEADER_LEN + b.len() * RAW_SAMPLE_LEN).sum::<usize>();
+
+    let mut buf = [0u8; protocol::MAX_FRAME_LEN];
+    let mut compressed = 0;
+    for batch in batches {
+        let len = Frame::DeltaSamples(batch.clone()).encode(&mut buf).unwrap();
+        let header = Header::decode(buf[..HEADER_LEN].try_into().unwrap());
+        let Ok(Frame::DeltaSamples(decoded)) = Frame::decode(header.tag, &buf[HEADER_LEN..len]) else {
+            panic!("{}: batch does not decode", name);
+        };
+        assert_eq!(&decoded, batch, "{}: batch does not round trip", name);
+        compressed += len;
+    }
+
+    let start = Instant::now();
+    for batch in batches {
+        black_box(delta::encode(black_box(batch), &mut buf).unwrap());
+    }
+    let per_sample = start.elapsed() / samples as u32;
+
+    println!(
+        "{:<40} {:>8} {:>10} {:>10} {:>7.1}% {:>7.1}% {:>10?}",
+        name,
+        samples,
+        raw,
+        compressed,
+        100.0 * compressed as f64 / raw as f64,
+        100.0 * compressed as f64 / float as f64,
+        per_sample,
+    );
+}
+
+/// Batches of 25 samples of a sensor with the given resolution, rotating about x at `speed`
+/// rad/s at 400 Hz, with gaussian-ish noise of `noise` g.
+fn synthetic(resolution: u32, speed: f32, noise: f32) -> std::vec::Vec<Batch> {
+    let mut rng = 0x2545_f491_4f6c_dd1du64;
+    let mut noise = move || {
+        // Sum of uniforms, close enough to the sensor's noise for sizing
+        (0..4)
+            .map(|_| {
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                (rng >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+            })
+            .sum::<f32>()
+            * noise
+    };
+    let mask = !((1i32 << (16 - resolution)) - 1);
+    let count = move |g: f32| ((g * COUNTS_PER_G) as i32).clamp(i16::MIN as i32, i16::MAX as i32) & mask;
+
+    (0..400)
+        .map(|batch| {
+            (0..25)
+                .map(|i| {
+                    let angle = speed * (batch * 25 + i) as f32 / 400.0;
+                    RawSample {
+                        x: count(noise()) as i16,
+                        y: count(angle.sin() + noise()) as i16,
+                        z: count(angle.cos() + noise()) as i16,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The sample batches of a recorded connection, of all sensors.
+fn recorded(data: &[u8]) -> std::vec::Vec<Batch> {
+    let mut batches = std::vec::Vec::new();
+    let Some(mut rest) = data.strip_prefix(&protocol::MAGIC) else {
+        eprintln!("Not a recording of the framed protocol");
+        return batches;
+    };
+    while let Some(header) = rest.first_chunk::<HEADER_LEN>() {
+        let header = Header::decode(*header);
+        let Some(payload) = rest.get(HEADER_LEN..HEADER_LEN + header.len as usize) else {
+            break;
+        };
+        rest = &rest[HEADER_LEN + payload.len()..];
+        match Frame::decode(header.tag, payload) {
+            Ok(Frame::RawSamples(samples) | Frame::DeltaSamples(samples)) => batches.push(samples),
+            Ok(Frame::Samples(samples)) => batches.push(
+                samples
+                    .iter()
+                    .map(|s| {
+                        let [x, y, z] = [s.x, s.y, s.z].map(|g| (g * COUNTS_PER_G) as i16);
+                        RawSample { x, y, z }
+                    })
+                    .collect(),
+            ),
+            _ => {}
+        }
+    }
+    batches
+}
+
+fn leak(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
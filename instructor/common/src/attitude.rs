@@ -0,0 +1,158 @@
+//! Orientation of the sensor, estimated from the acceleration and, if available, the angular
+//! rate.
+//!
+//! The math uses `micromath` on every target, including the host, so the device and the viewer
+//! compute bit-identical results from the same samples.
+
+use micromath::F32Ext;
+
+use crate::Sample;
+
+/// Gain of the accelerometer correction in [`AttitudeEstimator`]. Higher values trust the
+/// accelerometer more, at the cost of more noise.
+pub const DEFAULT_BETA: f32 = 0.1;
+
+const DEGREES_TO_RADIANS: f32 = core::f32::consts::PI / 180.0;
+
+/// Orientation of the sensor relative to the earth.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Attitude {
+    /// Roll and pitch in radians, from the direction of gravity alone. The heading cannot be
+    /// observed without a gyroscope or magnetometer.
+    Tilt { roll: f32, pitch: f32 },
+    /// Full orientation as a unit quaternion, fusing acceleration and angular rate.
+    Quaternion { w: f32, x: f32, y: f32, z: f32 },
+}
+
+impl Attitude {
+    /// Roll and pitch of a stationary sensor, from its acceleration.
+    pub fn from_sample(sample: &Sample) -> Self {
+        // Standing on its x axis the roll is undefined, and micromath gives NaN for it
+        let roll = if sample.y == 0.0 && sample.z == 0.0 { 0.0 } else { F32Ext::atan2(sample.y, sample.z) };
+        let pitch = F32Ext::atan2(-sample.x, sqrt(sample.y * sample.y + sample.z * sample.z));
+        Attitude::Tilt { roll, pitch }
+    }
+
+    /// Roll, pitch and yaw in radians, rotating about x, y and z in that order. The yaw of a tilt
+    /// is zero.
+    pub fn euler(&self) -> (f32, f32, f32) {
+        match *self {
+            Attitude::Tilt { roll, pitch } => (roll, pitch, 0.0),
+            Attitude::Quaternion { w, x, y, z } => {
+                let roll = F32Ext::atan2(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y));
+                let pitch = F32Ext::asin((2.0 * (w * y - z * x)).clamp(-1.0, 1.0));
+                let yaw = F32Ext::atan2(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+                (roll, pitch, yaw)
+            }
+        }
+    }
+}
+
+/// Tracks the attitude over a stream of samples.
+///
+/// Samples without angular rate give a [`Attitude::Tilt`] computed from each sample on its own.
+/// With angular rate, the Madgwick filter integrates the gyroscope and slowly corrects the drift
+/// of roll and pitch towards the direction of gravity.
+pub struct AttitudeEstimator {
+    beta: f32,
+    q: Option<[f32; 4]>,
+}
+
+impl AttitudeEstimator {
+    pub fn new(beta: f32) -> Self {
+        Self { beta, q: None }
+    }
+
+    /// Forget the fused orientation, for example after samples were lost.
+    pub fn reset(&mut self) {
+        self.q = None;
+    }
+
+    /// Update the estimate with a sample taken `dt` seconds after the previous one.
+    pub fn update(&mut self, sample: &Sample, dt: f32) -> Attitude {
+        let Some(rate) = sample.gyro else {
+            self.q = None;
+            return Attitude::from_sample(sample);
+        };
+        let [w, x, y, z] = match self.q {
+            Some(q) => madgwick(q, sample, [rate.x, rate.y, rate.z].map(|r| r * DEGREES_TO_RADIANS), self.beta, dt),
+            // Start from the tilt, so the filter does not need to converge from level
+            None => from_tilt(sample),
+        };
+        self.q = Some([w, x, y, z]);
+        Attitude::Quaternion { w, x, y, z }
+    }
+}
+
+impl Default for AttitudeEstimator {
+    fn default() -> Self {
+        Self::new(DEFAULT_BETA)
+    }
+}
+
+/// The quaternion with the roll and pitch of the sample and zero yaw.
+fn from_tilt(sample: &Sample) -> [f32; 4] {
+    let (roll, pitch, _) = Attitude::from_sample(sample).euler();
+    let (sr, cr) = (F32Ext::sin(roll / 2.0), F32Ext::cos(roll / 2.0));
+    let (sp, cp) = (F32Ext::sin(pitch / 2.0), F32Ext::cos(pitch / 2.0));
+    [cr * cp, sr * cp, cr * sp, -sr * sp]
+}
+
+/// One step of the IMU variant of Madgwick's filter, with the angular rate `g` in rad/s.
+fn madgwick([q0, q1, q2, q3]: [f32; 4], a: &Sample, [gx, gy, gz]: [f32; 3], beta: f32, dt: f32) -> [f32; 4] {
+    // Rate of change of the quaternion from the gyroscope
+    let mut q_dot = [
+        0.5 * (-q1 * gx - q2 * gy - q3 * gz),
+        0.5 * (q0 * gx + q2 * gz - q3 * gy),
+        0.5 * (q0 * gy - q1 * gz + q3 * gx),
+        0.5 * (q0 * gz + q1 * gy - q2 * gx),
+    ];
+
+    // Gradient descent step towards the measured direction of gravity, skipped in free fall
+    let norm = a.x * a.x + a.y * a.y + a.z * a.z;
+    if norm > 0.0 {
+        let inv = inv_sqrt(norm);
+        let (ax, ay, az) = (a.x * inv, a.y * inv, a.z * inv);
+
+        let s = [
+            4.0 * q0 * q2 * q2 + 2.0 * q2 * ax + 4.0 * q0 * q1 * q1 - 2.0 * q1 * ay,
+            4.0 * q1 * q3 * q3 - 2.0 * q3 * ax + 4.0 * q0 * q0 * q1 - 2.0 * q0 * ay - 4.0 * q1
+                + 8.0 * q1 * q1 * q1
+                + 8.0 * q1 * q2 * q2
+                + 4.0 * q1 * az,
+            4.0 * q0 * q0 * q2 + 2.0 * q0 * ax + 4.0 * q2 * q3 * q3 - 2.0 * q3 * ay - 4.0 * q2
+                + 8.0 * q2 * q1 * q1
+                + 8.0 * q2 * q2 * q2
+                + 4.0 * q2 * az,
+            4.0 * q1 * q1 * q3 - 2.0 * q1 * ax + 4.0 * q2 * q2 * q3 - 2.0 * q2 * ay,
+        ];
+        let s_norm = s[0] * s[0] + s[1] * s[1] + s[2] * s[2] + s[3] * s[3];
+        if s_norm > 0.0 {
+            let inv = inv_sqrt(s_norm);
+            for (d, s) in q_dot.iter_mut().zip(s) {
+                *d -= beta * s * inv;
+            }
+        }
+    }
+
+    let q = [q0 + q_dot[0] * dt, q1 + q_dot[1] * dt, q2 + q_dot[2] * dt, q3 + q_dot[3] * dt];
+    let inv = inv_sqrt(q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]);
+    q.map(|c| c * inv)
+}
+
+// The micromath square roots are bit tricks accurate to a few percent, which is too coarse for
+// the pitch and for keeping the quaternion normalized. One Newton step brings them to 0.1 %.
+
+fn sqrt(v: f32) -> f32 {
+    if v <= 0.0 {
+        return 0.0;
+    }
+    let s = F32Ext::sqrt(v);
+    0.5 * (s + v / s)
+}
+
+fn inv_sqrt(v: f32) -> f32 {
+    let i = F32Ext::invsqrt(v);
+    i * (1.5 - 0.5 * v * i * i)
+}
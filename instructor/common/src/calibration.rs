@@ -0,0 +1,440 @@
+//! Six-orientation accelerometer calibration.
+//!
+//! The sensor is held still with each axis pointing up and down in turn, in any order. Gravity
+//! gives a known +1 g and -1 g on every axis, from which the zero-g offset and gain of each axis
+//! follow.
+
+use core::fmt;
+
+use crate::motion::{Axis, Sign};
+use crate::Sample;
+
+/// Number of orientations the sensor has to be held in.
+pub const ORIENTATIONS: usize = 6;
+
+/// Encoded size of a [`Calibration`].
+pub const CALIBRATION_LEN: usize = 24;
+
+/// Largest spread, in g, of each axis over a window for the sensor to count as still.
+const STILL_TOLERANCE: f32 = 0.05;
+/// Smallest reading, in g, of the axis pointing up or down.
+const VERTICAL_MIN: f32 = 0.7;
+/// Largest reading, in g, of the other two axes.
+const LEVEL_MAX: f32 = 0.35;
+/// Range of the measured difference between +1 g and -1 g for the result to be plausible.
+const SPAN_MIN: f32 = 1.6;
+const SPAN_MAX: f32 = 2.4;
+/// Largest plausible zero-g offset, in g.
+const OFFSET_MAX: f32 = 0.3;
+
+/// Per-axis correction applied to raw accelerometer readings, as `(raw - offset) * scale`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Calibration {
+    /// Zero-g offset of x, y and z, in g.
+    pub offset: [f32; 3],
+    /// Gain correction of x, y and z.
+    pub scale: [f32; 3],
+}
+
+impl Calibration {
+    /// Leaves samples unchanged.
+    pub const IDENTITY: Calibration = Calibration { offset: [0.0; 3], scale: [1.0; 3] };
+
+    /// Correct the acceleration of a sample. The angular rate is passed through.
+    pub fn apply(&self, sample: Sample) -> Sample {
+        Sample {
+            x: (sample.x - self.offset[0]) * self.scale[0],
+            y: (sample.y - self.offset[1]) * self.scale[1],
+            z: (sample.z - self.offset[2]) * self.scale[2],
+            gyro: sample.gyro,
+        }
+    }
+
+    /// Little-endian offsets followed by scales.
+    pub fn to_bytes(&self) -> [u8; CALIBRATION_LEN] {
+        let mut buf = [0; CALIBRATION_LEN];
+        for (chunk, value) in buf.chunks_exact_mut(4).zip(self.offset.iter().chain(&self.scale)) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8; CALIBRATION_LEN]) -> Self {
+        let mut values = buf.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]));
+        let mut next = || values.next().unwrap_or_default();
+        Self {
+            offset: [next(), next(), next()],
+            scale: [next(), next(), next()],
+        }
+    }
+
+    /// Whether every value is finite and within the range a working sensor can produce.
+    pub fn is_plausible(&self) -> bool {
+        self.offset.iter().all(|o| o.is_finite() && o.abs() <= OFFSET_MAX)
+            && self.scale.iter().all(|s| s.is_finite() && (2.0 / SPAN_MAX..=2.0 / SPAN_MIN).contains(s))
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Index of an orientation in the bit mask of [`CalibrationStatus::InProgress`].
+pub fn orientation_index(axis: Axis, sign: Sign) -> usize {
+    let axis = match axis {
+        Axis::X => 0,
+        Axis::Y => 1,
+        Axis::Z => 2,
+    };
+    axis * 2 + matches!(sign, Sign::Negative) as usize
+}
+
+/// The axis pointing up or down in the orientation with this index.
+pub fn orientation(index: usize) -> (Axis, Sign) {
+    let axis = match index / 2 {
+        0 => Axis::X,
+        1 => Axis::Y,
+        _ => Axis::Z,
+    };
+    let sign = if index.is_multiple_of(2) { Sign::Positive } else { Sign::Negative };
+    (axis, sign)
+}
+
+/// Progress of a calibration, as reported to the viewer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CalibrationStatus {
+    /// Waiting for the sensor to be held still in the orientations whose bit is not set in
+    /// `captured`, see [`orientation_index`].
+    InProgress { captured: u8 },
+    /// All orientations were captured and the result is in use.
+    Done(Calibration),
+    /// The measurements were implausible or the calibration was cancelled. The previous
+    /// calibration is still in use.
+    Failed,
+}
+
+impl CalibrationStatus {
+    /// The first orientation still missing, if the calibration is in progress.
+    pub fn next_orientation(&self) -> Option<(Axis, Sign)> {
+        match self {
+            CalibrationStatus::InProgress { captured } => {
+                (0..ORIENTATIONS).find(|i| captured & (1 << i) == 0).map(orientation)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for CalibrationStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self, self.next_orientation()) {
+            (CalibrationStatus::InProgress { captured }, Some((axis, sign))) => write!(
+                f,
+                "calibrating {}/{}: hold {}{:?} up",
+                captured.count_ones(),
+                ORIENTATIONS,
+                sign,
+                axis
+            ),
+            (CalibrationStatus::InProgress { .. }, None) => f.write_str("calibrating"),
+            (CalibrationStatus::Done(_), _) => f.write_str("calibrated"),
+            (CalibrationStatus::Failed, _) => f.write_str("calibration failed"),
+        }
+    }
+}
+
+/// Collects stationary readings in the six orientations and computes the calibration.
+///
+/// Samples are taken in windows of a fixed length. A window in which every axis stays within a
+/// small band, with one axis close to vertical, is captured as the reading of that orientation.
+pub struct Calibrator {
+    window: u32,
+    count: u32,
+    sum: [f32; 3],
+    min: [f32; 3],
+    max: [f32; 3],
+    /// Mean reading in each orientation, by [`orientation_index`].
+    readings: [Option<[f32; 3]>; ORIENTATIONS],
+}
+
+impl Calibrator {
+    /// `window` is the number of consecutive samples the sensor has to be still for, a few
+    /// hundred milliseconds worth is enough.
+    pub fn new(window: u32) -> Self {
+        Self {
+            window: window.max(1),
+            count: 0,
+            sum: [0.0; 3],
+            min: [f32::MAX; 3],
+            max: [f32::MIN; 3],
+            readings: [None; ORIENTATIONS],
+        }
+    }
+
+    /// The orientations captured so far, as a bit mask.
+    pub fn captured(&self) -> u8 {
+        self.readings
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.is_some())
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+
+    /// Add an uncalibrated sample. Returns the new status when an orientation was captured, and
+    /// the result once all of them were.
+    pub fn feed(&mut self, sample: Sample) -> Option<CalibrationStatus> {
+        let values = [sample.x, sample.y, sample.z];
+        for (axis, value) in values.into_iter().enumerate() {
+            self.sum[axis] += value;
+            self.min[axis] = self.min[axis].min(value);
+            self.max[axis] = self.max[axis].max(value);
+        }
+        self.count += 1;
+        if self.count < self.window {
+            return None;
+        }
+
+        let still = (0..3).all(|axis| self.max[axis] - self.min[axis] <= STILL_TOLERANCE);
+        let mean = self.sum.map(|sum| sum / self.count as f32);
+        self.count = 0;
+        self.sum = [0.0; 3];
+        self.min = [f32::MAX; 3];
+        self.max = [f32::MIN; 3];
+        if !still {
+            return None;
+        }
+
+        let index = vertical_orientation(&mean)?;
+        if self.readings[index].is_some() {
+            return None;
+        }
+        self.readings[index] = Some(mean);
+
+        if self.readings.iter().all(Option::is_some) {
+            Some(match self.result() {
+                Some(calibration) => CalibrationStatus::Done(calibration),
+                None => CalibrationStatus::Failed,
+            })
+        } else {
+            Some(CalibrationStatus::InProgress { captured: self.captured() })
+        }
+    }
+
+    /// Offset and scale from the readings, if all were captured and the result is plausible.
+    fn result(&self) -> Option<Calibration> {
+        let mut calibration = Calibration::IDENTITY;
+        for axis in 0..3 {
+            let up = self.readings[axis * 2]?[axis];
+            let down = self.readings[axis * 2 + 1]?[axis];
+            calibration.offset[axis] = (up + down) / 2.0;
+            calibration.scale[axis] = 2.0 / (up - down);
+        }
+        calibration.is_plausible().then_some(calibration)
+    }
+}
+
+/// The orientation with one axis close to vertical and the other two close to level.
+fn vertical_orientation(mean: &[f32; 3]) -> Option<usize> {
+    let axis = (0..3).find(|&axis| mean[axis].abs() >= VERTICAL_MIN)?;
+    if (0..3).any(|other| other != axis && mean[other].abs() > LEVEL_MAX) {
+        return None;
+    }
+    Some(axis * 2 + (mean[axis] < 0.0) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u32 = 10;
+
+    /// A sensor with its own offset and gain on each axis.
+    struct Sensor {
+        offset: [f32; 3],
+        gain: [f32; 3],
+    }
+
+    impl Sensor {
+        const SKEWED: Sensor = Sensor { offset: [0.05, -0.1, 0.02], gain: [1.1, 0.95, 1.02] };
+
+        /// What the sensor reads when the acceleration is `g`.
+        fn read(&self, g: [f32; 3]) -> Sample {
+            let [x, y, z] = [0, 1, 2].map(|axis| g[axis] * self.gain[axis] + self.offset[axis]);
+            Sample::new(x, y, z)
+        }
+    }
+
+    /// Gravity in the orientation with `index`, see [`orientation_index`].
+    fn gravity(index: usize) -> [f32; 3] {
+        let mut g = [0.0; 3];
+        g[index / 2] = if index.is_multiple_of(2) { 1.0 } else { -1.0 };
+        g
+    }
+
+    /// Feed one window of the sensor held still in the orientation with `index`.
+    fn hold(calibrator: &mut Calibrator, sensor: &Sensor, index: usize) -> Option<CalibrationStatus> {
+        let mut status = None;
+        for i in 0..WINDOW {
+            // Noise well within the still tolerance
+            let noise = if i % 2 == 0 { 0.01 } else { -0.01 };
+            let g = gravity(index).map(|g| g + noise);
+            status = status.or(calibrator.feed(sensor.read(g)));
+        }
+        status
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn vertical_orientations() {
+        assert_eq!(vertical_orientation(&[1.0, 0.0, 0.0]), Some(0));
+        assert_eq!(vertical_orientation(&[-0.9, 0.1, -0.2]), Some(1));
+        assert_eq!(vertical_orientation(&[0.1, 0.8, 0.3]), Some(2));
+        assert_eq!(vertical_orientation(&[0.0, -1.1, 0.0]), Some(3));
+        assert_eq!(vertical_orientation(&[0.0, 0.0, 0.7]), Some(4));
+        assert_eq!(vertical_orientation(&[0.2, -0.2, -1.0]), Some(5));
+        for index in 0..ORIENTATIONS {
+            let (axis, sign) = orientation(index);
+            assert_eq!(orientation_index(axis, sign), index);
+        }
+    }
+
+    #[test]
+    fn tilted_orientations_are_not_vertical() {
+        // 45° between two axes, and flat readings of a sensor in free fall
+        assert_eq!(vertical_orientation(&[0.71, 0.71, 0.0]), None);
+        assert_eq!(vertical_orientation(&[0.0, 0.4, 0.9]), None);
+        assert_eq!(vertical_orientation(&[0.0, 0.0, 0.0]), None);
+        assert_eq!(vertical_orientation(&[0.6, 0.6, 0.5]), None);
+    }
+
+    #[test]
+    fn still_window_is_captured() {
+        let mut calibrator = Calibrator::new(WINDOW);
+        // Nothing happens before the window is complete
+        for _ in 0..WINDOW - 1 {
+            assert_eq!(calibrator.feed(Sample::new(0.0, 0.0, 1.0)), None);
+        }
+        assert_eq!(calibrator.feed(Sample::new(0.0, 0.0, 1.0)), Some(CalibrationStatus::InProgress { captured: 1 << 4 }));
+        // Holding the same orientation again does not change anything
+        assert_eq!(hold(&mut calibrator, &Sensor::SKEWED, 4), None);
+        assert_eq!(calibrator.captured(), 1 << 4);
+    }
+
+    #[test]
+    fn moving_window_is_rejected() {
+        let mut calibrator = Calibrator::new(WINDOW);
+        for i in 0..WINDOW {
+            // Swinging by 0.1 g, twice the tolerance, around a vertical z
+            let z = if i % 2 == 0 { 0.95 } else { 1.05 };
+            assert_eq!(calibrator.feed(Sample::new(0.0, 0.0, z)), None);
+        }
+        assert_eq!(calibrator.captured(), 0);
+        // The next window starts over
+        assert_eq!(hold(&mut calibrator, &Sensor::SKEWED, 4), Some(CalibrationStatus::InProgress { captured: 1 << 4 }));
+    }
+
+    #[test]
+    fn tilted_window_is_rejected() {
+        let mut calibrator = Calibrator::new(WINDOW);
+        for _ in 0..WINDOW {
+            assert_eq!(calibrator.feed(Sample::new(0.5, 0.0, 0.87)), None);
+        }
+        assert_eq!(calibrator.captured(), 0);
+    }
+
+    #[test]
+    fn six_orientations_give_the_calibration() {
+        let sensor = Sensor::SKEWED;
+        let mut calibrator = Calibrator::new(WINDOW);
+        // In any order, with a moving window in between
+        let order = [5, 0, 3, 2, 1, 4];
+        let mut captured = 0;
+        for (step, index) in order.into_iter().enumerate() {
+            if step == 2 {
+                for i in 0..WINDOW {
+                    assert_eq!(calibrator.feed(sensor.read([0.0, i as f32 * 0.1, 0.5])), None);
+                }
+            }
+            captured |= 1 << index;
+            let status = hold(&mut calibrator, &sensor, index);
+            if step < ORIENTATIONS - 1 {
+                assert_eq!(status, Some(CalibrationStatus::InProgress { captured }));
+            } else {
+                let Some(CalibrationStatus::Done(calibration)) = status else {
+                    panic!("calibration not done: {:?}", status);
+                };
+                for axis in 0..3 {
+                    assert_close(calibration.offset[axis], sensor.offset[axis]);
+                    assert_close(calibration.scale[axis], 1.0 / sensor.gain[axis]);
+                }
+                for index in 0..ORIENTATIONS {
+                    let corrected = calibration.apply(sensor.read(gravity(index)));
+                    let [x, y, z] = gravity(index);
+                    assert_close(corrected.x, x);
+                    assert_close(corrected.y, y);
+                    assert_close(corrected.z, z);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn implausible_readings_fail() {
+        // Too little gain on x, which reads +1 g and -1 g only 1.5 g apart
+        let sensor = Sensor { offset: [0.0; 3], gain: [0.75, 1.0, 1.0] };
+        let mut calibrator = Calibrator::new(WINDOW);
+        let statuses: [_; ORIENTATIONS] = core::array::from_fn(|index| hold(&mut calibrator, &sensor, index));
+        assert_eq!(statuses[ORIENTATIONS - 1], Some(CalibrationStatus::Failed));
+    }
+
+    #[test]
+    fn result_of_readings() {
+        let mut calibrator = Calibrator::new(WINDOW);
+        assert_eq!(calibrator.result(), None);
+        calibrator.readings = [
+            Some([1.1, 0.0, 0.0]),
+            Some([-0.9, 0.0, 0.0]),
+            Some([0.0, 0.95, 0.0]),
+            Some([0.0, -1.05, 0.0]),
+            Some([0.0, 0.0, 1.0]),
+            Some([0.0, 0.0, -1.0]),
+        ];
+        let calibration = calibrator.result().unwrap();
+        for (axis, offset) in [0.1, -0.05, 0.0].into_iter().enumerate() {
+            assert_close(calibration.offset[axis], offset);
+            assert_close(calibration.scale[axis], 1.0);
+        }
+
+        // An offset beyond what a working sensor has
+        calibrator.readings[4] = Some([0.0, 0.0, 1.8]);
+        calibrator.readings[5] = Some([0.0, 0.0, -0.2]);
+        assert_eq!(calibrator.result(), None);
+    }
+
+    #[test]
+    fn plausible_calibrations() {
+        assert!(Calibration::IDENTITY.is_plausible());
+        assert!(Calibration { offset: [0.3, -0.3, 0.0], scale: [2.0 / 2.4, 2.0 / 1.6, 1.0] }.is_plausible());
+        assert!(!Calibration { offset: [0.31, 0.0, 0.0], ..Calibration::IDENTITY }.is_plausible());
+        assert!(!Calibration { scale: [1.0, 0.5, 1.0], ..Calibration::IDENTITY }.is_plausible());
+        assert!(!Calibration { scale: [1.0, 1.0, 1.5], ..Calibration::IDENTITY }.is_plausible());
+        assert!(!Calibration { offset: [f32::NAN, 0.0, 0.0], ..Calibration::IDENTITY }.is_plausible());
+        assert!(!Calibration { scale: [f32::INFINITY, 1.0, 1.0], ..Calibration::IDENTITY }.is_plausible());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let calibration = Calibration { offset: [0.05, -0.1, 0.02], scale: [0.91, 1.05, 0.98] };
+        assert_eq!(Calibration::from_bytes(&calibration.to_bytes()), calibration);
+        assert_eq!(Calibration::from_bytes(&Calibration::IDENTITY.to_bytes()), Calibration::IDENTITY);
+        // Flash that was never written reads as all ones, which is NaN and so not plausible
+        assert!(!Calibration::from_bytes(&[0xFF; CALIBRATION_LEN]).is_plausible());
+    }
+}
@@ -0,0 +1,235 @@
+//! Compact encoding of [`RawSample`] batches.
+//!
+//! Consecutive samples of a sensor differ by little more than its noise, so each axis is sent as
+//! the difference to the previous sample. The first sample is sent as the difference to zero.
+//! Differences are zig-zag encoded, which maps small negative and positive values to small
+//! unsigned ones, and then written as varints of 7 bits per byte, least significant first.
+//!
+//! Sensors with less than 16 bits of resolution leave the low bits of their counts zero. The
+//! payload starts with the number of low bits that are zero in every count, which are dropped.
+//!
+//! A still sensor takes 3 to 6 bytes per sample instead of the 6 of
+//! [`crate::protocol::Frame::RawSamples`]. Large jumps take up to 9, so senders should compare
+//! [`encoded_len`] against the plain encoding.
+
+use heapless::Vec;
+
+use crate::raw::RawSample;
+
+/// Longest encoding of one axis.
+pub const MAX_AXIS_LEN: usize = 3;
+
+/// Longest encoding of one sample.
+pub const MAX_SAMPLE_LEN: usize = 3 * MAX_AXIS_LEN;
+
+/// Why a payload could not be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The output buffer cannot hold the encoded samples.
+    BufferTooSmall,
+    /// The payload ends in the middle of a sample.
+    Truncated,
+    /// A varint does not fit an axis.
+    Overflow,
+    /// More samples than the output can hold.
+    TooManySamples,
+}
+
+/// Length of the encoding of `samples`.
+pub fn encoded_len(samples: &[RawSample]) -> usize {
+    let shift = shift(samples);
+    let mut previous = RawSample::default();
+    let mut len = 1;
+    for sample in samples {
+        for (value, base) in axes(sample).into_iter().zip(axes(&previous)) {
+            len += varint_len(zigzag((value >> shift).wrapping_sub(base >> shift)));
+        }
+        previous = *sample;
+    }
+    len
+}
+
+/// Encode `samples` into `buf`, returning the number of bytes written.
+pub fn encode(samples: &[RawSample], buf: &mut [u8]) -> Result<usize, Error> {
+    let shift = shift(samples);
+    *buf.first_mut().ok_or(Error::BufferTooSmall)? = shift as u8;
+    let mut previous = RawSample::default();
+    let mut len = 1;
+    for sample in samples {
+        for (value, base) in axes(sample).into_iter().zip(axes(&previous)) {
+            let mut v = zigzag((value >> shift).wrapping_sub(base >> shift));
+            loop {
+                let byte = buf.get_mut(len).ok_or(Error::BufferTooSmall)?;
+                len += 1;
+                if v < 0x80 {
+                    *byte = v as u8;
+                    break;
+                }
+                *byte = (v as u8 & 0x7F) | 0x80;
+                v >>= 7;
+            }
+        }
+        previous = *sample;
+    }
+    Ok(len)
+}
+
+/// Decode a payload written by [`encode`].
+pub fn decode<const N: usize>(payload: &[u8]) -> Result<Vec<RawSample, N>, Error> {
+    let Some((&shift, mut payload)) = payload.split_first() else {
+        return Err(Error::Truncated);
+    };
+    if shift > 15 {
+        return Err(Error::Overflow);
+    }
+    let mut samples = Vec::new();
+    // The previous sample with the dropped bits shifted out
+    let mut previous = [0i16; 3];
+    while !payload.is_empty() {
+        let mut next = || -> Result<u16, Error> {
+            let mut value = 0u32;
+            for (i, &byte) in payload.iter().enumerate().take(MAX_AXIS_LEN) {
+                value |= ((byte & 0x7F) as u32) << (7 * i);
+                if byte & 0x80 == 0 {
+                    payload = &payload[i + 1..];
+                    return u16::try_from(value).map_err(|_| Error::Overflow);
+                }
+            }
+            Err(if payload.len() < MAX_AXIS_LEN { Error::Truncated } else { Error::Overflow })
+        };
+        for axis in &mut previous {
+            *axis = axis.wrapping_add(unzigzag(next()?));
+        }
+        let [x, y, z] = previous.map(|v| v << shift);
+        samples.push(RawSample { x, y, z }).map_err(|_| Error::TooManySamples)?;
+    }
+    Ok(samples)
+}
+
+/// Number of low bits that are zero in every count.
+fn shift(samples: &[RawSample]) -> u32 {
+    let bits = samples.iter().fold(0, |bits, s| bits | s.x | s.y | s.z);
+    bits.trailing_zeros().min(15)
+}
+
+fn axes(sample: &RawSample) -> [i16; 3] {
+    [sample.x, sample.y, sample.z]
+}
+
+fn zigzag(v: i16) -> u16 {
+    ((v << 1) ^ (v >> 15)) as u16
+}
+
+fn unzigzag(v: u16) -> i16 {
+    ((v >> 1) as i16) ^ -((v & 1) as i16)
+}
+
+fn varint_len(v: u16) -> usize {
+    match v {
+        0..0x80 => 1,
+        0x80..0x4000 => 2,
+        _ => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUF_LEN: usize = 1 + 64 * MAX_SAMPLE_LEN;
+
+    fn sample(x: i16, y: i16, z: i16) -> RawSample {
+        RawSample { x, y, z }
+    }
+
+    /// Encode and decode `samples`, checking the length against [`encoded_len`]. Returns the
+    /// encoding.
+    fn round_trip(samples: &[RawSample]) -> Vec<u8, BUF_LEN> {
+        let mut buf = [0; BUF_LEN];
+        let len = encode(samples, &mut buf).unwrap();
+        assert_eq!(len, encoded_len(samples));
+        let decoded: Vec<RawSample, 64> = decode(&buf[..len]).unwrap();
+        assert_eq!(&decoded[..], samples);
+        buf[..len].iter().copied().collect()
+    }
+
+    #[test]
+    fn extreme_jumps_round_trip() {
+        let (min, max) = (i16::MIN, i16::MAX);
+        let samples = [
+            sample(min, max, 0),
+            sample(max, min, -1),
+            sample(min, max, 1),
+            sample(0, 0, max),
+            sample(max, max, min),
+            sample(min, min, min),
+        ];
+        let encoded = round_trip(&samples);
+        assert_eq!(encoded[0], 0);
+        assert!(encoded.len() <= 1 + samples.len() * MAX_SAMPLE_LEN);
+    }
+
+    #[test]
+    fn every_shift_round_trips() {
+        for shift in 0..16 {
+            let step = 1i16.wrapping_shl(shift);
+            let top = (i16::MAX >> shift) << shift;
+            let samples = [
+                sample(step, 0, step.wrapping_neg()),
+                sample(i16::MIN, top, step),
+                sample(top, i16::MIN, 0),
+                sample(step.wrapping_mul(3), step.wrapping_mul(-5), step),
+            ];
+            assert_eq!(round_trip(&samples)[0], shift as u8, "shift {}", shift);
+        }
+    }
+
+    #[test]
+    fn still_samples_are_short() {
+        let samples = [sample(16, -32, 16368), sample(32, -32, 16384), sample(16, -16, 16368)];
+        let encoded = round_trip(&samples);
+        // The first sample is a jump from zero, the others one byte per axis
+        assert_eq!(encoded.len(), encoded_len(&samples[..1]) + 2 * 3);
+    }
+
+    #[test]
+    fn empty_batch() {
+        assert_eq!(&round_trip(&[])[..], &[15]);
+    }
+
+    #[test]
+    fn truncated_payloads_are_rejected() {
+        let cases: [&[u8]; 4] = [&[], &[0, 1], &[0, 1, 2], &[0, 1, 2, 0x80]];
+        for payload in cases {
+            assert_eq!(decode::<4>(payload), Err(Error::Truncated), "{:?}", payload);
+        }
+    }
+
+    #[test]
+    fn overflows_are_rejected() {
+        let cases: [&[u8]; 3] = [&[16], &[0, 0xFF, 0xFF, 0xFF, 0], &[0, 0xFF, 0xFF, 0x7F, 0, 0]];
+        for payload in cases {
+            assert_eq!(decode::<4>(payload), Err(Error::Overflow), "{:?}", payload);
+        }
+    }
+
+    #[test]
+    fn too_many_samples_are_rejected() {
+        let samples = [sample(1, 2, 3); 3];
+        let mut buf = [0; BUF_LEN];
+        let len = encode(&samples, &mut buf).unwrap();
+        assert_eq!(decode::<2>(&buf[..len]), Err(Error::TooManySamples));
+        assert_eq!(decode::<3>(&buf[..len]).map(|s| s.len()), Ok(3));
+    }
+
+    #[test]
+    fn short_buffers_are_reported() {
+        let samples = [sample(i16::MIN, 0, 1), sample(i16::MAX, 5, -1)];
+        let mut buf = [0; BUF_LEN];
+        let len = encode(&samples, &mut buf).unwrap();
+        for short in 0..len {
+            assert_eq!(encode(&samples, &mut buf[..short]), Err(Error::BufferTooSmall), "{} bytes", short);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Filters smoothing a stream of samples.
+
+use crate::Sample;
+
+/// Something that smooths a stream of samples, one sample at a time.
+pub trait Filter {
+    /// Filter the next sample, returning the smoothed one.
+    fn apply(&mut self, sample: Sample) -> Sample;
+
+    /// Filter consecutive samples in place.
+    fn apply_n(&mut self, samples: &mut [Sample]) {
+        for sample in samples {
+            *sample = self.apply(*sample);
+        }
+    }
+
+    /// Forget the previous samples, the next sample initializes the filter again.
+    fn reset(&mut self);
+}
+
+/// Exponential moving average over the acceleration. The angular rate is passed through.
+pub struct LowpassFilter {
+    filter_state: Option<Sample>,
+    alpha: f32,
+}
+
+impl LowpassFilter {
+    /// `alpha` is the weight of each new sample, lower values are smoother but add more delay.
+    pub fn new(alpha: f32) -> Self {
+        Self {
+            filter_state: None,
+            alpha,
+        }
+    }
+}
+
+impl Filter for LowpassFilter {
+    fn apply(&mut self, raw_sample: Sample) -> Sample {
+        match self.filter_state {
+            None => {
+                // First sample, initialize filter state
+                self.filter_state = Some(raw_sample);
+                raw_sample
+            }
+            Some(prev) => {
+                // Apply exponential moving average: filtered = alpha * new + (1 - alpha) * prev
+                let filtered = Sample {
+                    x: self.alpha * raw_sample.x + (1.0 - self.alpha) * prev.x,
+                    y: self.alpha * raw_sample.y + (1.0 - self.alpha) * prev.y,
+                    z: self.alpha * raw_sample.z + (1.0 - self.alpha) * prev.z,
+                    gyro: raw_sample.gyro,
+                };
+                self.filter_state = Some(filtered);
+                filtered
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.filter_state = None;
+    }
+}
+
+/// The parameters passed to [`Biquad::new`] do not give a stable low-pass filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidBiquad;
+
+/// Second order low-pass over the acceleration, steeper than [`LowpassFilter`] for the same
+/// delay. The angular rate is passed through.
+///
+/// The math follows the C biquad in `lowpass_filter_sys` step for step, so both give the same
+/// output.
+pub struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    // Transposed direct form II state of x, y and z
+    state: Option<[[f32; 2]; 3]>,
+}
+
+impl Biquad {
+    /// A low-pass with the given `cutoff` in Hz, below half the `sample_rate`. A `q` of 0.7071
+    /// gives the flattest pass band.
+    pub fn new(sample_rate: f32, cutoff: f32, q: f32) -> Result<Self, InvalidBiquad> {
+        // Written so that NaN fails the checks
+        if !(sample_rate > 0.0 && cutoff > 0.0 && cutoff < sample_rate / 2.0 && q > 0.0) {
+            return Err(InvalidBiquad);
+        }
+
+        // Low-pass from the Audio EQ Cookbook, w0 is within (0, pi)
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let sin_w0 = sin_poly(if w0 < PI / 2.0 { w0 } else { PI - w0 });
+        let cos_w0 = sin_poly(PI / 2.0 - w0);
+        let alpha = sin_w0 / (2.0 * q);
+        let a0 = 1.0 + alpha;
+
+        let b0 = (1.0 - cos_w0) / 2.0 / a0;
+        Ok(Self {
+            b: [b0, (1.0 - cos_w0) / a0, b0],
+            a: [-2.0 * cos_w0 / a0, (1.0 - alpha) / a0],
+            state: None,
+        })
+    }
+}
+
+impl Filter for Biquad {
+    fn apply(&mut self, raw_sample: Sample) -> Sample {
+        let [b0, b1, b2] = self.b;
+        let [a1, a2] = self.a;
+        let input = [raw_sample.x, raw_sample.y, raw_sample.z];
+        // Start as if the first sample had always been the input, the gain at 0 Hz is 1
+        let state = self
+            .state
+            .get_or_insert_with(|| input.map(|x| [(1.0 - b0) * x, (b2 - a2) * x]));
+
+        let mut output = [0.0; 3];
+        for ((y, x), [s1, s2]) in output.iter_mut().zip(input).zip(state) {
+            *y = b0 * x + *s1;
+            *s1 = b1 * x - a1 * *y + *s2;
+            *s2 = b2 * x - a2 * *y;
+        }
+        let [x, y, z] = output;
+        Sample { gyro: raw_sample.gyro, ..Sample::new(x, y, z) }
+    }
+
+    fn reset(&mut self) {
+        self.state = None;
+    }
+}
+
+const PI: f32 = core::f32::consts::PI;
+
+/// sin(x) for x in [-pi/2, pi/2], the same series as the C library.
+fn sin_poly(x: f32) -> f32 {
+    let x2 = x * x;
+    x * (1.0 - x2 / 6.0 * (1.0 - x2 / 20.0 * (1.0 - x2 / 42.0 * (1.0 - x2 / 72.0 * (1.0 - x2 / 110.0)))))
+}
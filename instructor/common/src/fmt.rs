@@ -0,0 +1,49 @@
+//! Logging from the code the firmware and the simulator share, through `defmt` on the device
+//! and `log` on the host. Without either feature nothing is logged.
+//!
+//! Only use `{}` for integers and `{:?}` for everything else, which both accept.
+
+#![allow(unused_macros)]
+
+/// What can be logged with `{:?}`.
+#[cfg(feature = "defmt")]
+pub trait Loggable: defmt::Format {}
+#[cfg(feature = "defmt")]
+impl<T: defmt::Format> Loggable for T {}
+
+/// What can be logged with `{:?}`.
+#[cfg(not(feature = "defmt"))]
+pub trait Loggable: core::fmt::Debug {}
+#[cfg(not(feature = "defmt"))]
+impl<T: core::fmt::Debug> Loggable for T {}
+
+macro_rules! log {
+    ($level:ident, $($arg:tt)*) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::$level!($($arg)*);
+        #[cfg(all(feature = "log", not(feature = "defmt")))]
+        ::log::$level!($($arg)*);
+        #[cfg(not(any(feature = "defmt", feature = "log")))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => { log!(trace, $($arg)*) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => { log!(debug, $($arg)*) };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => { log!(info, $($arg)*) };
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => { log!(warn, $($arg)*) };
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => { log!(error, $($arg)*) };
+}
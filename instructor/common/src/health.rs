@@ -0,0 +1,71 @@
+//! Device health reported to the viewer.
+
+use crate::Sample;
+
+/// Expected value of the LIS3DH WHO_AM_I register.
+pub const LIS3DH_WHO_AM_I: u8 = 0x33;
+
+/// Smallest output change, in g, the LIS3DH self-test must cause. The datasheet specifies
+/// 17 to 360 LSb at 4 mg/LSb.
+pub const SELF_TEST_MIN: f32 = 0.068;
+/// Largest output change, in g, the LIS3DH self-test may cause.
+pub const SELF_TEST_MAX: f32 = 1.44;
+
+/// Expected value of the LSM6DSO WHO_AM_I register.
+pub const LSM6DSO_WHO_AM_I: u8 = 0x6C;
+
+/// Smallest accelerometer output change, in g, the LSM6DSO self-test must cause.
+pub const LSM6DSO_SELF_TEST_MIN: f32 = 0.05;
+/// Largest accelerometer output change, in g, the LSM6DSO self-test may cause.
+pub const LSM6DSO_SELF_TEST_MAX: f32 = 1.7;
+
+/// Outcome of the boot-time accelerometer self-test.
+///
+/// Sensors have two self-test modes deflecting the sensing element in opposite directions, which
+/// are self-test 0 and 1 on the LIS3DH and the positive and negative self-test on the LSM6DSO.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SelfTest {
+    /// Identifies the sensor, and so the limits the result is checked against.
+    pub who_am_i: u8,
+    /// Per-axis output change with the first self-test mode enabled, in g.
+    pub st0: Sample,
+    /// Per-axis output change with the second self-test mode enabled, in g.
+    pub st1: Sample,
+}
+
+impl SelfTest {
+    /// Whether each of the x, y and z deltas is within the datasheet limits. Always false for
+    /// unknown sensors.
+    pub fn axes_within_limits(&self, delta: &Sample) -> [bool; 3] {
+        let limits = match self.who_am_i {
+            LIS3DH_WHO_AM_I => SELF_TEST_MIN..=SELF_TEST_MAX,
+            LSM6DSO_WHO_AM_I => LSM6DSO_SELF_TEST_MIN..=LSM6DSO_SELF_TEST_MAX,
+            _ => return [false; 3],
+        };
+        [delta.x, delta.y, delta.z].map(|d| limits.contains(&d.abs()))
+    }
+
+    pub fn passed(&self) -> bool {
+        self.axes_within_limits(&self.st0).iter().all(|ok| *ok)
+            && self.axes_within_limits(&self.st1).iter().all(|ok| *ok)
+    }
+}
+
+/// Whether the accelerometer is currently delivering samples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SensorStatus {
+    Ok,
+    /// The sensor stopped responding and the device is recovering the bus and sensor.
+    Recovering { attempt: u8 },
+    /// Recovery failed, the device keeps retrying at a slower pace.
+    Fault,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Health {
+    pub self_test: SelfTest,
+    pub status: SensorStatus,
+}
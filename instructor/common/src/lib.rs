@@ -0,0 +1,48 @@
+//! Types shared between the workshop firmware, the simulator and the viewer backend.
+#![no_std]
+
+#[macro_use]
+mod fmt;
+
+pub mod attitude;
+pub mod calibration;
+pub mod delta;
+pub mod filter;
+pub mod health;
+pub mod link;
+#[cfg(feature = "registers")]
+pub mod lis3dh;
+pub mod motion;
+pub mod protocol;
+pub mod raw;
+#[cfg(feature = "registers")]
+pub mod registers;
+pub mod sensor;
+
+pub use fmt::Loggable;
+
+/// One accelerometer reading, in g, with the angular rate if the sensor has a gyroscope.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sample {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub gyro: Option<AngularRate>,
+}
+
+impl Sample {
+    /// An accelerometer-only sample.
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z, gyro: None }
+    }
+}
+
+/// One gyroscope reading, in degrees per second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AngularRate {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
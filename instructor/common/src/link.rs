@@ -0,0 +1,279 @@
+//! The device side of a connection to the viewer, shared by the firmware and the simulator.
+//!
+//! A connection starts with [`crate::protocol::MAGIC`], followed by the frames the viewer has to
+//! be told again on every connection, kept in a [`Cache`]. After that the device sends the
+//! frames of its sensors through an [`Encoder`], and reassembles the commands of the viewer with
+//! a [`Receiver`]. [`forward`] does all of that over any [`Connection`].
+
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+
+use heapless::Vec;
+
+use crate::delta;
+use crate::health::Health;
+use crate::protocol::{self, Error, Frame, Header, HEADER_LEN, MAX_FRAME_LEN, MAX_SAMPLES, RAW_SAMPLE_LEN};
+use crate::raw::{RawFormat, RawSample};
+use crate::sensor::Message;
+use crate::Loggable;
+
+/// Longest output of [`Encoder::encode`], a frame preceded by a `Sensor` frame.
+pub const MAX_ENCODED_LEN: usize = HEADER_LEN + 1 + MAX_FRAME_LEN;
+
+/// What the viewer has to be told again on every new connection, by sensor.
+pub struct Cache<const SENSORS: usize> {
+    /// Latest health report.
+    health: [Option<Health>; SENSORS],
+    /// Format of the raw samples, without which the viewer cannot scale them.
+    format: [Option<RawFormat>; SENSORS],
+}
+
+impl<const SENSORS: usize> Default for Cache<SENSORS> {
+    fn default() -> Self {
+        Self {
+            health: [None; SENSORS],
+            format: [None; SENSORS],
+        }
+    }
+}
+
+impl<const SENSORS: usize> Cache<SENSORS> {
+    /// Remember `frame` of `sensor`, if it is one the viewer has to be told again.
+    pub fn update(&mut self, sensor: u8, frame: &Frame) {
+        let sensor = sensor as usize;
+        match frame {
+            Frame::Health(health) if sensor < SENSORS => self.health[sensor] = Some(*health),
+            Frame::RawFormat(format) if sensor < SENSORS => self.format[sensor] = Some(*format),
+            _ => {}
+        }
+    }
+
+    /// The remembered frames with their sensor, to send after the magic of a new connection.
+    pub fn frames(&self) -> impl Iterator<Item = (u8, Frame)> + '_ {
+        self.health.iter().zip(&self.format).enumerate().flat_map(|(sensor, (health, format))| {
+            [health.map(Frame::Health), format.map(Frame::RawFormat)]
+                .into_iter()
+                .flatten()
+                .map(move |frame| (sensor as u8, frame))
+        })
+    }
+}
+
+/// Encodes the frames sent to the viewer, telling it which sensor they are from.
+pub struct Encoder {
+    /// Sensor the viewer attributes frames to.
+    current: u8,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    /// For a new connection, on which frames are from sensor 0 until told otherwise.
+    pub const fn new() -> Self {
+        Self { current: 0 }
+    }
+
+    /// Encode `frame` of `sensor`, preceded by a `Sensor` frame unless the viewer already
+    /// attributes frames to `sensor`. Returns the number of bytes written, at most
+    /// [`MAX_ENCODED_LEN`].
+    pub fn encode(&mut self, sensor: u8, frame: &Frame, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut len = 0;
+        if sensor != self.current {
+            len = Frame::Sensor(sensor).encode(buf)?;
+        }
+        len += frame.encode(&mut buf[len..])?;
+        self.current = sensor;
+        Ok(len)
+    }
+}
+
+/// The frame for raw `samples`, delta encoded if `compressed` and that makes it smaller. Fast
+/// motion can make the deltas larger than the samples themselves.
+pub fn raw_samples(samples: Vec<RawSample, MAX_SAMPLES>, compressed: bool) -> Frame {
+    if compressed && delta::encoded_len(&samples) < samples.len() * RAW_SAMPLE_LEN {
+        Frame::DeltaSamples(samples)
+    } else {
+        Frame::RawSamples(samples)
+    }
+}
+
+/// Frames sent by the viewer, possibly split across reads. `N` bounds the length of a frame.
+pub struct Receiver<const N: usize> {
+    buf: [u8; N],
+    /// Start of the first frame not returned yet.
+    start: usize,
+    /// End of the bytes received.
+    len: usize,
+}
+
+impl<const N: usize> Default for Receiver<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Receiver<N> {
+    pub const fn new() -> Self {
+        Self { buf: [0; N], start: 0, len: 0 }
+    }
+
+    /// Where to read the next bytes into, followed by [`Receiver::received`].
+    pub fn buffer(&mut self) -> &mut [u8] {
+        self.buf.copy_within(self.start..self.len, 0);
+        self.len -= self.start;
+        self.start = 0;
+        &mut self.buf[self.len..]
+    }
+
+    /// Account for `n` bytes read into [`Receiver::buffer`].
+    pub fn received(&mut self, n: usize) {
+        self.len = (self.len + n).min(N);
+    }
+
+    /// The next complete frame, or `None` until more bytes are received. Frames that do not
+    /// decode are skipped after returning the error. A frame longer than `N` can never complete,
+    /// so it is reported as [`Error::InvalidLength`] and everything received is dropped.
+    pub fn next_frame(&mut self) -> Option<Result<Frame, Error>> {
+        let header = Header::decode(*self.buf[self.start..self.len].first_chunk::<HEADER_LEN>()?);
+        let end = self.start + HEADER_LEN + header.len as usize;
+        if end - self.start > N {
+            self.start = 0;
+            self.len = 0;
+            return Some(Err(Error::InvalidLength));
+        }
+        if end > self.len {
+            return None;
+        }
+        let frame = Frame::decode(header.tag, &self.buf[self.start + HEADER_LEN..end]);
+        self.start = end;
+        Some(frame)
+    }
+}
+
+/// A connection to the viewer.
+// Only used by single-threaded executors, so the futures do not have to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait Connection {
+    type Error: Loggable;
+
+    /// Read what the viewer sent into `buf`, returning 0 once it closed the connection.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+
+    async fn write_all(&mut self, buf: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// The sensor tasks, as seen from the connection.
+#[allow(async_fn_in_trait)]
+pub trait Sensors {
+    /// Wait for the next message of any sensor.
+    async fn receive(&mut self) -> Message;
+
+    /// Start calibrating all sensors, or cancel the calibrations in progress.
+    fn calibrate(&mut self);
+}
+
+/// Why [`forward`] stopped.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ForwardError<E> {
+    Connection(E),
+    /// The viewer closed the connection.
+    Closed,
+}
+
+// Frames sent by the viewer are short
+type Commands = Receiver<64>;
+
+/// Forward the messages of the sensors over a new connection to the viewer, and act on the
+/// commands it sends, until the connection fails. `cache` is kept across connections.
+pub async fn forward<C, S, const SENSORS: usize>(
+    mut conn: C,
+    sensors: &mut S,
+    cache: &mut Cache<SENSORS>,
+) -> Result<(), ForwardError<C::Error>>
+where
+    C: Connection,
+    S: Sensors,
+{
+    conn.write_all(&protocol::MAGIC).await.map_err(ForwardError::Connection)?;
+
+    let mut buf = [0u8; MAX_ENCODED_LEN];
+    let mut encoder = Encoder::new();
+    for (sensor, frame) in cache.frames() {
+        let len = encoder.encode(sensor, &frame, &mut buf).expect("buffer fits any frame");
+        conn.write_all(&buf[..len]).await.map_err(ForwardError::Connection)?;
+    }
+
+    let mut rx = Commands::new();
+    loop {
+        let message = match select(conn.read(rx.buffer()), sensors.receive()).await {
+            Either::First(read) => {
+                let n = read.map_err(ForwardError::Connection)?;
+                if n == 0 {
+                    return Err(ForwardError::Closed);
+                }
+                rx.received(n);
+                handle_commands(&mut rx, sensors);
+                continue;
+            }
+            Either::Second(message) => message,
+        };
+        log_forwarded(&message);
+
+        let sensor = message.sensor();
+        let frame = message.into_frame();
+        cache.update(sensor, &frame);
+        let len = encoder.encode(sensor, &frame, &mut buf).expect("buffer fits any frame");
+        conn.write_all(&buf[..len]).await.map_err(ForwardError::Connection)?;
+    }
+}
+
+/// Act on the complete frames received from the viewer.
+fn handle_commands<S: Sensors>(rx: &mut Commands, sensors: &mut S) {
+    while let Some(frame) = rx.next_frame() {
+        match frame {
+            Ok(Frame::Calibrate) => {
+                info!("Viewer requested calibration");
+                sensors.calibrate();
+            }
+            Ok(frame) => warn!("Unexpected frame from viewer: {:?}", frame),
+            Err(e) => warn!("Invalid frame from viewer: {:?}", e),
+        }
+    }
+}
+
+fn log_forwarded(message: &Message) {
+    match message {
+        Message::Samples { sensor, batch } => {
+            debug!("Forwarding {} samples of xl{} from {:?}", batch.samples.len(), sensor, batch.start);
+        }
+        Message::Motion { sensor, event } => info!("Forwarding xl{} motion event: {:?}", sensor, event),
+        Message::Health { .. } => {}
+        Message::Attitude { sensor, attitude } => trace!("Forwarding xl{} attitude {:?}", sensor, attitude),
+        Message::RawFormat { sensor, format } => info!("Forwarding xl{} raw format: {:?}", sensor, format),
+        Message::RawSamples { sensor, batch, .. } => {
+            debug!("Forwarding {} raw samples of xl{} from {:?}", batch.samples.len(), sensor, batch.start);
+        }
+        Message::Calibration { sensor, status } => info!("Forwarding xl{} calibration status: {:?}", sensor, status),
+    }
+}
+
+enum Either<A, B> {
+    First(A),
+    Second(B),
+}
+
+/// Wait for whichever of `a` and `b` completes first, dropping the other.
+async fn select<A: Future, B: Future>(a: A, b: B) -> Either<A::Output, B::Output> {
+    let (mut a, mut b) = (pin!(a), pin!(b));
+    poll_fn(|cx| match a.as_mut().poll(cx) {
+        Poll::Ready(output) => Poll::Ready(Either::First(output)),
+        Poll::Pending => b.as_mut().poll(cx).map(Either::Second),
+    })
+    .await
+}
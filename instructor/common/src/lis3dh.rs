@@ -0,0 +1,299 @@
+//! LIS3DH configuration, self-test and FIFO reads, over [`Registers`] alone.
+//!
+//! Shared by the firmware and the simulator, so the simulated part is sampled by the same
+//! register sequences as the real one. What the firmware does on top, data-ready sampling and
+//! motion detection, stays next to its driver.
+
+use core::time::Duration;
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::I2c;
+use embedded_hal_async::spi::SpiDevice;
+use heapless::Vec;
+
+use crate::health::SelfTest;
+use crate::raw::RawSample;
+use crate::registers::{I2cRegisters, Registers, SpiRegisters};
+use crate::Sample;
+
+/// Register addresses, see the LIS3DH datasheet section 7.
+pub mod register {
+    pub const WHO_AM_I: u8 = 0x0F;
+    pub const CTRL1: u8 = 0x20;
+    pub const CTRL3: u8 = 0x22;
+    pub const CTRL4: u8 = 0x23;
+    pub const CTRL5: u8 = 0x24;
+    pub const STATUS: u8 = 0x27;
+    pub const OUT_X_L: u8 = 0x28;
+    pub const FIFO_CTRL: u8 = 0x2E;
+    pub const FIFO_SRC: u8 = 0x2F;
+}
+
+/// Number of samples the FIFO can hold.
+pub const FIFO_DEPTH: usize = 32;
+
+/// Significant bits of the output in high resolution mode.
+pub const RESOLUTION: u8 = 12;
+
+/// I2C address with SA0 pulled low, the address with SA0 pulled high is one more.
+pub const ADDRESS: u8 = 0x18;
+// Setting the MSB of the register address enables auto-increment for multi-byte I2C reads.
+const AUTO_INCREMENT: u8 = 0x80;
+// Over SPI, the bit after the read bit enables auto-increment.
+const SPI_AUTO_INCREMENT: u8 = 0x40;
+
+// X, Y and Z enabled in CTRL1, next to the output data rate
+const CTRL1_XYZ_EN: u8 = 0b111;
+const CTRL4_BDU: u8 = 1 << 7;
+const CTRL4_HR: u8 = 1 << 3;
+
+// Register bits used for FIFO streaming, see the LIS3DH datasheet section 8.
+const CTRL5_FIFO_EN: u8 = 1 << 6;
+const FIFO_CTRL_MODE_BYPASS: u8 = 0;
+const CTRL3_I1_ZYXDA: u8 = 1 << 4;
+const CTRL3_I1_WTM: u8 = 1 << 2;
+const FIFO_CTRL_MODE_STREAM: u8 = 0b10 << 6;
+const FIFO_SRC_OVRN: u8 = 1 << 6;
+const FIFO_SRC_FSS_MASK: u8 = 0x1F;
+
+// Self-test configuration from the datasheet: 50 Hz with all axes enabled, and block data
+// update, high resolution and ±2g
+const SELF_TEST_CTRL1: u8 = 0b0100_0111;
+const SELF_TEST_CTRL4: u8 = 0b1000_1000;
+const CTRL4_ST0: u8 = 0b01 << 1;
+const CTRL4_ST1: u8 = 0b10 << 1;
+const STATUS_ZYXDA: u8 = 1 << 3;
+// Number of samples averaged for each self-test step
+const SELF_TEST_SAMPLES: usize = 5;
+
+// Output data rates in high resolution mode by their CTRL1 ODR value, slowest first
+const DATA_RATES: [(u8, f32); 8] = [
+    (1, 1.0),
+    (2, 10.0),
+    (3, 25.0),
+    (4, 50.0),
+    (5, 100.0),
+    (6, 200.0),
+    (7, 400.0),
+    (9, 1344.0),
+];
+
+// Full scales in g by their CTRL4 FS value
+const RANGES: [f32; 4] = [2.0, 4.0, 8.0, 16.0];
+// Sensitivity in high resolution mode by the CTRL4 FS value, in g per 12-bit digit. At ±16 g
+// it is 12 mg rather than 8 mg, so the output reaches 24.576 g.
+const SENSITIVITY: [f32; 4] = [0.001, 0.002, 0.004, 0.012];
+// Step of the click and interrupt thresholds by the CTRL4 FS value, in g. Not the full scale
+// over 128, which at ±16 g would be 125 mg instead of 186 mg.
+const THRESHOLD_STEP: [f32; 4] = [0.016, 0.032, 0.062, 0.186];
+
+/// Register access to a LIS3DH on an I2C bus, `sa0` the level of its SA0 pin.
+pub fn i2c_registers<I: I2c>(i2c: I, sa0: bool) -> I2cRegisters<I> {
+    I2cRegisters::new(i2c, ADDRESS + sa0 as u8, AUTO_INCREMENT)
+}
+
+/// Register access to a LIS3DH on a SPI bus.
+pub fn spi_registers<S: SpiDevice>(spi: S) -> SpiRegisters<S> {
+    SpiRegisters::new(spi, SPI_AUTO_INCREMENT)
+}
+
+/// Output data rate and full scale, in high resolution mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Output {
+    // CTRL1 ODR value
+    odr: u8,
+    // CTRL4 FS value
+    fs: u8,
+}
+
+impl Output {
+    /// The lowest rate of at least `hz`, or the highest rate, and the smallest range of at
+    /// least `g`, or the largest range.
+    pub fn new(hz: f32, g: f32) -> Self {
+        let (odr, _) = DATA_RATES
+            .into_iter()
+            .find(|(_, rate)| *rate >= hz)
+            .unwrap_or(DATA_RATES[DATA_RATES.len() - 1]);
+        let fs = RANGES.iter().position(|range| *range >= g).unwrap_or(RANGES.len() - 1);
+        Self { odr, fs: fs as u8 }
+    }
+
+    /// Output data rate in Hz.
+    pub fn rate(&self) -> f32 {
+        DATA_RATES.iter().find(|(odr, _)| *odr == self.odr).map_or(0.0, |(_, rate)| *rate)
+    }
+
+    /// Time between two samples.
+    pub fn period(&self) -> Duration {
+        Duration::from_micros((1_000_000.0 / self.rate()) as u64)
+    }
+
+    /// Nominal full scale in g.
+    pub fn full_scale(&self) -> f32 {
+        RANGES[self.fs as usize]
+    }
+
+    /// Acceleration in g of one count of the left-justified output.
+    pub fn scale(&self) -> f32 {
+        SENSITIVITY[self.fs as usize] / 16.0
+    }
+
+    /// Acceleration in g of one step of the click and interrupt thresholds.
+    pub fn threshold_step(&self) -> f32 {
+        THRESHOLD_STEP[self.fs as usize]
+    }
+}
+
+/// Samples read from the FIFO, oldest first.
+pub struct Fifo {
+    pub samples: Vec<RawSample, FIFO_DEPTH>,
+    /// Samples were lost since the previous read.
+    pub overrun: bool,
+}
+
+/// Enable all axes and set the output data rate and full scale, in high resolution mode with
+/// block data update.
+pub async fn configure<R: Registers>(registers: &mut R, output: Output) -> Result<(), R::Error> {
+    registers.write_register(register::CTRL1, output.odr << 4 | CTRL1_XYZ_EN).await?;
+    registers.write_register(register::CTRL4, CTRL4_BDU | output.fs << 4 | CTRL4_HR).await
+}
+
+/// Queue samples in the FIFO, signalling INT1 once more than `watermark` are pending.
+pub async fn enable_fifo<R: Registers>(registers: &mut R, watermark: u8) -> Result<(), R::Error> {
+    // The interrupt fires when more than `watermark` samples are pending, so keep it below the
+    // FIFO depth to leave room for the samples arriving during the read
+    let watermark = watermark.clamp(1, FIFO_DEPTH as u8 - 2);
+    let ctrl5 = registers.read_register(register::CTRL5).await?;
+    registers.write_register(register::CTRL5, ctrl5 | CTRL5_FIFO_EN).await?;
+    // Passing through bypass mode empties the FIFO
+    registers.write_register(register::FIFO_CTRL, FIFO_CTRL_MODE_BYPASS).await?;
+    registers.write_register(register::FIFO_CTRL, FIFO_CTRL_MODE_STREAM | watermark).await?;
+
+    // Route the watermark, and nothing else, to INT1
+    let ctrl3 = registers.read_register(register::CTRL3).await?;
+    registers.write_register(register::CTRL3, (ctrl3 & !CTRL3_I1_ZYXDA) | CTRL3_I1_WTM).await
+}
+
+/// Run the built-in self-test, which electrostatically deflects the sensing element in both
+/// directions, and measure the output change. The configuration is restored afterwards, and
+/// the FIFO emptied.
+pub async fn self_test<R: Registers, D: DelayNs>(registers: &mut R, delay: &mut D) -> Result<SelfTest, R::Error> {
+    let who_am_i = registers.read_register(register::WHO_AM_I).await?;
+
+    let ctrl1 = registers.read_register(register::CTRL1).await?;
+    let ctrl4 = registers.read_register(register::CTRL4).await?;
+    let ctrl5 = registers.read_register(register::CTRL5).await?;
+    let fifo_ctrl = registers.read_register(register::FIFO_CTRL).await?;
+
+    registers.write_register(register::FIFO_CTRL, FIFO_CTRL_MODE_BYPASS).await?;
+    registers.write_register(register::CTRL5, ctrl5 & !CTRL5_FIFO_EN).await?;
+    registers.write_register(register::CTRL1, SELF_TEST_CTRL1).await?;
+    registers.write_register(register::CTRL4, SELF_TEST_CTRL4).await?;
+    let baseline = settled_average(registers, delay).await?;
+
+    registers.write_register(register::CTRL4, SELF_TEST_CTRL4 | CTRL4_ST0).await?;
+    let st0 = settled_average(registers, delay).await?;
+
+    registers.write_register(register::CTRL4, SELF_TEST_CTRL4 | CTRL4_ST1).await?;
+    let st1 = settled_average(registers, delay).await?;
+
+    registers.write_register(register::CTRL4, ctrl4).await?;
+    registers.write_register(register::CTRL1, ctrl1).await?;
+    registers.write_register(register::CTRL5, ctrl5).await?;
+    // Going through bypass mode also emptied the FIFO of self-test samples
+    registers.write_register(register::FIFO_CTRL, fifo_ctrl).await?;
+
+    let delta = |s: Sample| Sample::new(s.x - baseline.x, s.y - baseline.y, s.z - baseline.z);
+    Ok(SelfTest {
+        who_am_i,
+        st0: delta(st0),
+        st1: delta(st1),
+    })
+}
+
+/// Let the output settle after a configuration change and average a few samples, in g at ±2g.
+async fn settled_average<R: Registers, D: DelayNs>(registers: &mut R, delay: &mut D) -> Result<Sample, R::Error> {
+    delay.delay_ms(90).await;
+    // The first sample after a change is discarded
+    read_output(registers, delay).await?;
+
+    let mut sum = [0i32; 3];
+    for _ in 0..SELF_TEST_SAMPLES {
+        let raw = read_output(registers, delay).await?;
+        sum[0] += raw.x as i32;
+        sum[1] += raw.y as i32;
+        sum[2] += raw.z as i32;
+    }
+
+    let scale = Output::new(0.0, 2.0).scale() / SELF_TEST_SAMPLES as f32;
+    Ok(Sample::new(sum[0] as f32 * scale, sum[1] as f32 * scale, sum[2] as f32 * scale))
+}
+
+/// Wait for a new sample and read it from the output registers.
+async fn read_output<R: Registers, D: DelayNs>(registers: &mut R, delay: &mut D) -> Result<RawSample, R::Error> {
+    while registers.read_register(register::STATUS).await? & STATUS_ZYXDA == 0 {
+        // One sample period at the 50 Hz self-test rate
+        delay.delay_ms(20).await;
+    }
+    let mut buf = [0u8; 6];
+    registers.read_registers(register::OUT_X_L, &mut buf).await?;
+    Ok(raw_sample(&buf))
+}
+
+/// Read all samples pending in the FIFO, in a single transaction.
+pub async fn read_fifo<R: Registers>(registers: &mut R) -> Result<Fifo, R::Error> {
+    let src = registers.read_register(register::FIFO_SRC).await?;
+    let overrun = src & FIFO_SRC_OVRN != 0;
+    let pending = if overrun {
+        warn!("xl FIFO overrun, samples were lost");
+        FIFO_DEPTH
+    } else {
+        (src & FIFO_SRC_FSS_MASK) as usize
+    };
+
+    let mut buf = [0u8; FIFO_DEPTH * 6];
+    let buf = &mut buf[..pending * 6];
+    if !buf.is_empty() {
+        registers.read_registers(register::OUT_X_L, buf).await?;
+    }
+    Ok(Fifo {
+        samples: buf.chunks_exact(6).map(raw_sample).collect(),
+        overrun,
+    })
+}
+
+fn raw_sample(raw: &[u8]) -> RawSample {
+    RawSample {
+        x: i16::from_le_bytes([raw[0], raw[1]]),
+        y: i16::from_le_bytes([raw[2], raw[3]]),
+        z: i16::from_le_bytes([raw[4], raw[5]]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_rounds_up() {
+        let output = Output::new(300.0, 3.0);
+        assert_eq!(output.rate(), 400.0);
+        assert_eq!(output.period(), Duration::from_micros(2500));
+        assert_eq!(output.full_scale(), 4.0);
+        assert_eq!(output.scale(), 0.002 / 16.0);
+
+        let exact = Output::new(25.0, 2.0);
+        assert_eq!((exact.rate(), exact.full_scale()), (25.0, 2.0));
+    }
+
+    #[test]
+    fn output_beyond_the_fastest_and_largest() {
+        let output = Output::new(5000.0, 32.0);
+        assert_eq!(output.rate(), 1344.0);
+        assert_eq!(output.full_scale(), 16.0);
+        // The ±16 g sensitivity is not the full scale over 32768
+        assert_eq!(output.scale() * 32768.0, 24.576);
+        assert_eq!(output.threshold_step(), 0.186);
+    }
+}
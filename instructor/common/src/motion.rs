@@ -0,0 +1,50 @@
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Sign {
+    Positive,
+    Negative,
+}
+
+/// Motion detected by the sensor itself rather than derived from the sample stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MotionEvent {
+    Tap { axis: Axis, sign: Sign },
+    DoubleTap { axis: Axis, sign: Sign },
+    FreeFall,
+    /// The sensor started moving again after being inactive.
+    Activity,
+    /// The sensor has been still for the configured duration.
+    Inactivity,
+}
+
+impl fmt::Display for MotionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MotionEvent::Tap { axis, sign } => write!(f, "tap {}{:?}", sign, axis),
+            MotionEvent::DoubleTap { axis, sign } => write!(f, "double tap {}{:?}", sign, axis),
+            MotionEvent::FreeFall => f.write_str("free fall"),
+            MotionEvent::Activity => f.write_str("activity"),
+            MotionEvent::Inactivity => f.write_str("inactivity"),
+        }
+    }
+}
+
+impl fmt::Display for Sign {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sign::Positive => f.write_str("+"),
+            Sign::Negative => f.write_str("-"),
+        }
+    }
+}
@@ -0,0 +1,646 @@
+//! Wire protocol between the firmware and the viewer.
+//!
+//! A connection starts with [`MAGIC`], followed by frames made of a [`Header`] (tag and payload
+//! length) and the payload. Receivers skip frames with tags they do not know, so new frame types
+//! can be added without breaking older viewers.
+//!
+//! Devices with several sensors send a [`Frame::Sensor`] before the frames of each sensor. Frames
+//! before the first one are from sensor 0.
+//!
+//! Sensors can send their acceleration as [`Frame::Samples`] in g, or as the more compact
+//! [`Frame::RawSamples`] in native counts, optionally compressed as [`Frame::DeltaSamples`]. The
+//! [`Frame::RawFormat`] needed to scale the counts comes first on every connection.
+//!
+//! The viewer sends frames to the device over the same connection, without [`MAGIC`]. The only
+//! one so far is [`Frame::Calibrate`].
+//!
+//! Devices that do not start with [`MAGIC`] are legacy clients, which stream bare `x, y, z`
+//! little-endian f32 triplets (see [`decode_legacy_sample`]).
+
+use heapless::Vec;
+
+use crate::attitude::Attitude;
+use crate::calibration::{Calibration, CalibrationStatus, CALIBRATION_LEN};
+use crate::delta;
+use crate::health::{Health, SelfTest, SensorStatus};
+use crate::motion::{Axis, MotionEvent, Sign};
+use crate::raw::{RawFormat, RawSample};
+use crate::{AngularRate, Sample};
+
+/// Sent once by the device when the connection is opened.
+pub const MAGIC: [u8; 4] = *b"XLP1";
+
+/// Length of an encoded [`Header`].
+pub const HEADER_LEN: usize = 3;
+
+/// Maximum number of samples in one [`Frame::Samples`].
+pub const MAX_SAMPLES: usize = 32;
+
+/// Length of one accelerometer-only sample, both in a [`Frame::Samples`] payload and in the
+/// legacy stream.
+pub const SAMPLE_LEN: usize = 12;
+
+/// Length of one sample with angular rate in a [`Frame::Samples`] payload.
+pub const GYRO_SAMPLE_LEN: usize = 2 * SAMPLE_LEN;
+
+/// Length of one sample in a [`Frame::RawSamples`] payload.
+pub const RAW_SAMPLE_LEN: usize = 6;
+
+/// Length of the largest frame, header included.
+pub const MAX_FRAME_LEN: usize = HEADER_LEN + MAX_SAMPLES * GYRO_SAMPLE_LEN;
+
+mod tag {
+    pub const SAMPLES: u8 = 0x01;
+    pub const MOTION: u8 = 0x02;
+    pub const HEALTH: u8 = 0x03;
+    pub const SENSOR: u8 = 0x04;
+    pub const GYRO_SAMPLES: u8 = 0x05;
+    pub const CALIBRATION: u8 = 0x06;
+    pub const CALIBRATE: u8 = 0x07;
+    pub const ATTITUDE: u8 = 0x08;
+    pub const RAW_FORMAT: u8 = 0x09;
+    pub const RAW_SAMPLES: u8 = 0x0A;
+    pub const DELTA_SAMPLES: u8 = 0x0B;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The output buffer cannot hold the encoded frame.
+    BufferTooSmall,
+    /// The payload is shorter or longer than the frame requires.
+    InvalidLength,
+    /// A field in the payload has a value outside its range.
+    InvalidValue,
+    /// The frame type is not known to this receiver.
+    UnknownTag(u8),
+}
+
+impl From<delta::Error> for Error {
+    fn from(e: delta::Error) -> Self {
+        match e {
+            delta::Error::BufferTooSmall => Error::BufferTooSmall,
+            delta::Error::Truncated | delta::Error::TooManySamples => Error::InvalidLength,
+            delta::Error::Overflow => Error::InvalidValue,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub tag: u8,
+    /// Length of the payload following the header.
+    pub len: u16,
+}
+
+impl Header {
+    pub fn decode(buf: [u8; HEADER_LEN]) -> Self {
+        Self {
+            tag: buf[0],
+            len: u16::from_le_bytes([buf[1], buf[2]]),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let len = self.len.to_le_bytes();
+        [self.tag, len[0], len[1]]
+    }
+}
+
+// Frames are built on the stack in no_std code, so boxing the samples is not an option
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Frame {
+    /// Consecutive samples, oldest first. The angular rate is only sent if all samples have one.
+    Samples(Vec<Sample, MAX_SAMPLES>),
+    Motion(MotionEvent),
+    Health(Health),
+    /// The following frames, up to the next `Sensor` frame, are from the sensor with this index.
+    Sensor(u8),
+    /// Calibration progress of the current sensor.
+    Calibration(CalibrationStatus),
+    /// Sent by the viewer to start calibrating all sensors of the device, or to cancel a
+    /// calibration in progress.
+    Calibrate,
+    /// Orientation of the current sensor, sent instead of samples by devices estimating it
+    /// themselves.
+    Attitude(Attitude),
+    /// How to scale the raw samples of the current sensor. Sent before its first raw samples on
+    /// each connection, and whenever it changes.
+    RawFormat(RawFormat),
+    /// Consecutive raw samples of the current sensor, oldest first.
+    RawSamples(Vec<RawSample, MAX_SAMPLES>),
+    /// The same as [`Frame::RawSamples`], delta encoded as described in [`crate::delta`].
+    DeltaSamples(Vec<RawSample, MAX_SAMPLES>),
+}
+
+impl Frame {
+    /// Encode the frame, header included, returning the number of bytes written.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let (header, payload) = buf.split_at_mut_checked(HEADER_LEN).ok_or(Error::BufferTooSmall)?;
+        let (tag, len) = match self {
+            Frame::Samples(samples) if samples.iter().all(|s| s.gyro.is_some()) && !samples.is_empty() => {
+                (tag::GYRO_SAMPLES, encode_gyro_samples(samples, payload)?)
+            }
+            Frame::Samples(samples) => (tag::SAMPLES, encode_samples(samples, payload)?),
+            Frame::Motion(event) => (tag::MOTION, encode_motion(event, payload)?),
+            Frame::Health(health) => (tag::HEALTH, encode_health(health, payload)?),
+            Frame::Sensor(index) => {
+                *payload.first_mut().ok_or(Error::BufferTooSmall)? = *index;
+                (tag::SENSOR, 1)
+            }
+            Frame::Calibration(status) => (tag::CALIBRATION, encode_calibration(status, payload)?),
+            Frame::Calibrate => (tag::CALIBRATE, 0),
+            Frame::Attitude(attitude) => (tag::ATTITUDE, encode_attitude(attitude, payload)?),
+            Frame::RawFormat(format) => (tag::RAW_FORMAT, encode_raw_format(format, payload)?),
+            Frame::RawSamples(samples) => (tag::RAW_SAMPLES, encode_raw_samples(samples, payload)?),
+            Frame::DeltaSamples(samples) => (tag::DELTA_SAMPLES, delta::encode(samples, payload)?),
+        };
+        header.copy_from_slice(&Header { tag, len: len as u16 }.encode());
+        Ok(HEADER_LEN + len)
+    }
+
+    /// Decode the payload of a frame with the given tag.
+    pub fn decode(tag: u8, payload: &[u8]) -> Result<Frame, Error> {
+        match tag {
+            tag::SAMPLES => decode_samples(payload).map(Frame::Samples),
+            tag::GYRO_SAMPLES => decode_gyro_samples(payload).map(Frame::Samples),
+            tag::MOTION => decode_motion(payload).map(Frame::Motion),
+            tag::HEALTH => decode_health(payload).map(Frame::Health),
+            tag::SENSOR => match payload {
+                &[index] => Ok(Frame::Sensor(index)),
+                _ => Err(Error::InvalidLength),
+            },
+            tag::CALIBRATION => decode_calibration(payload).map(Frame::Calibration),
+            tag::CALIBRATE => match payload {
+                [] => Ok(Frame::Calibrate),
+                _ => Err(Error::InvalidLength),
+            },
+            tag::ATTITUDE => decode_attitude(payload).map(Frame::Attitude),
+            tag::RAW_FORMAT => decode_raw_format(payload).map(Frame::RawFormat),
+            tag::RAW_SAMPLES => decode_raw_samples(payload).map(Frame::RawSamples),
+            tag::DELTA_SAMPLES => Ok(Frame::DeltaSamples(delta::decode(payload)?)),
+            other => Err(Error::UnknownTag(other)),
+        }
+    }
+}
+
+/// Decode one sample of the legacy stream.
+pub fn decode_legacy_sample(buf: &[u8; SAMPLE_LEN]) -> Sample {
+    decode_sample(buf)
+}
+
+fn encode_sample(sample: &Sample, buf: &mut [u8]) {
+    buf[0..4].copy_from_slice(&sample.x.to_le_bytes());
+    buf[4..8].copy_from_slice(&sample.y.to_le_bytes());
+    buf[8..12].copy_from_slice(&sample.z.to_le_bytes());
+}
+
+fn decode_sample(buf: &[u8]) -> Sample {
+    let [x, y, z] = decode_vector(buf);
+    Sample::new(x, y, z)
+}
+
+fn decode_vector(buf: &[u8]) -> [f32; 3] {
+    [
+        f32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+        f32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+        f32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]),
+    ]
+}
+
+fn encode_samples(samples: &[Sample], buf: &mut [u8]) -> Result<usize, Error> {
+    let len = samples.len() * SAMPLE_LEN;
+    let buf = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+    for (sample, chunk) in samples.iter().zip(buf.chunks_exact_mut(SAMPLE_LEN)) {
+        encode_sample(sample, chunk);
+    }
+    Ok(len)
+}
+
+fn decode_samples(payload: &[u8]) -> Result<Vec<Sample, MAX_SAMPLES>, Error> {
+    if !payload.len().is_multiple_of(SAMPLE_LEN) || payload.len() > MAX_SAMPLES * SAMPLE_LEN {
+        return Err(Error::InvalidLength);
+    }
+    Ok(payload.chunks_exact(SAMPLE_LEN).map(decode_sample).collect())
+}
+
+// Samples with angular rate: the acceleration followed by the angular rate, both as x, y, z
+fn encode_gyro_samples(samples: &[Sample], buf: &mut [u8]) -> Result<usize, Error> {
+    let len = samples.len() * GYRO_SAMPLE_LEN;
+    let buf = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+    for (sample, chunk) in samples.iter().zip(buf.chunks_exact_mut(GYRO_SAMPLE_LEN)) {
+        let (accel, gyro) = chunk.split_at_mut(SAMPLE_LEN);
+        encode_sample(sample, accel);
+        let rate = sample.gyro.ok_or(Error::InvalidValue)?;
+        encode_sample(&Sample::new(rate.x, rate.y, rate.z), gyro);
+    }
+    Ok(len)
+}
+
+fn decode_gyro_samples(payload: &[u8]) -> Result<Vec<Sample, MAX_SAMPLES>, Error> {
+    if !payload.len().is_multiple_of(GYRO_SAMPLE_LEN) || payload.len() > MAX_SAMPLES * GYRO_SAMPLE_LEN {
+        return Err(Error::InvalidLength);
+    }
+    Ok(payload
+        .chunks_exact(GYRO_SAMPLE_LEN)
+        .map(|chunk| {
+            let [x, y, z] = decode_vector(&chunk[SAMPLE_LEN..]);
+            Sample {
+                gyro: Some(AngularRate { x, y, z }),
+                ..decode_sample(chunk)
+            }
+        })
+        .collect())
+}
+
+// Motion payload: kind, axis, sign. Axis and sign are zero for events without a direction.
+const MOTION_TAP: u8 = 0;
+const MOTION_DOUBLE_TAP: u8 = 1;
+const MOTION_FREE_FALL: u8 = 2;
+const MOTION_ACTIVITY: u8 = 3;
+const MOTION_INACTIVITY: u8 = 4;
+
+fn encode_motion(event: &MotionEvent, buf: &mut [u8]) -> Result<usize, Error> {
+    let buf = buf.get_mut(..3).ok_or(Error::BufferTooSmall)?;
+    let (kind, axis, sign) = match *event {
+        MotionEvent::Tap { axis, sign } => (MOTION_TAP, axis, sign),
+        MotionEvent::DoubleTap { axis, sign } => (MOTION_DOUBLE_TAP, axis, sign),
+        MotionEvent::FreeFall => (MOTION_FREE_FALL, Axis::X, Sign::Positive),
+        MotionEvent::Activity => (MOTION_ACTIVITY, Axis::X, Sign::Positive),
+        MotionEvent::Inactivity => (MOTION_INACTIVITY, Axis::X, Sign::Positive),
+    };
+    buf[0] = kind;
+    buf[1] = match axis {
+        Axis::X => 0,
+        Axis::Y => 1,
+        Axis::Z => 2,
+    };
+    buf[2] = match sign {
+        Sign::Positive => 0,
+        Sign::Negative => 1,
+    };
+    Ok(3)
+}
+
+fn decode_motion(payload: &[u8]) -> Result<MotionEvent, Error> {
+    let &[kind, axis, sign] = payload else {
+        return Err(Error::InvalidLength);
+    };
+    let axis = match axis {
+        0 => Axis::X,
+        1 => Axis::Y,
+        2 => Axis::Z,
+        _ => return Err(Error::InvalidValue),
+    };
+    let sign = match sign {
+        0 => Sign::Positive,
+        1 => Sign::Negative,
+        _ => return Err(Error::InvalidValue),
+    };
+    match kind {
+        MOTION_TAP => Ok(MotionEvent::Tap { axis, sign }),
+        MOTION_DOUBLE_TAP => Ok(MotionEvent::DoubleTap { axis, sign }),
+        MOTION_FREE_FALL => Ok(MotionEvent::FreeFall),
+        MOTION_ACTIVITY => Ok(MotionEvent::Activity),
+        MOTION_INACTIVITY => Ok(MotionEvent::Inactivity),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+// Health payload: WHO_AM_I, the self-test 0 and self-test 1 deltas, then the sensor status and
+// recovery attempt
+const HEALTH_STATUS: usize = 1 + 2 * SAMPLE_LEN;
+const HEALTH_LEN: usize = HEALTH_STATUS + 2;
+
+const STATUS_OK: u8 = 0;
+const STATUS_RECOVERING: u8 = 1;
+const STATUS_FAULT: u8 = 2;
+
+fn encode_health(health: &Health, buf: &mut [u8]) -> Result<usize, Error> {
+    let buf = buf.get_mut(..HEALTH_LEN).ok_or(Error::BufferTooSmall)?;
+    let self_test = &health.self_test;
+    buf[0] = self_test.who_am_i;
+    encode_sample(&self_test.st0, &mut buf[1..1 + SAMPLE_LEN]);
+    encode_sample(&self_test.st1, &mut buf[1 + SAMPLE_LEN..HEALTH_STATUS]);
+    let (status, attempt) = match health.status {
+        SensorStatus::Ok => (STATUS_OK, 0),
+        SensorStatus::Recovering { attempt } => (STATUS_RECOVERING, attempt),
+        SensorStatus::Fault => (STATUS_FAULT, 0),
+    };
+    buf[HEALTH_STATUS] = status;
+    buf[HEALTH_STATUS + 1] = attempt;
+    Ok(HEALTH_LEN)
+}
+
+fn decode_health(payload: &[u8]) -> Result<Health, Error> {
+    if payload.len() != HEALTH_LEN {
+        return Err(Error::InvalidLength);
+    }
+    let status = match payload[HEALTH_STATUS] {
+        STATUS_OK => SensorStatus::Ok,
+        STATUS_RECOVERING => SensorStatus::Recovering { attempt: payload[HEALTH_STATUS + 1] },
+        STATUS_FAULT => SensorStatus::Fault,
+        _ => return Err(Error::InvalidValue),
+    };
+    Ok(Health {
+        self_test: SelfTest {
+            who_am_i: payload[0],
+            st0: decode_sample(&payload[1..1 + SAMPLE_LEN]),
+            st1: decode_sample(&payload[1 + SAMPLE_LEN..HEALTH_STATUS]),
+        },
+        status,
+    })
+}
+
+// Calibration payload: the state, then the captured orientations while in progress, or the
+// offsets and scales when done
+const CALIBRATION_IN_PROGRESS: u8 = 0;
+const CALIBRATION_DONE: u8 = 1;
+const CALIBRATION_FAILED: u8 = 2;
+
+fn encode_calibration(status: &CalibrationStatus, buf: &mut [u8]) -> Result<usize, Error> {
+    let (state, rest) = buf.split_first_mut().ok_or(Error::BufferTooSmall)?;
+    match status {
+        CalibrationStatus::InProgress { captured } => {
+            *state = CALIBRATION_IN_PROGRESS;
+            *rest.first_mut().ok_or(Error::BufferTooSmall)? = *captured;
+            Ok(2)
+        }
+        CalibrationStatus::Done(calibration) => {
+            *state = CALIBRATION_DONE;
+            rest.get_mut(..CALIBRATION_LEN)
+                .ok_or(Error::BufferTooSmall)?
+                .copy_from_slice(&calibration.to_bytes());
+            Ok(1 + CALIBRATION_LEN)
+        }
+        CalibrationStatus::Failed => {
+            *state = CALIBRATION_FAILED;
+            Ok(1)
+        }
+    }
+}
+
+fn decode_calibration(payload: &[u8]) -> Result<CalibrationStatus, Error> {
+    match payload {
+        &[CALIBRATION_IN_PROGRESS, captured] => Ok(CalibrationStatus::InProgress { captured }),
+        [CALIBRATION_DONE, rest @ ..] => {
+            let bytes = rest.try_into().map_err(|_| Error::InvalidLength)?;
+            Ok(CalibrationStatus::Done(Calibration::from_bytes(bytes)))
+        }
+        &[CALIBRATION_FAILED] => Ok(CalibrationStatus::Failed),
+        [CALIBRATION_IN_PROGRESS | CALIBRATION_FAILED, ..] | [] => Err(Error::InvalidLength),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+// Attitude payload: the representation, then roll and pitch, or w, x, y and z
+const ATTITUDE_TILT: u8 = 0;
+const ATTITUDE_QUATERNION: u8 = 1;
+
+fn encode_attitude(attitude: &Attitude, buf: &mut [u8]) -> Result<usize, Error> {
+    let (kind, values, count) = match *attitude {
+        Attitude::Tilt { roll, pitch } => (ATTITUDE_TILT, [roll, pitch, 0.0, 0.0], 2),
+        Attitude::Quaternion { w, x, y, z } => (ATTITUDE_QUATERNION, [w, x, y, z], 4),
+    };
+    let len = 1 + 4 * count;
+    let buf = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+    buf[0] = kind;
+    for (chunk, value) in buf[1..].chunks_exact_mut(4).zip(values) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    Ok(len)
+}
+
+fn decode_attitude(payload: &[u8]) -> Result<Attitude, Error> {
+    let (&kind, rest) = payload.split_first().ok_or(Error::InvalidLength)?;
+    let mut values = rest.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]));
+    let mut next = || values.next().unwrap_or_default();
+    match (kind, rest.len()) {
+        (ATTITUDE_TILT, 8) => Ok(Attitude::Tilt { roll: next(), pitch: next() }),
+        (ATTITUDE_QUATERNION, 16) => Ok(Attitude::Quaternion { w: next(), x: next(), y: next(), z: next() }),
+        (ATTITUDE_TILT | ATTITUDE_QUATERNION, _) => Err(Error::InvalidLength),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+// Raw format payload: g per count as f32, resolution, then the calibration
+const RAW_FORMAT_LEN: usize = 5 + CALIBRATION_LEN;
+
+fn encode_raw_format(format: &RawFormat, buf: &mut [u8]) -> Result<usize, Error> {
+    let buf = buf.get_mut(..RAW_FORMAT_LEN).ok_or(Error::BufferTooSmall)?;
+    buf[0..4].copy_from_slice(&format.scale.to_le_bytes());
+    buf[4] = format.resolution;
+    buf[5..].copy_from_slice(&format.calibration.to_bytes());
+    Ok(RAW_FORMAT_LEN)
+}
+
+fn decode_raw_format(payload: &[u8]) -> Result<RawFormat, Error> {
+    let [s0, s1, s2, s3, resolution, calibration @ ..] = payload else {
+        return Err(Error::InvalidLength);
+    };
+    let calibration = calibration.try_into().map_err(|_| Error::InvalidLength)?;
+    let scale = f32::from_le_bytes([*s0, *s1, *s2, *s3]);
+    if !(scale.is_finite() && scale > 0.0 && (1..=16).contains(resolution)) {
+        return Err(Error::InvalidValue);
+    }
+    Ok(RawFormat {
+        scale,
+        resolution: *resolution,
+        calibration: Calibration::from_bytes(calibration),
+    })
+}
+
+fn encode_raw_samples(samples: &[RawSample], buf: &mut [u8]) -> Result<usize, Error> {
+    let len = samples.len() * RAW_SAMPLE_LEN;
+    let buf = buf.get_mut(..len).ok_or(Error::BufferTooSmall)?;
+    for (sample, chunk) in samples.iter().zip(buf.chunks_exact_mut(RAW_SAMPLE_LEN)) {
+        chunk[0..2].copy_from_slice(&sample.x.to_le_bytes());
+        chunk[2..4].copy_from_slice(&sample.y.to_le_bytes());
+        chunk[4..6].copy_from_slice(&sample.z.to_le_bytes());
+    }
+    Ok(len)
+}
+
+fn decode_raw_samples(payload: &[u8]) -> Result<Vec<RawSample, MAX_SAMPLES>, Error> {
+    if !payload.len().is_multiple_of(RAW_SAMPLE_LEN) || payload.len() > MAX_SAMPLES * RAW_SAMPLE_LEN {
+        return Err(Error::InvalidLength);
+    }
+    Ok(payload
+        .chunks_exact(RAW_SAMPLE_LEN)
+        .map(|c| RawSample {
+            x: i16::from_le_bytes([c[0], c[1]]),
+            y: i16::from_le_bytes([c[2], c[3]]),
+            z: i16::from_le_bytes([c[4], c[5]]),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(samples: &[Sample]) -> Vec<Sample, MAX_SAMPLES> {
+        samples.iter().copied().collect()
+    }
+
+    fn gyro_sample(x: f32, rate: f32) -> Sample {
+        Sample {
+            gyro: Some(AngularRate { x: rate, y: -rate, z: 2.0 * rate }),
+            ..Sample::new(x, -x, 1.0)
+        }
+    }
+
+    fn raw_samples() -> Vec<RawSample, MAX_SAMPLES> {
+        (0..MAX_SAMPLES as i16)
+            .map(|i| RawSample { x: i * 1000, y: -i * 7, z: i16::MAX - i })
+            .collect()
+    }
+
+    /// One frame of every kind, and of every variant of their payloads.
+    fn frames() -> [Frame; 19] {
+        let self_test = SelfTest {
+            who_am_i: 0x33,
+            st0: Sample::new(0.1, 0.2, 0.3),
+            st1: Sample::new(-0.1, -0.2, -0.3),
+        };
+        let calibration = Calibration { offset: [0.01, -0.02, 0.03], scale: [1.01, 0.99, 1.0] };
+        [
+            Frame::Samples(samples(&[Sample::new(0.0, 0.5, 1.0), Sample::new(-1.5, 2.0, -0.25)])),
+            Frame::Samples(samples(&[gyro_sample(0.1, 10.0), gyro_sample(0.2, -20.0)])),
+            Frame::Samples(Vec::new()),
+            Frame::Motion(MotionEvent::Tap { axis: Axis::Y, sign: Sign::Negative }),
+            Frame::Motion(MotionEvent::DoubleTap { axis: Axis::Z, sign: Sign::Positive }),
+            Frame::Motion(MotionEvent::FreeFall),
+            Frame::Motion(MotionEvent::Activity),
+            Frame::Motion(MotionEvent::Inactivity),
+            Frame::Health(Health { self_test, status: SensorStatus::Ok }),
+            Frame::Health(Health { self_test, status: SensorStatus::Recovering { attempt: 2 } }),
+            Frame::Health(Health { self_test, status: SensorStatus::Fault }),
+            Frame::Sensor(3),
+            Frame::Calibration(CalibrationStatus::InProgress { captured: 0b010110 }),
+            Frame::Calibration(CalibrationStatus::Done(calibration)),
+            Frame::Calibration(CalibrationStatus::Failed),
+            Frame::Calibrate,
+            Frame::Attitude(Attitude::Tilt { roll: 0.5, pitch: -0.25 }),
+            Frame::Attitude(Attitude::Quaternion { w: 0.5, x: 0.5, y: -0.5, z: 0.5 }),
+            Frame::RawFormat(RawFormat { scale: 0.012 / 16.0, resolution: 12, calibration }),
+        ]
+    }
+
+    /// Encode `frame` and decode it again, checking the header on the way.
+    fn round_trip(frame: &Frame) -> (u8, Frame) {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = frame.encode(&mut buf).unwrap();
+        let header = Header::decode([buf[0], buf[1], buf[2]]);
+        assert_eq!(header.len as usize, len - HEADER_LEN);
+        (header.tag, Frame::decode(header.tag, &buf[HEADER_LEN..len]).unwrap())
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        for frame in frames() {
+            assert_eq!(round_trip(&frame).1, frame);
+        }
+    }
+
+    #[test]
+    fn raw_samples_round_trip() {
+        for frame in [Frame::RawSamples(raw_samples()), Frame::DeltaSamples(raw_samples())] {
+            assert_eq!(round_trip(&frame).1, frame);
+        }
+    }
+
+    #[test]
+    fn full_batches_round_trip() {
+        let accel: Vec<Sample, MAX_SAMPLES> = (0..MAX_SAMPLES).map(|i| Sample::new(i as f32, 0.0, 1.0)).collect();
+        let gyro: Vec<Sample, MAX_SAMPLES> = (0..MAX_SAMPLES).map(|i| gyro_sample(i as f32, 1.0)).collect();
+        assert_eq!(round_trip(&Frame::Samples(accel.clone())), (tag::SAMPLES, Frame::Samples(accel)));
+        assert_eq!(round_trip(&Frame::Samples(gyro.clone())), (tag::GYRO_SAMPLES, Frame::Samples(gyro)));
+    }
+
+    #[test]
+    fn samples_with_rate_are_sent_as_gyro_samples() {
+        let frame = Frame::Samples(samples(&[gyro_sample(0.1, 10.0)]));
+        assert_eq!(round_trip(&frame).0, tag::GYRO_SAMPLES);
+        assert_eq!(round_trip(&Frame::Samples(Vec::new())).0, tag::SAMPLES);
+    }
+
+    #[test]
+    fn mixed_samples_drop_the_rate() {
+        let frame = Frame::Samples(samples(&[gyro_sample(0.1, 10.0), Sample::new(0.2, -0.2, 1.0)]));
+        let (tag, decoded) = round_trip(&frame);
+        assert_eq!(tag, tag::SAMPLES);
+        let expected = samples(&[Sample::new(0.1, -0.1, 1.0), Sample::new(0.2, -0.2, 1.0)]);
+        assert_eq!(decoded, Frame::Samples(expected));
+    }
+
+    #[test]
+    fn unknown_tags_are_reported() {
+        assert_eq!(Frame::decode(0xFF, &[]), Err(Error::UnknownTag(0xFF)));
+        assert_eq!(Frame::decode(0x00, &[1, 2, 3]), Err(Error::UnknownTag(0x00)));
+    }
+
+    #[test]
+    fn payloads_of_the_wrong_length_are_rejected() {
+        let cases: [(u8, &[u8]); 14] = [
+            (tag::SAMPLES, &[0; SAMPLE_LEN + 1]),
+            (tag::SAMPLES, &[0; (MAX_SAMPLES + 1) * SAMPLE_LEN]),
+            (tag::GYRO_SAMPLES, &[0; SAMPLE_LEN]),
+            (tag::GYRO_SAMPLES, &[0; (MAX_SAMPLES + 1) * GYRO_SAMPLE_LEN]),
+            (tag::MOTION, &[MOTION_TAP, 0]),
+            (tag::HEALTH, &[0; HEALTH_LEN - 1]),
+            (tag::SENSOR, &[]),
+            (tag::SENSOR, &[0, 1]),
+            (tag::CALIBRATION, &[]),
+            (tag::CALIBRATION, &[CALIBRATION_DONE, 0, 0]),
+            (tag::CALIBRATE, &[0]),
+            (tag::ATTITUDE, &[ATTITUDE_TILT, 0, 0, 0, 0]),
+            (tag::RAW_FORMAT, &[0; RAW_FORMAT_LEN - 1]),
+            (tag::RAW_SAMPLES, &[0; RAW_SAMPLE_LEN - 1]),
+        ];
+        for (tag, payload) in cases {
+            assert_eq!(Frame::decode(tag, payload), Err(Error::InvalidLength), "tag {:#x}", tag);
+        }
+    }
+
+    #[test]
+    fn values_out_of_range_are_rejected() {
+        let mut health = [0; HEALTH_LEN];
+        health[HEALTH_STATUS] = 3;
+        let mut format = [0; RAW_FORMAT_LEN];
+        format[4] = 12;
+        let mut nan_format = format;
+        nan_format[..4].copy_from_slice(&f32::NAN.to_le_bytes());
+        let mut resolution = format;
+        resolution[..4].copy_from_slice(&1.0f32.to_le_bytes());
+        resolution[4] = 17;
+        let cases: [(u8, &[u8]); 9] = [
+            (tag::MOTION, &[5, 0, 0]),
+            (tag::MOTION, &[MOTION_TAP, 3, 0]),
+            (tag::MOTION, &[MOTION_TAP, 0, 2]),
+            (tag::HEALTH, &health),
+            (tag::CALIBRATION, &[3]),
+            (tag::ATTITUDE, &[2, 0, 0, 0, 0, 0, 0, 0, 0]),
+            (tag::RAW_FORMAT, &format),
+            (tag::RAW_FORMAT, &nan_format),
+            (tag::RAW_FORMAT, &resolution),
+        ];
+        for (tag, payload) in cases {
+            assert_eq!(Frame::decode(tag, payload), Err(Error::InvalidValue), "tag {:#x}", tag);
+        }
+    }
+
+    #[test]
+    fn short_buffers_are_reported() {
+        let raw = [Frame::RawSamples(raw_samples()), Frame::DeltaSamples(raw_samples())];
+        for frame in frames().into_iter().chain(raw) {
+            let mut buf = [0; MAX_FRAME_LEN];
+            let len = frame.encode(&mut buf).unwrap();
+            for short in 0..len {
+                assert_eq!(frame.encode(&mut buf[..short]), Err(Error::BufferTooSmall), "{:?} in {} bytes", frame, short);
+            }
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Accelerometer readings in the sensor's native counts, scaled by the receiver.
+//!
+//! Sending counts takes 6 bytes per sample instead of 12 and spares the device the float work.
+//! The [`RawFormat`] needed to turn them into g is sent once per connection, and again whenever
+//! it changes.
+
+use crate::calibration::Calibration;
+use crate::Sample;
+
+/// One accelerometer reading as the 16-bit left-justified counts of the output registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RawSample {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+/// How to turn [`RawSample`]s into g.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RawFormat {
+    /// The acceleration in g of one count. Not always the nominal range over 32768, the LIS3DH
+    /// at ±16 g for one reads up to 24.576 g.
+    pub scale: f32,
+    /// Significant bits of each count, the lower bits are zero. 8, 10 or 12 for the LIS3DH in
+    /// low-power, normal and high-resolution mode, 16 for the LSM6DSO.
+    pub resolution: u8,
+    /// Calibration of the sensor, applied after scaling.
+    pub calibration: Calibration,
+}
+
+impl RawFormat {
+    /// The acceleration in g at a count of 32768.
+    pub fn full_scale(&self) -> f32 {
+        self.scale * 32768.0
+    }
+
+    /// The reading in g, without the calibration.
+    pub fn uncalibrated(&self, raw: &RawSample) -> Sample {
+        let scale = self.scale;
+        Sample::new(raw.x as f32 * scale, raw.y as f32 * scale, raw.z as f32 * scale)
+    }
+
+    /// The calibrated reading in g.
+    pub fn to_sample(&self, raw: &RawSample) -> Sample {
+        self.calibration.apply(self.uncalibrated(raw))
+    }
+}
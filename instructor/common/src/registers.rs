@@ -0,0 +1,79 @@
+//! Register access to sensors, for the burst reads drivers do not support.
+//!
+//! Shared by the firmware and the simulator, so both frame register transfers the same way.
+
+use embedded_hal_async::i2c::I2c;
+use embedded_hal_async::spi::{Operation, SpiDevice};
+
+/// Register access next to the sensor driver.
+// Only used by single-threaded executors, so the futures do not have to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait Registers {
+    type Error;
+
+    /// Fill `buf` with the registers starting at `start`.
+    async fn read_registers(&mut self, start: u8, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), Self::Error>;
+
+    async fn read_register(&mut self, register: u8) -> Result<u8, Self::Error> {
+        let mut value = [0];
+        self.read_registers(register, &mut value).await?;
+        Ok(value[0])
+    }
+}
+
+/// Register access to a sensor on an I2C bus.
+pub struct I2cRegisters<I> {
+    i2c: I,
+    address: u8,
+    auto_increment: u8,
+}
+
+impl<I: I2c> I2cRegisters<I> {
+    /// `auto_increment` is or-ed into the register address of multi-byte reads.
+    pub fn new(i2c: I, address: u8, auto_increment: u8) -> Self {
+        Self { i2c, address, auto_increment }
+    }
+}
+
+impl<I: I2c> Registers for I2cRegisters<I> {
+    type Error = I::Error;
+
+    async fn read_registers(&mut self, start: u8, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.i2c.write_read(self.address, &[start | self.auto_increment], buf).await
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), Self::Error> {
+        self.i2c.write(self.address, &[register, value]).await
+    }
+}
+
+/// The MSB of the first byte of a SPI transfer selects a read.
+pub const SPI_READ: u8 = 0x80;
+
+/// Register access to a sensor on a SPI bus.
+pub struct SpiRegisters<S> {
+    spi: S,
+    auto_increment: u8,
+}
+
+impl<S: SpiDevice> SpiRegisters<S> {
+    /// `auto_increment` is or-ed into the register address of multi-byte reads.
+    pub fn new(spi: S, auto_increment: u8) -> Self {
+        Self { spi, auto_increment }
+    }
+}
+
+impl<S: SpiDevice> Registers for SpiRegisters<S> {
+    type Error = S::Error;
+
+    async fn read_registers(&mut self, start: u8, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let address = start | SPI_READ | self.auto_increment;
+        self.spi.transaction(&mut [Operation::Write(&[address]), Operation::Read(buf)]).await
+    }
+
+    async fn write_register(&mut self, register: u8, value: u8) -> Result<(), Self::Error> {
+        self.spi.write(&[register, value]).await
+    }
+}
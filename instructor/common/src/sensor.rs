@@ -0,0 +1,606 @@
+//! The sampling loop of a device, shared by the firmware and the simulator.
+//!
+//! Each sensor gets a task running [`run`], which reads batches from a [`MotionSensor`], turns
+//! them into the [`Message`]s of the configured [`OutputMode`], runs the calibrations that are
+//! asked for and recovers the sensor when it stops responding. What depends on the system the
+//! loop runs on, such as time, storage and the bus, is left to a [`Host`].
+
+use core::str::FromStr;
+use core::time::Duration;
+
+use heapless::Vec;
+
+use crate::attitude::{Attitude, AttitudeEstimator};
+use crate::calibration::{Calibration, CalibrationStatus, Calibrator};
+use crate::health::{Health, SelfTest, SensorStatus};
+use crate::link;
+use crate::motion::MotionEvent;
+use crate::protocol::{Frame, MAX_SAMPLES};
+use crate::raw::{RawFormat, RawSample};
+use crate::{Loggable, Sample};
+
+/// How long a sensor has to be held still in each orientation while calibrating.
+const CALIBRATION_WINDOW: Duration = Duration::from_millis(500);
+
+/// A sensor producing a stream of 3-axis samples. The rest of the application only deals with
+/// this, so any accelerometer or IMU can take the place of the LIS3DH.
+// Only used by single-threaded executors, so the futures do not have to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait MotionSensor {
+    type Error: Loggable;
+
+    /// Write the complete configuration. Used at startup and to bring back a sensor that lost
+    /// its configuration.
+    async fn init(&mut self) -> Result<(), Self::Error>;
+
+    /// Change the output data rate to the lowest supported rate of at least `hz`, or the highest
+    /// supported rate. Returns the rate in use, in Hz.
+    async fn set_rate(&mut self, hz: f32) -> Result<f32, Self::Error>;
+
+    /// Change the accelerometer full scale to the smallest supported range of at least `g`, or
+    /// the largest supported range. Returns the range in use, in g.
+    async fn set_range(&mut self, g: f32) -> Result<f32, Self::Error>;
+
+    /// Wait for the next sample.
+    async fn sample(&mut self) -> Result<Sample, Self::Error>;
+
+    /// Wait for the next batch of samples, using the configured sampling mode.
+    async fn batch(&mut self) -> Result<Batch, Self::Error>;
+
+    /// Wait for the next batch of accelerometer readings in native counts, neither calibrated
+    /// nor filtered. The angular rate of an IMU is not included.
+    async fn raw_batch(&mut self) -> Result<RawBatch, Self::Error>;
+
+    /// How to scale the counts of [`MotionSensor::raw_batch`] with the current range and
+    /// calibration.
+    fn raw_format(&self) -> RawFormat;
+
+    /// Run the built-in self-test. The configuration is restored afterwards.
+    async fn self_test(&mut self) -> Result<SelfTest, Self::Error>;
+
+    /// Correct the acceleration of all following samples, before filtering. The filter starts
+    /// over, so it does not mix samples corrected differently.
+    fn set_calibration(&mut self, calibration: Calibration);
+}
+
+/// A run of consecutive samples read from the sensor in one go.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Batch {
+    /// Time since the device started when the first sample in the batch was taken.
+    pub start: Duration,
+    /// Time between two consecutive samples.
+    pub period: Duration,
+    pub samples: Vec<Sample, MAX_SAMPLES>,
+}
+
+impl Batch {
+    /// A batch holding a single sample taken at `start`.
+    pub fn single(sample: Sample, start: Duration, period: Duration) -> Self {
+        let mut samples = Vec::new();
+        let _ = samples.push(sample);
+        Self { start, period, samples }
+    }
+
+    /// Iterate over the samples together with their reconstructed timestamps.
+    pub fn timestamped(&self) -> impl Iterator<Item = (Duration, Sample)> + '_ {
+        self.samples
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (self.start + self.period * i as u32, *s))
+    }
+}
+
+/// A run of consecutive readings in native counts, see [`MotionSensor::raw_batch`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RawBatch {
+    /// Time since the device started when the first sample in the batch was taken.
+    pub start: Duration,
+    /// Time between two consecutive samples.
+    pub period: Duration,
+    pub samples: Vec<RawSample, MAX_SAMPLES>,
+}
+
+/// How the sampling loop reacts to a sensor that stops responding.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RecoveryPolicy {
+    /// Consecutive sampling failures before the bus is recovered and the sensor reconfigured.
+    pub failures_before_recovery: u32,
+    /// Recoveries without a successful sample in between before the sensor is reported faulty.
+    pub max_recoveries: u8,
+    /// Time between recovery attempts once the sensor is reported faulty.
+    pub fault_retry: Duration,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self {
+            failures_before_recovery: 3,
+            max_recoveries: 3,
+            fault_retry: Duration::from_secs(5),
+        }
+    }
+}
+
+/// What the device sends for each sensor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OutputMode {
+    /// The filtered acceleration vectors, and angular rates if the sensor has a gyroscope.
+    Vectors,
+    /// The orientation estimated on the device, once per batch.
+    Attitude,
+    /// Unfiltered accelerometer counts, half the size of vectors. The viewer scales and
+    /// calibrates them. The angular rate of an IMU is not sent.
+    ///
+    /// `compressed` delta encodes the batches, which roughly halves them again unless the sensor
+    /// moves fast.
+    Raw { compressed: bool },
+}
+
+impl FromStr for OutputMode {
+    type Err = &'static str;
+
+    /// Parses the names the simulator takes on its command line.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vectors" => Ok(OutputMode::Vectors),
+            "attitude" => Ok(OutputMode::Attitude),
+            "raw" => Ok(OutputMode::Raw { compressed: false }),
+            "compressed" => Ok(OutputMode::Raw { compressed: true }),
+            _ => Err("expected vectors, attitude, raw or compressed"),
+        }
+    }
+}
+
+/// What the sensor tasks produce, tagged with the index of the sensor.
+// Messages are queued by value on the device, which has no allocator to box the batches
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Message {
+    Samples { sensor: u8, batch: Batch },
+    Motion { sensor: u8, event: MotionEvent },
+    Health { sensor: u8, health: Health },
+    Calibration { sensor: u8, status: CalibrationStatus },
+    Attitude { sensor: u8, attitude: Attitude },
+    RawFormat { sensor: u8, format: RawFormat },
+    RawSamples { sensor: u8, batch: RawBatch, compressed: bool },
+}
+
+impl Message {
+    pub fn sensor(&self) -> u8 {
+        match self {
+            Message::Samples { sensor, .. }
+            | Message::Motion { sensor, .. }
+            | Message::Health { sensor, .. }
+            | Message::Calibration { sensor, .. }
+            | Message::Attitude { sensor, .. }
+            | Message::RawFormat { sensor, .. }
+            | Message::RawSamples { sensor, .. } => *sensor,
+        }
+    }
+
+    /// The frame telling the viewer about the message.
+    pub fn into_frame(self) -> Frame {
+        match self {
+            Message::Samples { batch, .. } => Frame::Samples(batch.samples),
+            Message::Motion { event, .. } => Frame::Motion(event),
+            Message::Health { health, .. } => Frame::Health(health),
+            Message::Calibration { status, .. } => Frame::Calibration(status),
+            Message::Attitude { attitude, .. } => Frame::Attitude(attitude),
+            Message::RawFormat { format, .. } => Frame::RawFormat(format),
+            Message::RawSamples { batch, compressed, .. } => link::raw_samples(batch.samples, compressed),
+        }
+    }
+}
+
+/// What [`run`] needs from the system it runs on, for one sensor.
+// Only used by single-threaded executors, so the futures do not have to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait Host {
+    type StorageError: Loggable;
+
+    /// Queue `message` for the connection to the viewer, waiting while the queue is full.
+    async fn send(&mut self, message: Message);
+
+    async fn sleep(&mut self, duration: Duration);
+
+    /// Whether a calibration was requested since the last call.
+    fn calibration_requested(&mut self) -> bool;
+
+    /// The stored calibration of the sensor, if it has been calibrated.
+    async fn load_calibration(&mut self) -> Option<Calibration>;
+
+    /// Keep `calibration` for the next time the sensor starts.
+    async fn store_calibration(&mut self, calibration: &Calibration) -> Result<(), Self::StorageError>;
+
+    /// Bring back the bus of a sensor that stopped responding. Buses that cannot get stuck have
+    /// nothing to do.
+    async fn recover_bus(&mut self);
+}
+
+/// A batch in the form the output mode needs.
+#[allow(clippy::large_enum_variant)]
+enum Readings {
+    Vectors(Batch),
+    Raw(RawBatch),
+}
+
+impl Readings {
+    fn period(&self) -> Duration {
+        match self {
+            Readings::Vectors(batch) => batch.period,
+            Readings::Raw(batch) => batch.period,
+        }
+    }
+
+    /// Feed the samples to a calibration, stopping at the first status change. Vectors are
+    /// filtered and calibrated already, so only raw readings are used.
+    fn feed(&self, calibrator: &mut Calibrator, format: &RawFormat) -> Option<CalibrationStatus> {
+        match self {
+            Readings::Vectors(_) => None,
+            Readings::Raw(batch) => batch.samples.iter().find_map(|s| calibrator.feed(format.uncalibrated(s))),
+        }
+    }
+
+    /// The readings as vectors. Raw readings are scaled and calibrated, but neither filtered nor
+    /// given an angular rate.
+    fn into_vectors(self, format: &RawFormat) -> Batch {
+        match self {
+            Readings::Vectors(batch) => batch,
+            Readings::Raw(batch) => Batch {
+                start: batch.start,
+                period: batch.period,
+                samples: batch.samples.iter().map(|s| format.to_sample(s)).collect(),
+            },
+        }
+    }
+}
+
+/// Forward samples from the sensor, recovering it according to `policy` when it stops
+/// responding. `health` is the result of the self-test at startup, already sent. Calibrations
+/// requested through the host are run on the raw readings, which are also forwarded while
+/// calibrating in place of the vectors.
+pub async fn run<S: MotionSensor, H: Host>(
+    sensor: u8,
+    mut xl: S,
+    mut host: H,
+    policy: RecoveryPolicy,
+    output: OutputMode,
+    mut health: Health,
+) {
+    let mut calibration = host.load_calibration().await.unwrap_or_default();
+    if calibration != Calibration::IDENTITY {
+        info!("xl{} using stored calibration {:?}", sensor, calibration);
+    }
+    xl.set_calibration(calibration);
+    let mut calibrator = None;
+    let mut attitude = AttitudeEstimator::default();
+    // The format last sent in raw mode
+    let mut format = None;
+
+    let mut failures = 0;
+    let mut recoveries = 0;
+    let raw_output = matches!(output, OutputMode::Raw { .. });
+    loop {
+        let readings = if raw_output || calibrator.is_some() {
+            xl.raw_batch().await.map(Readings::Raw)
+        } else {
+            xl.batch().await.map(Readings::Vectors)
+        };
+        match readings {
+            Ok(readings) => {
+                failures = 0;
+                recoveries = 0;
+                if health.status != SensorStatus::Ok {
+                    info!("xl{} recovered", sensor);
+                    health.status = SensorStatus::Ok;
+                    host.send(Message::Health { sensor, health }).await;
+                }
+
+                if host.calibration_requested() {
+                    let status = if calibrator.is_none() {
+                        info!("xl{} calibrating", sensor);
+                        // Averaging the raw readings over half a second per orientation
+                        let window = (CALIBRATION_WINDOW.as_micros() / readings.period().as_micros().max(1)) as u32;
+                        calibrator = Some(Calibrator::new(window));
+                        xl.set_calibration(Calibration::IDENTITY);
+                        CalibrationStatus::InProgress { captured: 0 }
+                    } else {
+                        warn!("xl{} calibration cancelled", sensor);
+                        calibrator = None;
+                        xl.set_calibration(calibration);
+                        CalibrationStatus::Failed
+                    };
+                    host.send(Message::Calibration { sensor, status }).await;
+                }
+                if let Some(status) = calibrator.as_mut().and_then(|c| readings.feed(c, &xl.raw_format())) {
+                    match status {
+                        CalibrationStatus::InProgress { captured } => {
+                            info!("xl{} calibration captured orientations {:06b}", sensor, captured);
+                        }
+                        CalibrationStatus::Done(result) => {
+                            info!("xl{} calibrated: {:?}", sensor, result);
+                            calibrator = None;
+                            calibration = result;
+                            if let Err(e) = host.store_calibration(&calibration).await {
+                                error!("Error storing xl{} calibration: {:?}", sensor, e);
+                            }
+                        }
+                        CalibrationStatus::Failed => {
+                            warn!("xl{} calibration failed, readings out of range", sensor);
+                            calibrator = None;
+                        }
+                    }
+                    if calibrator.is_none() {
+                        xl.set_calibration(calibration);
+                    }
+                    host.send(Message::Calibration { sensor, status }).await;
+                }
+
+                match readings {
+                    Readings::Raw(batch) if raw_output => {
+                        // Sent again whenever a calibration changes it
+                        let current = xl.raw_format();
+                        if format != Some(current) {
+                            format = Some(current);
+                            host.send(Message::RawFormat { sensor, format: current }).await;
+                        }
+                        let compressed = matches!(output, OutputMode::Raw { compressed: true });
+                        host.send(Message::RawSamples { sensor, batch, compressed }).await;
+                    }
+                    readings if output == OutputMode::Attitude => {
+                        let batch = readings.into_vectors(&xl.raw_format());
+                        let dt = batch.period.as_secs_f32();
+                        if let Some(latest) = batch.samples.iter().fold(None, |_, s| Some(attitude.update(s, dt))) {
+                            host.send(Message::Attitude { sensor, attitude: latest }).await;
+                        }
+                    }
+                    readings => {
+                        let batch = readings.into_vectors(&xl.raw_format());
+                        host.send(Message::Samples { sensor, batch }).await
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Error sampling xl{}: {:?}", sensor, e);
+                // Samples were lost, so the gyroscope integration is off
+                attitude.reset();
+                failures += 1;
+                if failures < policy.failures_before_recovery {
+                    continue;
+                }
+                failures = 0;
+
+                if recoveries < policy.max_recoveries {
+                    recoveries += 1;
+                    warn!("xl{} not responding, recovery attempt {}", sensor, recoveries);
+                    health.status = SensorStatus::Recovering { attempt: recoveries };
+                    host.send(Message::Health { sensor, health }).await;
+                } else {
+                    if health.status != SensorStatus::Fault {
+                        error!("xl{} recovery failed, retrying every {} ms", sensor, policy.fault_retry.as_millis() as u32);
+                        health.status = SensorStatus::Fault;
+                        host.send(Message::Health { sensor, health }).await;
+                    }
+                    host.sleep(policy.fault_retry).await;
+                }
+
+                host.recover_bus().await;
+                // The sensor may have been power cycled and lost its configuration
+                if let Err(e) = xl.init().await {
+                    warn!("Error configuring xl{}: {:?}", sensor, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+    use core::future::{pending, Future};
+    use core::pin::pin;
+    use core::task::{Context, Waker};
+
+    use super::*;
+
+    const POLICY: RecoveryPolicy = RecoveryPolicy {
+        failures_before_recovery: 2,
+        max_recoveries: 2,
+        fault_retry: Duration::from_secs(5),
+    };
+
+    /// What the sensor and the host were asked to do, in order. Samples sent are left out.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Event {
+        Init,
+        Health(SensorStatus),
+        Sleep(Duration),
+        RecoverBus,
+    }
+
+    type Events = RefCell<Vec<Event, 64>>;
+
+    fn record(events: &Events, event: Event) {
+        events.borrow_mut().push(event).expect("too many events");
+    }
+
+    /// A sensor that succeeds or fails each read as scripted, then never returns another batch.
+    struct Scripted<'a> {
+        reads: core::slice::Iter<'a, bool>,
+        events: &'a Events,
+    }
+
+    impl Scripted<'_> {
+        async fn read(&mut self) -> Result<(), ()> {
+            match self.reads.next() {
+                Some(true) => Ok(()),
+                Some(false) => Err(()),
+                None => pending().await,
+            }
+        }
+    }
+
+    impl MotionSensor for Scripted<'_> {
+        type Error = ();
+
+        async fn init(&mut self) -> Result<(), ()> {
+            record(self.events, Event::Init);
+            Ok(())
+        }
+
+        async fn set_rate(&mut self, hz: f32) -> Result<f32, ()> {
+            Ok(hz)
+        }
+
+        async fn set_range(&mut self, g: f32) -> Result<f32, ()> {
+            Ok(g)
+        }
+
+        async fn sample(&mut self) -> Result<Sample, ()> {
+            self.read().await.map(|_| Sample::new(0.0, 0.0, 1.0))
+        }
+
+        async fn batch(&mut self) -> Result<Batch, ()> {
+            let sample = self.sample().await?;
+            Ok(Batch::single(sample, Duration::ZERO, Duration::from_millis(10)))
+        }
+
+        async fn raw_batch(&mut self) -> Result<RawBatch, ()> {
+            self.read().await?;
+            let mut samples = Vec::new();
+            let _ = samples.push(RawSample { x: 0, y: 0, z: 16384 });
+            Ok(RawBatch { start: Duration::ZERO, period: Duration::from_millis(10), samples })
+        }
+
+        fn raw_format(&self) -> RawFormat {
+            RawFormat { scale: 1.0 / 16384.0, resolution: 12, calibration: Calibration::IDENTITY }
+        }
+
+        async fn self_test(&mut self) -> Result<SelfTest, ()> {
+            Err(())
+        }
+
+        fn set_calibration(&mut self, _calibration: Calibration) {}
+    }
+
+    /// A host recording the health reports, sleeps and bus recoveries.
+    struct Recorder<'a> {
+        events: &'a Events,
+    }
+
+    impl Host for Recorder<'_> {
+        type StorageError = ();
+
+        async fn send(&mut self, message: Message) {
+            if let Message::Health { health, .. } = message {
+                record(self.events, Event::Health(health.status));
+            }
+        }
+
+        async fn sleep(&mut self, duration: Duration) {
+            record(self.events, Event::Sleep(duration));
+        }
+
+        fn calibration_requested(&mut self) -> bool {
+            false
+        }
+
+        async fn load_calibration(&mut self) -> Option<Calibration> {
+            None
+        }
+
+        async fn store_calibration(&mut self, _calibration: &Calibration) -> Result<(), ()> {
+            Ok(())
+        }
+
+        async fn recover_bus(&mut self) {
+            record(self.events, Event::RecoverBus);
+        }
+    }
+
+    /// Run the sampling loop on the scripted reads until they run out.
+    fn run_script(reads: &[bool], status: SensorStatus) -> Vec<Event, 64> {
+        let events = Events::default();
+        {
+            let sensor = Scripted { reads: reads.iter(), events: &events };
+            let health = Health {
+                self_test: SelfTest {
+                    who_am_i: 0x33,
+                    st0: Sample::new(0.0, 0.0, 0.0),
+                    st1: Sample::new(0.0, 0.0, 0.0),
+                },
+                status,
+            };
+            let run = pin!(run(0, sensor, Recorder { events: &events }, POLICY, OutputMode::Vectors, health));
+            // Nothing the fakes do waits, so a single poll runs through the whole script
+            assert!(run.poll(&mut Context::from_waker(Waker::noop())).is_pending());
+        }
+        events.into_inner()
+    }
+
+    /// What one recovery attempt does.
+    fn recovery(attempt: u8) -> [Event; 3] {
+        [Event::Health(SensorStatus::Recovering { attempt }), Event::RecoverBus, Event::Init]
+    }
+
+    /// What one retry of a faulty sensor does.
+    const RETRY: [Event; 3] = [Event::Sleep(POLICY.fault_retry), Event::RecoverBus, Event::Init];
+
+    fn concat(parts: &[&[Event]]) -> Vec<Event, 64> {
+        let mut events = Vec::new();
+        for part in parts {
+            events.extend_from_slice(part).expect("too many events");
+        }
+        events
+    }
+
+    #[test]
+    fn failures_below_threshold_are_tolerated() {
+        let events = run_script(&[false, true, false, true, true, false], SensorStatus::Ok);
+        assert_eq!(events, []);
+    }
+
+    #[test]
+    fn consecutive_failures_recover_the_sensor() {
+        let events = run_script(&[false, false, false, false], SensorStatus::Ok);
+        assert_eq!(events, concat(&[&recovery(1), &recovery(2)]));
+    }
+
+    #[test]
+    fn fault_after_max_recoveries() {
+        let events = run_script(&[false; 10], SensorStatus::Ok);
+        // The fault is reported once, then retried at the slower pace
+        let fault = [Event::Health(SensorStatus::Fault)];
+        assert_eq!(events, concat(&[&recovery(1), &recovery(2), &fault, &RETRY, &RETRY, &RETRY]));
+    }
+
+    #[test]
+    fn success_resets_the_counters() {
+        // The failure before each success does not count towards the next recovery, and the
+        // recovery attempts start over
+        let reads = [false, false, false, true, false, false, false, true, false, false];
+        let events = run_script(&reads, SensorStatus::Ok);
+        let recovered = [Event::Health(SensorStatus::Ok)];
+        assert_eq!(events, concat(&[&recovery(1), &recovered, &recovery(1), &recovered, &recovery(1)]));
+    }
+
+    #[test]
+    fn recovery_from_fault_is_reported() {
+        let reads = [false, false, false, false, false, false, false, false, true, true];
+        let events = run_script(&reads, SensorStatus::Ok);
+        let fault = [Event::Health(SensorStatus::Fault)];
+        let recovered = [Event::Health(SensorStatus::Ok)];
+        assert_eq!(events, concat(&[&recovery(1), &recovery(2), &fault, &RETRY, &RETRY, &recovered]));
+    }
+
+    #[test]
+    fn failed_self_test_is_reported_recovered() {
+        let events = run_script(&[true, true], SensorStatus::Recovering { attempt: 1 });
+        assert_eq!(events, [Event::Health(SensorStatus::Ok)]);
+    }
+}
@@ -0,0 +1,147 @@
+//! The tilt computed on the device, and by the viewer from the last sample of each frame with
+//! `Attitude::from_sample`, is checked against a double precision reference after going over
+//! the wire.
+
+use heapless::Vec;
+use workshop_common::attitude::{Attitude, AttitudeEstimator};
+use workshop_common::protocol::{Frame, Header, HEADER_LEN, MAX_FRAME_LEN};
+use workshop_common::{AngularRate, Sample};
+
+const DT: f32 = 1.0 / 400.0;
+
+// The micromath approximations are accurate to about a degree
+const TOLERANCE: f64 = 0.02;
+
+/// Gravity vectors all around the sphere, scaled to include readings off 1 g.
+fn samples() -> impl Iterator<Item = Sample> {
+    (0..24).flat_map(|i| {
+        (0..12).flat_map(move |j| {
+            let azimuth = i as f64 * std::f64::consts::TAU / 24.0;
+            let elevation = (j as f64 - 5.5) * std::f64::consts::PI / 12.0;
+            [0.5, 1.0, 1.7].map(|g| {
+                Sample::new(
+                    (g * elevation.cos() * azimuth.cos()) as f32,
+                    (g * elevation.cos() * azimuth.sin()) as f32,
+                    (g * elevation.sin()) as f32,
+                )
+            })
+        })
+    })
+}
+
+fn round_trip(frame: &Frame) -> Frame {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = frame.encode(&mut buf).unwrap();
+    let header = Header::decode(buf[..HEADER_LEN].try_into().unwrap());
+    Frame::decode(header.tag, &buf[HEADER_LEN..len]).unwrap()
+}
+
+/// Difference between two angles, modulo a full turn since ±π are the same roll.
+fn angle_error(angle: f32, expected: f64) -> f64 {
+    ((angle as f64 - expected + std::f64::consts::PI).rem_euclid(std::f64::consts::TAU) - std::f64::consts::PI).abs()
+}
+
+/// Check the roll and pitch of `attitude` against a double precision reference computed from the
+/// direction of gravity in `sample`.
+fn assert_matches_reference(attitude: &Attitude, sample: &Sample) {
+    let (roll, pitch, yaw) = attitude.euler();
+    let (x, y, z) = (sample.x as f64, sample.y as f64, sample.z as f64);
+    let expected_roll = y.atan2(z);
+    let expected_pitch = (-x).atan2((y * y + z * z).sqrt());
+
+    assert!(angle_error(roll, expected_roll) < TOLERANCE, "roll {} expected {} for {:?}", roll, expected_roll, sample);
+    assert!((pitch as f64 - expected_pitch).abs() < TOLERANCE, "pitch {} expected {} for {:?}", pitch, expected_pitch, sample);
+    assert_eq!(yaw, 0.0);
+}
+
+#[test]
+fn tilt_matches_reference() {
+    for sample in samples() {
+        assert_matches_reference(&Attitude::from_sample(&sample), &sample);
+    }
+}
+
+#[test]
+fn tilt_of_axes() {
+    use std::f64::consts::{FRAC_PI_2, PI};
+    let cases = [
+        // Lying flat, on its side both ways, standing on either end and upside down
+        (Sample::new(0.0, 0.0, 1.0), 0.0, 0.0),
+        (Sample::new(0.0, 1.0, 0.0), FRAC_PI_2, 0.0),
+        (Sample::new(0.0, -1.0, 0.0), -FRAC_PI_2, 0.0),
+        (Sample::new(-1.0, 0.0, 0.0), 0.0, FRAC_PI_2),
+        (Sample::new(1.0, 0.0, 0.0), 0.0, -FRAC_PI_2),
+        (Sample::new(0.0, 0.0, -1.0), PI, 0.0),
+    ];
+    for (sample, expected_roll, expected_pitch) in cases {
+        let (roll, pitch, _) = Attitude::from_sample(&sample).euler();
+        assert!(angle_error(roll, expected_roll) < TOLERANCE, "roll {} expected {} for {:?}", roll, expected_roll, sample);
+        assert!(angle_error(pitch, expected_pitch) < TOLERANCE, "pitch {} expected {} for {:?}", pitch, expected_pitch, sample);
+    }
+}
+
+/// The tilt sent by the device, and the tilt the viewer computes from the samples, both after
+/// going over the wire.
+#[test]
+fn device_and_viewer_tilt_match_reference() {
+    for sample in samples() {
+        let mut estimator = AttitudeEstimator::default();
+        let Frame::Attitude(device) = round_trip(&Frame::Attitude(estimator.update(&sample, DT))) else {
+            panic!("not an attitude frame");
+        };
+        assert_matches_reference(&device, &sample);
+
+        let Frame::Samples(received) = round_trip(&Frame::Samples(Vec::from_slice(&[sample]).unwrap())) else {
+            panic!("not a samples frame");
+        };
+        assert_matches_reference(&Attitude::from_sample(received.last().unwrap()), &sample);
+    }
+}
+
+fn with_rate(sample: Sample, x: f32, y: f32, z: f32) -> Sample {
+    Sample { gyro: Some(AngularRate { x, y, z }), ..sample }
+}
+
+#[test]
+fn quaternion_starts_from_tilt() {
+    for sample in samples() {
+        let mut estimator = AttitudeEstimator::default();
+        let (roll, pitch, yaw) = estimator.update(&with_rate(sample, 0.0, 0.0, 0.0), DT).euler();
+        let (tilt_roll, tilt_pitch, _) = Attitude::from_sample(&sample).euler();
+        // Away from gimbal lock, where roll and yaw are not separable and asin is least accurate
+        if tilt_pitch.abs() < 1.2 {
+            assert!((roll - tilt_roll).abs() < TOLERANCE as f32 || (roll - tilt_roll).abs() > 6.27, "roll {} tilt {}", roll, tilt_roll);
+            assert!(yaw.abs() < TOLERANCE as f32, "yaw {}", yaw);
+            assert!((pitch - tilt_pitch).abs() < TOLERANCE as f32, "pitch {} tilt {}", pitch, tilt_pitch);
+        }
+    }
+}
+
+#[test]
+fn quaternion_integrates_yaw() {
+    let level = Sample::new(0.0, 0.0, 1.0);
+    let mut estimator = AttitudeEstimator::default();
+    let mut attitude = estimator.update(&with_rate(level, 0.0, 0.0, 0.0), DT);
+    // 90°/s about z for one second
+    for _ in 0..400 {
+        attitude = estimator.update(&with_rate(level, 0.0, 0.0, 90.0), DT);
+    }
+    let (roll, pitch, yaw) = attitude.euler();
+    assert!((yaw - std::f32::consts::FRAC_PI_2).abs() < 0.02, "yaw {}", yaw);
+    assert!(roll.abs() < 0.01 && pitch.abs() < 0.01, "roll {} pitch {}", roll, pitch);
+}
+
+#[test]
+fn quaternion_corrects_gyro_drift() {
+    let tilted = Sample::new(0.0, 0.5, 0.866);
+    let mut estimator = AttitudeEstimator::default();
+    estimator.update(&with_rate(Sample::new(0.0, 0.0, 1.0), 0.0, 0.0, 0.0), DT);
+    // A stationary, tilted sensor with a biased gyroscope
+    let mut attitude = None;
+    for _ in 0..4000 {
+        attitude = Some(estimator.update(&with_rate(tilted, 0.5, 0.0, 0.0), DT));
+    }
+    let (roll, _, _) = attitude.unwrap().euler();
+    let (expected, _, _) = Attitude::from_sample(&tilted).euler();
+    assert!((roll - expected).abs() < 0.05, "roll {} expected {}", roll, expected);
+}
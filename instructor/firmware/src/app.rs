@@ -1,10 +1,11 @@
 use crate::{net, xl};
 use static_cell::StaticCell;
-use embedded_io_async::Write;
+use embedded_io_async::{Read, Write};
 use core::net::{SocketAddr, Ipv4Addr, IpAddr};
 use embedded_nal_async::TcpConnect as _;
 use embassy_time::Timer;
 use defmt::*;
+use workshop_common::link::{self, Cache};
 
 pub struct App {
     tcp: net::Client,
@@ -27,15 +28,16 @@ pub fn init(stream: xl::SampleStream, net: net::Net) -> App {
 pub async fn run(app: App) {
     let App {
         tcp,
-        stream
+        mut stream
     } = app;
     let remote = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 8080);
+    let mut cache = Cache::<{ xl::MAX_SENSORS }>::default();
     loop {
         match tcp.connect(remote).await {
             Ok(connection) => {
                 info!("Connected to {:?}. Forwarding stream...", remote);
 
-                if let Err(e) = forward(stream, connection).await {
+                if let Err(e) = link::forward(Viewer(connection), &mut stream, &mut cache).await {
                     warn!("Error while forwarding stream: {:?}", e);
                 }
             }
@@ -47,14 +49,17 @@ pub async fn run(app: App) {
     }
 }
 
-async fn forward(stream: xl::SampleStream, mut conn: net::Connection<'_>) -> Result<(), net::Error> {
-    loop {
-        let sample = stream.receive().await;
-        info!("Forwarding sample: {:?}", sample);
+/// The connection to the viewer.
+struct Viewer<'a>(net::Connection<'a>);
+
+impl link::Connection for Viewer<'_> {
+    type Error = net::Error;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, net::Error> {
+        self.0.read(buf).await
+    }
 
-        let (x, y, z) = (sample.x.to_le_bytes(), sample.y.to_le_bytes(), sample.z.to_le_bytes());
-        conn.write_all(&x).await?;
-        conn.write_all(&y).await?;
-        conn.write_all(&z).await?;
+    async fn write_all(&mut self, buf: &[u8]) -> Result<(), net::Error> {
+        self.0.write_all(buf).await
     }
 }
@@ -1,5 +1,10 @@
 use assign_resources::assign_resources;
-use embassy_stm32::exti;
+use embassy_stm32::exti::{self, ExtiInput};
+use embassy_stm32::gpio::{Level, Output, OutputOpenDrain, Pull, Speed};
+use embassy_stm32::i2c::{I2c, Master};
+use embassy_stm32::mode::Async;
+use embassy_stm32::spi::{self, Spi};
+use lis3dh_async::SlaveAddr;
 use embassy_stm32::rcc::{
     AHBPrescaler, APBPrescaler, Hse, HseMode, Pll, PllDiv, PllMul, PllPreDiv, PllSource, Sysclk,
     VoltageScale,
@@ -7,6 +12,9 @@ use embassy_stm32::rcc::{
 use embassy_stm32::time::Hertz;
 use embassy_stm32::{bind_interrupts, eth, i2c, peripherals, rng, Config, Peri};
 
+use crate::bus::{BusPins, RecoverableI2c};
+use crate::xl;
+
 assign_resources! {
     net: NetResources {
         eth: ETH,
@@ -22,20 +30,115 @@ assign_resources! {
         pg11: PG11,
         rng: RNG,
     }
-    xl: XlResources {
+    xl_bus: XlBusResources {
         i2c1: I2C1,
         scl: PB8,
         sda: PB9,
-        exti: EXTI5,
-        irq: PA5,
         dma1: GPDMA1_CH4,
         dma2: GPDMA1_CH5,
     }
+    xl_spi: XlSpiResources {
+        spi3: SPI3,
+        sck: PC10,
+        miso: PC11,
+        mosi: PC12,
+        cs: PD14,
+        dma1: GPDMA1_CH6,
+        dma2: GPDMA1_CH7,
+    }
+    xl: XlResources {
+        exti: EXTI5,
+        irq: PA5,
+        exti2: EXTI6,
+        irq2: PA6,
+    }
+    storage: StorageResources {
+        flash: FLASH,
+    }
+    button: ButtonResources {
+        exti: EXTI13,
+        pin: PC13,
+    }
 }
 
 pub struct Board {
     pub net: NetResources,
-    pub xl: XlResources,
+    pub xl: xl::Resources,
+    pub flash: Peri<'static, peripherals::FLASH>,
+    /// The blue user button, high while pressed.
+    pub button: ExtiInput<'static>,
+}
+
+// Bus recovery for the accelerometer bus on I2C1
+const XL_BUS: BusPins = BusPins {
+    i2c: xl_bus_i2c,
+    gpio: xl_bus_gpio,
+};
+
+unsafe fn xl_bus_i2c() -> I2c<'static, Async, Master> {
+    unsafe {
+        I2c::new(
+            peripherals::I2C1::steal(),
+            peripherals::PB8::steal(),
+            peripherals::PB9::steal(),
+            Irqs,
+            peripherals::GPDMA1_CH4::steal(),
+            peripherals::GPDMA1_CH5::steal(),
+            Default::default(),
+        )
+    }
+}
+
+unsafe fn xl_bus_gpio() -> (OutputOpenDrain<'static>, OutputOpenDrain<'static>) {
+    unsafe {
+        (
+            OutputOpenDrain::new(peripherals::PB8::steal(), Level::High, Speed::Low),
+            OutputOpenDrain::new(peripherals::PB9::steal(), Level::High, Speed::Low),
+        )
+    }
+}
+
+/// How the accelerometer is wired on this board.
+#[allow(dead_code)]
+enum XlTransport {
+    /// I2C1 on the Arduino header.
+    I2c,
+    /// SPI3, with chip select on PD14, as on carrier boards streaming at high rates.
+    Spi,
+}
+
+const XL_TRANSPORT: XlTransport = XlTransport::I2c;
+
+/// The motion sensors on the board. An LSM6DSO is added with `xl::SensorKind::Lsm6dso`. A second
+/// LIS3DH with SA0 pulled high shares the I2C bus by adding a sensor with `SlaveAddr::Alternate`;
+/// sensors on another peripheral need a bus of their own. Every sensor needs its own INT1 pin.
+fn xl_resources(i2c: XlBusResources, spi: XlSpiResources, pins: XlResources) -> xl::Resources {
+    let mut resources = xl::Resources::default();
+
+    let bus = match XL_TRANSPORT {
+        XlTransport::I2c => {
+            let i2c = I2c::new(i2c.i2c1, i2c.scl, i2c.sda, Irqs, i2c.dma1, i2c.dma2, Default::default());
+            let _ = resources.i2c_buses.push(RecoverableI2c::new(i2c, XL_BUS));
+            xl::SensorBus::I2c { bus: 0, address: SlaveAddr::Default }
+        }
+        XlTransport::Spi => {
+            // The LIS3DH supports up to 10 MHz in mode 3
+            let mut config = spi::Config::default();
+            config.frequency = Hertz(8_000_000);
+            config.mode = spi::MODE_3;
+            let spi3 = Spi::new(spi.spi3, spi.sck, spi.mosi, spi.miso, spi.dma1, spi.dma2, config);
+            let _ = resources.spi_buses.push(spi3);
+            xl::SensorBus::Spi { bus: 0, cs: Output::new(spi.cs, Level::High, Speed::VeryHigh) }
+        }
+    };
+
+    let _ = resources.sensors.push(xl::SensorResources {
+        kind: xl::SensorKind::Lis3dh,
+        bus,
+        irq: ExtiInput::new(pins.irq, pins.exti, Pull::None, Irqs),
+        irq2: Some(ExtiInput::new(pins.irq2, pins.exti2, Pull::None, Irqs)),
+    });
+    resources
 }
 
 bind_interrupts!(pub struct Irqs {
@@ -44,6 +147,8 @@ bind_interrupts!(pub struct Irqs {
     ETH => eth::InterruptHandler;
     RNG => rng::InterruptHandler<peripherals::RNG>;
     EXTI5 => exti::InterruptHandler<embassy_stm32::interrupt::typelevel::EXTI5>;
+    EXTI6 => exti::InterruptHandler<embassy_stm32::interrupt::typelevel::EXTI6>;
+    EXTI13 => exti::InterruptHandler<embassy_stm32::interrupt::typelevel::EXTI13>;
 });
 
 pub fn init() -> Board {
@@ -74,6 +179,8 @@ pub fn init() -> Board {
 
     Board {
         net: r.net,
-        xl: r.xl,
+        xl: xl_resources(r.xl_bus, r.xl_spi, r.xl),
+        flash: r.storage.flash,
+        button: ExtiInput::new(r.button.pin, r.button.exti, Pull::Down, Irqs),
     }
 }
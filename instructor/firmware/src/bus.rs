@@ -0,0 +1,110 @@
+use embassy_stm32::gpio::OutputOpenDrain;
+use embassy_stm32::i2c::{self, I2c, Master};
+use embassy_stm32::mode::Async;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::Timer;
+use embedded_hal_async::i2c::{ErrorType, Operation};
+use embedded_hal_async::spi;
+
+/// Takes over the peripherals of an I2C bus, for recovering it.
+///
+/// Both functions steal the peripherals, so they may only be called once the driver created
+/// from them, and anything created by the other function, has been dropped.
+#[derive(Clone, Copy)]
+pub struct BusPins {
+    /// Create the I2C driver.
+    pub i2c: unsafe fn() -> I2c<'static, Async, Master>,
+    /// SCL and SDA as open-drain outputs, initially high.
+    pub gpio: unsafe fn() -> (OutputOpenDrain<'static>, OutputOpenDrain<'static>),
+}
+
+/// An I2C bus, which can be recovered when a device is stuck holding SDA low.
+///
+/// A device that lost track of a transfer, for example after a reset of the MCU in the middle of
+/// a read, keeps driving SDA until it has clocked out the rest of its byte. The I2C peripheral
+/// cannot generate those clocks, so recovery temporarily takes over the pins.
+pub struct RecoverableI2c {
+    // Only `None` while the bus is being recovered
+    i2c: Option<I2c<'static, Async, Master>>,
+    pins: BusPins,
+}
+
+impl RecoverableI2c {
+    pub fn new(i2c: I2c<'static, Async, Master>, pins: BusPins) -> Self {
+        Self { i2c: Some(i2c), pins }
+    }
+
+    /// Clock out any partial transfer, generate a STOP condition and reinitialize the peripheral.
+    pub async fn recover(&mut self) {
+        // Dropping the driver releases the pins and disables the peripheral
+        self.i2c = None;
+
+        // SAFETY: the pins and peripherals were owned by the dropped driver and are only used here
+        // until the driver is created again.
+        let (mut scl, mut sda) = unsafe { (self.pins.gpio)() };
+
+        // At most 9 clocks are needed to finish a byte and its acknowledge bit
+        for _ in 0..9 {
+            if sda.is_high() {
+                break;
+            }
+            scl.set_low();
+            Timer::after_micros(5).await;
+            scl.set_high();
+            Timer::after_micros(5).await;
+        }
+
+        // STOP: SDA rising while SCL is high. SCL is still high after the clocks, so it is pulled
+        // low first, or pulling SDA low would be a START.
+        scl.set_low();
+        Timer::after_micros(5).await;
+        sda.set_low();
+        Timer::after_micros(5).await;
+        scl.set_high();
+        Timer::after_micros(5).await;
+        sda.set_high();
+        Timer::after_micros(5).await;
+
+        drop(scl);
+        drop(sda);
+
+        // SAFETY: see above, nothing else is using these any more.
+        self.i2c = Some(unsafe { (self.pins.i2c)() });
+    }
+}
+
+impl ErrorType for RecoverableI2c {
+    type Error = i2c::Error;
+}
+
+impl embedded_hal_async::i2c::I2c for RecoverableI2c {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        match &mut self.i2c {
+            Some(i2c) => i2c.transaction(address, operations).await,
+            None => Err(i2c::Error::Bus),
+        }
+    }
+}
+
+/// A SPI device with several handles, for a driver and other code both talking to one chip
+/// select. Transactions from different handles do not interleave.
+pub struct SharedSpiDevice<D: 'static> {
+    device: &'static Mutex<NoopRawMutex, D>,
+}
+
+impl<D> SharedSpiDevice<D> {
+    pub fn new(device: &'static Mutex<NoopRawMutex, D>) -> Self {
+        Self { device }
+    }
+}
+
+impl<D: spi::ErrorType> spi::ErrorType for SharedSpiDevice<D> {
+    type Error = D::Error;
+}
+
+impl<D: spi::SpiDevice> spi::SpiDevice for SharedSpiDevice<D> {
+    async fn transaction(&mut self, operations: &mut [spi::Operation<'_, u8>]) -> Result<(), Self::Error> {
+        self.device.lock().await.transaction(operations).await
+    }
+}
@@ -0,0 +1,333 @@
+use embedded_hal_async::i2c::I2c;
+use embedded_hal_async::spi::SpiDevice;
+use embedded_hal_async::digital::Wait;
+use embedded_hal::digital::InputPin;
+use lis3dh_async::{Lis3dh, Lis3dhCore, Lis3dhI2C, Lis3dhSPI, SlaveAddr, Configuration, Interrupt1, InterruptMode, Mode, DataRate, Register, InterruptConfig, IrqPin1Config, Error};
+use embassy_time::{Delay, Duration, Instant};
+use heapless::Vec;
+
+use crate::sensor::{self, Batch, Config, I2cRegisters, MotionConfig, MotionSensor, RawBatch, Registers, SampleError, Sampling, SpiRegisters, Timeline};
+use workshop_common::Sample;
+use workshop_common::calibration::Calibration;
+use workshop_common::filter::{Filter, LowpassFilter};
+use workshop_common::health::SelfTest;
+use workshop_common::lis3dh::{Output, RESOLUTION};
+use workshop_common::motion::{Axis, MotionEvent, Sign};
+use workshop_common::raw::{RawFormat, RawSample};
+
+// Register bits used for motion detection on INT2
+const CTRL2_HPCLICK: u8 = 1 << 2;
+const CTRL5_LIR_INT2: u8 = 1 << 1;
+const CTRL6_I2_CLICK: u8 = 1 << 7;
+const CTRL6_I2_IA2: u8 = 1 << 5;
+const CTRL6_I2_ACT: u8 = 1 << 3;
+// Single and double click on all axes
+const CLICK_CFG_ALL: u8 = 0b0011_1111;
+const CLICK_THS_LIR: u8 = 1 << 7;
+const CLICK_SRC_IA: u8 = 1 << 6;
+const CLICK_SRC_DCLICK: u8 = 1 << 5;
+const CLICK_SRC_SIGN: u8 = 1 << 3;
+const CLICK_SRC_Z: u8 = 1 << 2;
+const CLICK_SRC_Y: u8 = 1 << 1;
+// AND combination of the low events on all axes, which is how free-fall is detected
+const INT_CFG_FREE_FALL: u8 = 0b1001_0101;
+const INT_SRC_IA: u8 = 1 << 6;
+
+/// Register access to a LIS3DH on an I2C bus, next to the driver.
+pub fn i2c_registers<I: I2c>(i2c: I, address: SlaveAddr) -> I2cRegisters<I> {
+    workshop_common::lis3dh::i2c_registers(i2c, matches!(address, SlaveAddr::Alternate))
+}
+
+/// Register access to a LIS3DH on a SPI bus, next to the driver.
+pub fn spi_registers<S: SpiDevice>(spi: S) -> SpiRegisters<S> {
+    workshop_common::lis3dh::spi_registers(spi)
+}
+
+/// A LIS3DH, accessed through the driver over either I2C (`Lis3dhI2C`) or SPI (`Lis3dhSPI`), and
+/// through `bus`, a second handle to the same sensor. Sampling is configured, the self-test run
+/// and the FIFO read through `bus` by [`workshop_common::lis3dh`], the same as in the simulator.
+pub struct Accel<C: Lis3dhCore, B, IRQ: Wait + InputPin> {
+    xl: Lis3dh<C>,
+    bus: B,
+    irq: IRQ,
+    calibration: Calibration,
+    filter: LowpassFilter,
+    config: Config,
+    output: Output,
+    period: Duration,
+    timeline: Timeline,
+}
+
+// Configuration the driver starts with, before `Accel::configure` sets up sampling
+fn driver_config() -> Configuration {
+    Configuration {
+        mode: Mode::HighResolution,
+        datarate: DataRate::PowerDown,
+        ..Configuration::default()
+    }
+}
+
+impl<I: I2c, IRQ: Wait + InputPin> Accel<Lis3dhI2C<I>, I2cRegisters<I>, IRQ> {
+    /// Configure a sensor on an I2C bus. `i2c` is used by the driver, while `bus` is a second
+    /// handle to the same bus used for burst-reading the FIFO.
+    pub async fn new_i2c(i2c: I, bus: I, irq: IRQ, address: SlaveAddr, config: Config) -> Result<Self, Error<I::Error>> {
+        let xl = Lis3dh::new_i2c_with_config(i2c, address, driver_config()).await?;
+        Self::new(xl, i2c_registers(bus, address), irq, config).await
+    }
+}
+
+impl<S: SpiDevice, IRQ: Wait + InputPin> Accel<Lis3dhSPI<S>, SpiRegisters<S>, IRQ> {
+    /// Configure a sensor on a SPI bus. `spi` is used by the driver, while `bus` is a second
+    /// handle to the same device used for burst-reading the FIFO.
+    pub async fn new_spi(spi: S, bus: S, irq: IRQ, config: Config) -> Result<Self, Error<S::Error>> {
+        let xl = Lis3dh::new_spi_with_config(spi, driver_config()).await?;
+        Self::new(xl, spi_registers(bus), irq, config).await
+    }
+}
+
+impl<C, B, IRQ> Accel<C, B, IRQ>
+where
+    C: Lis3dhCore,
+    B: Registers<Error = C::BusError>,
+    IRQ: Wait + InputPin,
+{
+    async fn new(xl: Lis3dh<C>, bus: B, irq: IRQ, config: Config) -> Result<Self, Error<C::BusError>> {
+        let output = Output::new(config.rate, config.range);
+        let mut accel = Self {
+            xl,
+            bus,
+            irq,
+            calibration: Calibration::IDENTITY,
+            filter: LowpassFilter::new(0.1), // Lower value -> smoother but more delay
+            config,
+            output,
+            period: period(output),
+            timeline: Timeline::new(period(output)),
+        };
+        accel.configure().await?;
+        Ok(accel)
+    }
+
+    /// Write the complete sensor configuration.
+    async fn configure(&mut self) -> Result<(), Error<C::BusError>> {
+        self.output = Output::new(self.config.rate, self.config.range);
+        self.period = period(self.output);
+        self.timeline.reset(self.period);
+
+        workshop_common::lis3dh::configure(&mut self.bus, self.output).await.map_err(Error::Bus)?;
+
+        match self.config.sampling {
+            Sampling::DataReady => {
+                self.xl.configure_irq_src(
+                    Interrupt1,
+                    InterruptMode::Position,
+                    InterruptConfig::high_and_low(),
+                ).await?;
+
+                // Raise pin state if interrupt 1 is raised and there is movement
+                self.xl.configure_interrupt_pin(IrqPin1Config {
+                    zyxda_en: true,
+                    ..IrqPin1Config::default()
+                }).await?;
+            }
+            Sampling::Fifo { watermark } => {
+                workshop_common::lis3dh::enable_fifo(&mut self.bus, watermark).await.map_err(Error::Bus)?;
+            }
+        }
+
+        if let Some(motion) = self.config.motion {
+            self.configure_motion(&motion).await?;
+        }
+        Ok(())
+    }
+
+    /// Configure click, free-fall and activity detection, all signalled on the INT2 pin.
+    async fn configure_motion(&mut self, config: &MotionConfig) -> Result<(), Error<C::BusError>> {
+        let threshold = |g: f32| ((g / self.output.threshold_step()) as u8).min(0x7F);
+        let ticks = |d: Duration, max: u8| (d.as_micros() / self.period.as_micros()).min(max as u64) as u8;
+
+        // Single and double tap, high-pass filtered so gravity does not count towards the threshold
+        let ctrl2 = self.xl.read_register(Register::CTRL2).await?;
+        self.xl.write_register(Register::CTRL2, ctrl2 | CTRL2_HPCLICK).await?;
+        self.xl.write_register(Register::CLICK_CFG, CLICK_CFG_ALL).await?;
+        self.xl.write_register(Register::CLICK_THS, CLICK_THS_LIR | threshold(config.tap_threshold)).await?;
+        self.xl.write_register(Register::TIME_LIMIT, ticks(config.tap_limit, 0x7F)).await?;
+        self.xl.write_register(Register::TIME_LATENCY, ticks(config.tap_latency, 0xFF)).await?;
+        self.xl.write_register(Register::TIME_WINDOW, ticks(config.tap_window, 0xFF)).await?;
+
+        // Free-fall on the second interrupt generator, latched until INT2_SRC is read
+        self.xl.write_register(Register::INT2_CFG, INT_CFG_FREE_FALL).await?;
+        self.xl.write_register(Register::INT2_THS, threshold(config.free_fall_threshold)).await?;
+        self.xl.write_register(Register::INT2_DURATION, ticks(config.free_fall_duration, 0x7F)).await?;
+        let ctrl5 = self.xl.read_register(Register::CTRL5).await?;
+        self.xl.write_register(Register::CTRL5, ctrl5 | CTRL5_LIR_INT2).await?;
+
+        // Inactivity, which keeps INT2 high until the sensor moves again. The duration is counted
+        // in units of 8 samples.
+        self.xl.write_register(Register::ACT_THS, threshold(config.inactivity_threshold)).await?;
+        let act_dur = (config.inactivity_duration.as_micros() / (self.period.as_micros() * 8)).min(0xFF) as u8;
+        self.xl.write_register(Register::ACT_DUR, act_dur).await?;
+
+        let ctrl6 = self.xl.read_register(Register::CTRL6).await?;
+        self.xl.write_register(Register::CTRL6, ctrl6 | CTRL6_I2_CLICK | CTRL6_I2_IA2 | CTRL6_I2_ACT).await?;
+        Ok(())
+    }
+
+    /// Run the built-in self-test. The configuration is restored afterwards.
+    async fn run_self_test(&mut self) -> Result<SelfTest, Error<C::BusError>> {
+        let self_test = workshop_common::lis3dh::self_test(&mut self.bus, &mut Delay).await.map_err(Error::Bus)?;
+        self.timeline.reset(self.period);
+
+        // The deflection may have been detected as motion, clear the latched sources
+        self.xl.read_register(Register::CLICK_SRC).await?;
+        self.xl.read_register(Register::INT2_SRC).await?;
+        Ok(self_test)
+    }
+
+    fn format(&self) -> RawFormat {
+        RawFormat {
+            scale: self.output.scale(),
+            resolution: RESOLUTION,
+            calibration: self.calibration,
+        }
+    }
+
+    /// Read all samples pending in the FIFO, in counts.
+    async fn read_fifo(&mut self) -> Result<RawBatch, SampleError<Error<C::BusError>>> {
+        sensor::wait_for_data(&mut self.irq, sensor::data_timeout(self.period, self.config.sampling)).await?;
+        let now = Instant::now();
+
+        let fifo = workshop_common::lis3dh::read_fifo(&mut self.bus).await.map_err(Error::Bus)?;
+        if fifo.overrun {
+            self.timeline.reset(self.period);
+        }
+
+        Ok(RawBatch {
+            start: self.timeline.start(now, fifo.samples.len()),
+            period: sensor::duration(self.period),
+            samples: fifo.samples.into_iter().collect(),
+        })
+    }
+
+    async fn drain_fifo(&mut self) -> Result<Batch, SampleError<Error<C::BusError>>> {
+        let raw = self.read_fifo().await?;
+        let format = self.format();
+        let mut samples = Vec::new();
+        for sample in &raw.samples {
+            let _ = samples.push(self.filter.apply(format.to_sample(sample)));
+        }
+
+        Ok(Batch {
+            start: raw.start,
+            period: raw.period,
+            samples,
+        })
+    }
+}
+
+impl<C, B, IRQ> MotionSensor for Accel<C, B, IRQ>
+where
+    C: Lis3dhCore,
+    C::BusError: defmt::Format,
+    B: Registers<Error = C::BusError>,
+    IRQ: Wait + InputPin,
+{
+    type Error = SampleError<Error<C::BusError>>;
+
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        Ok(self.configure().await?)
+    }
+
+    async fn set_rate(&mut self, hz: f32) -> Result<f32, Self::Error> {
+        self.config.rate = hz;
+        self.configure().await?;
+        Ok(self.output.rate())
+    }
+
+    async fn set_range(&mut self, g: f32) -> Result<f32, Self::Error> {
+        self.config.range = g;
+        self.configure().await?;
+        Ok(self.output.full_scale())
+    }
+
+    async fn sample(&mut self) -> Result<Sample, Self::Error> {
+        sensor::wait_for_data(&mut self.irq, sensor::data_timeout(self.period, self.config.sampling)).await?;
+        let raw_sample = self.xl.accel_norm().await?;
+        let raw_sample = Sample::new(raw_sample.x, raw_sample.y, raw_sample.z);
+        let filtered_sample = self.filter.apply(self.calibration.apply(raw_sample));
+        Ok(filtered_sample)
+    }
+
+    async fn batch(&mut self) -> Result<Batch, Self::Error> {
+        match self.config.sampling {
+            Sampling::DataReady => {
+                let sample = self.sample().await?;
+                Ok(Batch::single(sample, sensor::now(), sensor::duration(self.period)))
+            }
+            Sampling::Fifo { .. } => self.drain_fifo().await,
+        }
+    }
+
+    async fn raw_batch(&mut self) -> Result<RawBatch, Self::Error> {
+        match self.config.sampling {
+            Sampling::DataReady => {
+                sensor::wait_for_data(&mut self.irq, sensor::data_timeout(self.period, self.config.sampling)).await?;
+                let raw = self.xl.accel_raw().await?;
+                let mut samples = Vec::new();
+                let _ = samples.push(RawSample { x: raw.x, y: raw.y, z: raw.z });
+                Ok(RawBatch {
+                    start: sensor::now(),
+                    period: sensor::duration(self.period),
+                    samples,
+                })
+            }
+            Sampling::Fifo { .. } => self.read_fifo().await,
+        }
+    }
+
+    fn raw_format(&self) -> RawFormat {
+        self.format()
+    }
+
+    async fn self_test(&mut self) -> Result<SelfTest, Self::Error> {
+        Ok(self.run_self_test().await?)
+    }
+
+    fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+        self.filter.reset();
+    }
+}
+
+/// Read the latched click and free-fall sources, which also clears them.
+pub async fn read_motion_events<R: Registers>(bus: &mut R) -> Result<Vec<MotionEvent, 2>, R::Error> {
+    let mut events = Vec::new();
+
+    let click = bus.read_register(Register::CLICK_SRC.addr()).await?;
+    if click & CLICK_SRC_IA != 0 {
+        let axis = if click & CLICK_SRC_Z != 0 {
+            Axis::Z
+        } else if click & CLICK_SRC_Y != 0 {
+            Axis::Y
+        } else {
+            Axis::X
+        };
+        let sign = if click & CLICK_SRC_SIGN != 0 { Sign::Negative } else { Sign::Positive };
+        let event = if click & CLICK_SRC_DCLICK != 0 {
+            MotionEvent::DoubleTap { axis, sign }
+        } else {
+            MotionEvent::Tap { axis, sign }
+        };
+        let _ = events.push(event);
+    }
+
+    let int2 = bus.read_register(Register::INT2_SRC.addr()).await?;
+    if int2 & INT_SRC_IA != 0 {
+        let _ = events.push(MotionEvent::FreeFall);
+    }
+    Ok(events)
+}
+
+fn period(output: Output) -> Duration {
+    Duration::from_micros(output.period().as_micros() as u64)
+}
@@ -0,0 +1,447 @@
+//! Register-level driver for the LSM6DSO accelerometer and gyroscope.
+
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::i2c::I2c;
+use embedded_hal_async::spi::SpiDevice;
+use embedded_hal::digital::InputPin;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+use defmt::warn;
+
+use crate::sensor::{self, Batch, Config, I2cRegisters, MotionSensor, RawBatch, Registers, SampleError, Sampling, SpiRegisters, Timeline};
+use workshop_common::{AngularRate, Sample};
+use workshop_common::calibration::Calibration;
+use workshop_common::filter::{Filter, LowpassFilter};
+use workshop_common::health::SelfTest;
+use workshop_common::protocol::MAX_SAMPLES;
+use workshop_common::raw::{RawFormat, RawSample};
+
+/// I2C address of the LSM6DSO with SA0 pulled low.
+pub const ADDRESS: u8 = 0x6A;
+/// I2C address of the LSM6DSO with SA0 pulled high.
+pub const ADDRESS_ALTERNATE: u8 = 0x6B;
+
+// Registers, see the LSM6DSO datasheet section 8
+const FIFO_CTRL1: u8 = 0x07;
+const FIFO_CTRL2: u8 = 0x08;
+const FIFO_CTRL3: u8 = 0x09;
+const FIFO_CTRL4: u8 = 0x0A;
+const INT1_CTRL: u8 = 0x0D;
+const WHO_AM_I: u8 = 0x0F;
+const CTRL1_XL: u8 = 0x10;
+const CTRL2_G: u8 = 0x11;
+const CTRL3_C: u8 = 0x12;
+const CTRL5_C: u8 = 0x14;
+const STATUS_REG: u8 = 0x1E;
+const OUTX_L_G: u8 = 0x22;
+const OUTX_L_A: u8 = 0x28;
+const FIFO_STATUS1: u8 = 0x3A;
+const FIFO_DATA_OUT_TAG: u8 = 0x78;
+
+// Block data update, and auto-increment for multi-byte reads on both I2C and SPI
+const CTRL3_C_BDU_IF_INC: u8 = (1 << 6) | (1 << 2);
+const INT1_DRDY_XL: u8 = 1 << 0;
+const INT1_FIFO_TH: u8 = 1 << 3;
+const FIFO_MODE_BYPASS: u8 = 0b000;
+const FIFO_MODE_CONTINUOUS: u8 = 0b110;
+const FIFO_STATUS2_OVR: u8 = 1 << 6;
+const FIFO_STATUS2_DIFF_MASK: u8 = 0b11;
+const STATUS_XLDA: u8 = 1 << 0;
+// The gyroscope always runs at ±2000 dps, 70 mdps/LSB
+const CTRL2_G_FS_2000: u8 = 0b11 << 2;
+const GYRO_SCALE: f32 = 0.070;
+
+// FIFO words are a tag byte followed by x, y and z
+const FIFO_WORD_LEN: usize = 7;
+const TAG_GYRO: u8 = 0x01;
+const TAG_ACCEL: u8 = 0x02;
+// The tag counter is the same for the words of one time slot, and counts up from slot to slot
+const TAG_CNT_MASK: u8 = 0b11;
+// Every sample takes an accelerometer and a gyroscope word
+const MAX_FIFO_WORDS: usize = 2 * MAX_SAMPLES;
+const FIFO_LEN: usize = MAX_FIFO_WORDS * FIFO_WORD_LEN;
+// The accelerometer output uses all 16 bits
+const RESOLUTION: u8 = 16;
+
+// Self-test from the datasheet: 52 Hz, ±4g with the gyroscope off
+const SELF_TEST_CTRL1_XL: u8 = 0x38;
+const SELF_TEST_SCALE: f32 = 4.0 / 32768.0;
+const CTRL5_ST_XL_POSITIVE: u8 = 0b01;
+const CTRL5_ST_XL_NEGATIVE: u8 = 0b10;
+// Number of samples averaged for each self-test step
+const SELF_TEST_SAMPLES: usize = 5;
+
+// Output data rate codes, shared by the accelerometer, gyroscope and FIFO batching rates
+const DATA_RATES: [(u8, f32); 10] = [
+    (0b0001, 12.5),
+    (0b0010, 26.0),
+    (0b0011, 52.0),
+    (0b0100, 104.0),
+    (0b0101, 208.0),
+    (0b0110, 416.0),
+    (0b0111, 833.0),
+    (0b1000, 1666.0),
+    (0b1001, 3332.0),
+    (0b1010, 6664.0),
+];
+
+// Accelerometer full scale codes and ranges in g
+const RANGES: [(u8, f32); 4] = [(0b00, 2.0), (0b10, 4.0), (0b11, 8.0), (0b01, 16.0)];
+
+/// An LSM6DSO on an I2C bus.
+pub fn i2c<I: I2c, IRQ: Wait + InputPin>(i2c: I, address: u8, irq: IRQ, config: Config) -> Lsm6ds<I2cRegisters<I>, IRQ> {
+    Lsm6ds::new(I2cRegisters::new(i2c, address, 0), irq, config)
+}
+
+/// An LSM6DSO on a SPI bus.
+pub fn spi<S: SpiDevice, IRQ: Wait + InputPin>(spi: S, irq: IRQ, config: Config) -> Lsm6ds<SpiRegisters<S>, IRQ> {
+    Lsm6ds::new(SpiRegisters::new(spi, 0), irq, config)
+}
+
+pub struct Lsm6ds<R, IRQ> {
+    registers: R,
+    irq: IRQ,
+    calibration: Calibration,
+    filter: LowpassFilter,
+    config: Config,
+    // Indices into DATA_RATES and RANGES
+    rate: usize,
+    range: usize,
+    period: Duration,
+    timeline: Timeline,
+    // Time slot whose other word was still in the FIFO at the last read
+    slot: Option<Slot>,
+}
+
+/// The accelerometer and gyroscope words of one FIFO time slot, as far as they were read.
+#[derive(Clone, Copy)]
+struct Slot {
+    count: u8,
+    accel: Option<[u8; 6]>,
+    gyro: Option<[u8; 6]>,
+}
+
+impl<R: Registers, IRQ: Wait + InputPin> Lsm6ds<R, IRQ> {
+    /// Create the driver. The sensor is configured by [`MotionSensor::init`].
+    pub fn new(registers: R, irq: IRQ, config: Config) -> Self {
+        let period = Duration::from_secs(1);
+        Self {
+            registers,
+            irq,
+            calibration: Calibration::IDENTITY,
+            filter: LowpassFilter::new(0.1), // Lower value -> smoother but more delay
+            config,
+            rate: 0,
+            range: 0,
+            period,
+            timeline: Timeline::new(period),
+            slot: None,
+        }
+    }
+
+    async fn configure(&mut self) -> Result<(), R::Error> {
+        self.rate = DATA_RATES
+            .iter()
+            .position(|(_, hz)| *hz >= self.config.rate)
+            .unwrap_or(DATA_RATES.len() - 1);
+        self.range = RANGES
+            .iter()
+            .position(|(_, g)| *g >= self.config.range)
+            .unwrap_or(RANGES.len() - 1);
+        let (odr, hz) = DATA_RATES[self.rate];
+        self.period = Duration::from_micros((1_000_000.0 / hz) as u64);
+        self.timeline.reset(self.period);
+        self.slot = None;
+
+        let r = &mut self.registers;
+        r.write_register(CTRL3_C, CTRL3_C_BDU_IF_INC).await?;
+        r.write_register(CTRL5_C, 0).await?;
+        r.write_register(CTRL1_XL, (odr << 4) | (RANGES[self.range].0 << 2)).await?;
+        r.write_register(CTRL2_G, (odr << 4) | CTRL2_G_FS_2000).await?;
+
+        // Passing through bypass mode empties the FIFO
+        r.write_register(FIFO_CTRL4, FIFO_MODE_BYPASS).await?;
+        match self.config.sampling {
+            Sampling::DataReady => {
+                r.write_register(INT1_CTRL, INT1_DRDY_XL).await?;
+            }
+            Sampling::Fifo { watermark } => {
+                // Both sensors are batched at the output data rate, so a sample is two words
+                let words = watermark.clamp(1, MAX_SAMPLES as u8) as u16 * 2;
+                r.write_register(FIFO_CTRL1, words as u8).await?;
+                r.write_register(FIFO_CTRL2, (words >> 8) as u8).await?;
+                r.write_register(FIFO_CTRL3, (odr << 4) | odr).await?;
+                r.write_register(FIFO_CTRL4, FIFO_MODE_CONTINUOUS).await?;
+                r.write_register(INT1_CTRL, INT1_FIFO_TH).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn accel_scale(&self) -> f32 {
+        RANGES[self.range].1 / 32768.0
+    }
+
+    fn decode(&self, accel: &[u8], gyro: Option<&[u8]>) -> Sample {
+        let [x, y, z] = vector(accel).map(|v| v * self.accel_scale());
+        Sample {
+            gyro: gyro.map(|gyro| {
+                let [x, y, z] = vector(gyro).map(|v| v * GYRO_SCALE);
+                AngularRate { x, y, z }
+            }),
+            ..Sample::new(x, y, z)
+        }
+    }
+
+    fn format(&self) -> RawFormat {
+        RawFormat {
+            scale: self.accel_scale(),
+            resolution: RESOLUTION,
+            calibration: self.calibration,
+        }
+    }
+
+    /// Read up to one batch of FIFO words into `buf`. Returns when the read started and the
+    /// number of bytes read.
+    async fn read_fifo(&mut self, buf: &mut [u8; FIFO_LEN]) -> Result<(Instant, usize), SampleError<R::Error>> {
+        sensor::wait_for_data(&mut self.irq, sensor::data_timeout(self.period, self.config.sampling)).await?;
+        let now = Instant::now();
+
+        let mut status = [0u8; 2];
+        self.registers.read_registers(FIFO_STATUS1, &mut status).await?;
+        if status[1] & FIFO_STATUS2_OVR != 0 {
+            warn!("xl FIFO overrun, samples were lost");
+            self.timeline.reset(self.period);
+            self.slot = None;
+        }
+        let pending = u16::from_le_bytes([status[0], status[1] & FIFO_STATUS2_DIFF_MASK]) as usize;
+        // Anything beyond one batch stays in the FIFO, which keeps the watermark interrupt raised
+        let len = pending.min(MAX_FIFO_WORDS) * FIFO_WORD_LEN;
+        if len > 0 {
+            // The address wraps around to the tag register after each word
+            self.registers.read_registers(FIFO_DATA_OUT_TAG, &mut buf[..len]).await?;
+        }
+        Ok((now, len))
+    }
+
+    async fn drain_fifo(&mut self) -> Result<Batch, SampleError<R::Error>> {
+        let mut buf = [0u8; FIFO_LEN];
+        let (now, len) = self.read_fifo(&mut buf).await?;
+
+        // Words are paired by their tag counter, as the order within a time slot is not fixed
+        // and a read can end between the two words of a slot
+        let mut samples = Vec::new();
+        let mut slot = self.slot.take();
+        for word in buf[..len].chunks_exact(FIFO_WORD_LEN) {
+            let sensor = word[0] >> 3;
+            if sensor != TAG_GYRO && sensor != TAG_ACCEL {
+                continue;
+            }
+            let count = (word[0] >> 1) & TAG_CNT_MASK;
+            let mut current = match slot.take() {
+                Some(current) if current.count == count => current,
+                previous => {
+                    // The other word of the previous slot was not stored
+                    if let Some(previous) = previous {
+                        self.push_slot(&mut samples, previous);
+                    }
+                    Slot { count, accel: None, gyro: None }
+                }
+            };
+            let data = [word[1], word[2], word[3], word[4], word[5], word[6]];
+            if sensor == TAG_ACCEL {
+                current.accel = Some(data);
+            } else {
+                current.gyro = Some(data);
+            }
+            if current.accel.is_some() && current.gyro.is_some() {
+                self.push_slot(&mut samples, current);
+            } else {
+                slot = Some(current);
+            }
+        }
+        // Completed by the next read
+        self.slot = slot;
+
+        Ok(Batch {
+            start: self.timeline.start(now, samples.len()),
+            period: sensor::duration(self.period),
+            samples,
+        })
+    }
+
+    /// Add the sample of a time slot, which needs at least its accelerometer word.
+    fn push_slot(&mut self, samples: &mut Vec<Sample, MAX_SAMPLES>, slot: Slot) {
+        if let Some(accel) = slot.accel {
+            let sample = self.decode(&accel, slot.gyro.as_ref().map(|gyro| &gyro[..]));
+            let _ = samples.push(self.filter.apply(self.calibration.apply(sample)));
+        }
+    }
+
+    async fn drain_fifo_raw(&mut self) -> Result<RawBatch, SampleError<R::Error>> {
+        let mut buf = [0u8; FIFO_LEN];
+        let (now, len) = self.read_fifo(&mut buf).await?;
+        // Raw readings are not paired, so a slot left by a vector read is dropped
+        self.slot = None;
+
+        // The gyroscope words are skipped
+        let samples: Vec<RawSample, MAX_SAMPLES> = buf[..len]
+            .chunks_exact(FIFO_WORD_LEN)
+            .filter(|word| word[0] >> 3 == TAG_ACCEL)
+            .map(|word| raw_sample(&word[1..]))
+            .collect();
+
+        Ok(RawBatch {
+            start: self.timeline.start(now, samples.len()),
+            period: sensor::duration(self.period),
+            samples,
+        })
+    }
+
+    async fn run_self_test(&mut self) -> Result<SelfTest, R::Error> {
+        let who_am_i = self.registers.read_register(WHO_AM_I).await?;
+
+        self.registers.write_register(FIFO_CTRL4, FIFO_MODE_BYPASS).await?;
+        self.registers.write_register(INT1_CTRL, 0).await?;
+        self.registers.write_register(CTRL2_G, 0).await?;
+        self.registers.write_register(CTRL1_XL, SELF_TEST_CTRL1_XL).await?;
+        let baseline = self.settled_average().await?;
+
+        self.registers.write_register(CTRL5_C, CTRL5_ST_XL_POSITIVE).await?;
+        let positive = self.settled_average().await?;
+
+        self.registers.write_register(CTRL5_C, CTRL5_ST_XL_NEGATIVE).await?;
+        let negative = self.settled_average().await?;
+
+        // Also leaves self-test mode
+        self.configure().await?;
+
+        let delta = |s: Sample| Sample::new(s.x - baseline.x, s.y - baseline.y, s.z - baseline.z);
+        Ok(SelfTest {
+            who_am_i,
+            st0: delta(positive),
+            st1: delta(negative),
+        })
+    }
+
+    /// Let the output settle after a configuration change and average a few samples, in g
+    /// at ±4g.
+    async fn settled_average(&mut self) -> Result<Sample, R::Error> {
+        Timer::after_millis(100).await;
+        let mut buf = [0u8; 6];
+        // The first sample after a change is discarded
+        self.wait_data_ready().await?;
+        self.registers.read_registers(OUTX_L_A, &mut buf).await?;
+
+        let mut sum = [0f32; 3];
+        for _ in 0..SELF_TEST_SAMPLES {
+            self.wait_data_ready().await?;
+            self.registers.read_registers(OUTX_L_A, &mut buf).await?;
+            for (sum, v) in sum.iter_mut().zip(vector(&buf)) {
+                *sum += v;
+            }
+        }
+
+        let [x, y, z] = sum.map(|s| s * SELF_TEST_SCALE / SELF_TEST_SAMPLES as f32);
+        Ok(Sample::new(x, y, z))
+    }
+
+    async fn wait_data_ready(&mut self) -> Result<(), R::Error> {
+        while self.registers.read_register(STATUS_REG).await? & STATUS_XLDA == 0 {
+            // One sample period at the 52 Hz self-test rate
+            Timer::after_millis(20).await;
+        }
+        Ok(())
+    }
+}
+
+impl<R, IRQ> MotionSensor for Lsm6ds<R, IRQ>
+where
+    R: Registers,
+    R::Error: defmt::Format,
+    IRQ: Wait + InputPin,
+{
+    type Error = SampleError<R::Error>;
+
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        Ok(self.configure().await?)
+    }
+
+    async fn set_rate(&mut self, hz: f32) -> Result<f32, Self::Error> {
+        self.config.rate = hz;
+        self.configure().await?;
+        Ok(DATA_RATES[self.rate].1)
+    }
+
+    async fn set_range(&mut self, g: f32) -> Result<f32, Self::Error> {
+        self.config.range = g;
+        self.configure().await?;
+        Ok(RANGES[self.range].1)
+    }
+
+    async fn sample(&mut self) -> Result<Sample, Self::Error> {
+        sensor::wait_for_data(&mut self.irq, sensor::data_timeout(self.period, self.config.sampling)).await?;
+        // Gyroscope and accelerometer outputs are consecutive
+        let mut buf = [0u8; 12];
+        self.registers.read_registers(OUTX_L_G, &mut buf).await?;
+        let sample = self.decode(&buf[6..], Some(&buf[..6]));
+        Ok(self.filter.apply(self.calibration.apply(sample)))
+    }
+
+    async fn batch(&mut self) -> Result<Batch, Self::Error> {
+        match self.config.sampling {
+            Sampling::DataReady => {
+                let sample = self.sample().await?;
+                Ok(Batch::single(sample, sensor::now(), sensor::duration(self.period)))
+            }
+            Sampling::Fifo { .. } => self.drain_fifo().await,
+        }
+    }
+
+    async fn raw_batch(&mut self) -> Result<RawBatch, Self::Error> {
+        match self.config.sampling {
+            Sampling::DataReady => {
+                sensor::wait_for_data(&mut self.irq, sensor::data_timeout(self.period, self.config.sampling)).await?;
+                let mut buf = [0u8; 6];
+                self.registers.read_registers(OUTX_L_A, &mut buf).await?;
+                let mut samples = Vec::new();
+                let _ = samples.push(raw_sample(&buf));
+                Ok(RawBatch {
+                    start: sensor::now(),
+                    period: sensor::duration(self.period),
+                    samples,
+                })
+            }
+            Sampling::Fifo { .. } => self.drain_fifo_raw().await,
+        }
+    }
+
+    fn raw_format(&self) -> RawFormat {
+        self.format()
+    }
+
+    async fn self_test(&mut self) -> Result<SelfTest, Self::Error> {
+        Ok(self.run_self_test().await?)
+    }
+
+    fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+        self.filter.reset();
+    }
+}
+
+fn vector(raw: &[u8]) -> [f32; 3] {
+    [
+        i16::from_le_bytes([raw[0], raw[1]]) as f32,
+        i16::from_le_bytes([raw[2], raw[3]]) as f32,
+        i16::from_le_bytes([raw[4], raw[5]]) as f32,
+    ]
+}
+
+fn raw_sample(raw: &[u8]) -> RawSample {
+    RawSample {
+        x: i16::from_le_bytes([raw[0], raw[1]]),
+        y: i16::from_le_bytes([raw[2], raw[3]]),
+        z: i16::from_le_bytes([raw[4], raw[5]]),
+    }
+}
@@ -5,6 +5,11 @@ use embassy_executor::Spawner;
 use {defmt_rtt as _, panic_probe as _};
 use defmt::*;
 
+mod bus;
+mod sensor;
+mod lis3dh;
+mod lsm6ds;
+mod storage;
 mod xl;
 mod net;
 mod app;
@@ -14,7 +19,9 @@ mod board;
 async fn main(spawner: Spawner) {
     let board = board::init();
 
-    let stream = unwrap!(xl::init(board.xl, spawner).await);
+    let storage = storage::init(board.flash);
+    let stream = unwrap!(xl::init(board.xl, storage, xl::Config::default(), spawner).await);
+    spawner.must_spawn(xl::calibrate_on_press(board.button));
     let net = net::init(board.net, &spawner).await;
     let app = app::init(stream, net);
 
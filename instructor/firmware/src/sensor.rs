@@ -0,0 +1,159 @@
+use embedded_hal_async::digital::Wait;
+use embassy_time::{with_timeout, Duration, Instant, TimeoutError};
+
+pub use workshop_common::Sample;
+pub use workshop_common::calibration::Calibration;
+pub use workshop_common::health::SelfTest;
+pub use workshop_common::protocol::MAX_SAMPLES;
+pub use workshop_common::raw::{RawFormat, RawSample};
+pub use workshop_common::registers::{I2cRegisters, Registers, SpiRegisters};
+pub use workshop_common::sensor::{Batch, MotionSensor, OutputMode, RawBatch, RecoveryPolicy};
+
+/// How samples are read out of the sensor.
+#[derive(Clone, Copy, defmt::Format)]
+pub enum Sampling {
+    /// One data-ready interrupt and one bus transaction per sample.
+    DataReady,
+    /// Samples are queued in the sensor FIFO and burst-read once `watermark` samples are pending.
+    Fifo { watermark: u8 },
+}
+
+/// Thresholds for the motion events detected by the sensor itself.
+#[derive(Clone, Copy, defmt::Format)]
+pub struct MotionConfig {
+    /// Acceleration a tap has to exceed, in g.
+    pub tap_threshold: f32,
+    /// Longest time a tap may stay above the threshold.
+    pub tap_limit: Duration,
+    /// Quiet time after the first tap of a double tap.
+    pub tap_latency: Duration,
+    /// Time after the latency in which the second tap of a double tap has to start.
+    pub tap_window: Duration,
+    /// All axes have to stay below this, in g, for the sensor to be in free fall.
+    pub free_fall_threshold: f32,
+    /// How long all axes have to stay below the free fall threshold.
+    pub free_fall_duration: Duration,
+    /// Acceleration, in g, below which the sensor counts as still.
+    pub inactivity_threshold: f32,
+    /// How long the sensor has to be still before it is reported inactive.
+    pub inactivity_duration: Duration,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            tap_threshold: 0.8,
+            tap_limit: Duration::from_millis(50),
+            tap_latency: Duration::from_millis(100),
+            tap_window: Duration::from_millis(300),
+            free_fall_threshold: 0.35,
+            free_fall_duration: Duration::from_millis(30),
+            inactivity_threshold: 0.1,
+            inactivity_duration: Duration::from_secs(5),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Config {
+    /// Output data rate in Hz, rounded up to a rate the sensor supports.
+    pub rate: f32,
+    /// Accelerometer full scale in g, rounded up to a range the sensor supports.
+    pub range: f32,
+    pub sampling: Sampling,
+    /// Motion event detection on INT2, disabled if `None`. Only supported by the LIS3DH.
+    pub motion: Option<MotionConfig>,
+    pub recovery: RecoveryPolicy,
+    pub output: OutputMode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            // 400 Hz with a watermark of 16 samples gives an interrupt every 40 ms
+            rate: 400.0,
+            range: 2.0,
+            sampling: Sampling::Fifo { watermark: 16 },
+            motion: Some(MotionConfig::default()),
+            recovery: RecoveryPolicy::default(),
+            output: OutputMode::Vectors,
+        }
+    }
+}
+
+#[derive(defmt::Format)]
+pub enum SampleError<E> {
+    Sensor(E),
+    /// The sensor did not signal new data in time.
+    Timeout,
+}
+
+impl<E> From<E> for SampleError<E> {
+    fn from(e: E) -> Self {
+        SampleError::Sensor(e)
+    }
+}
+
+/// Time since boot, which batches are timestamped with.
+pub(crate) fn now() -> core::time::Duration {
+    since_boot(Instant::now())
+}
+
+fn since_boot(instant: Instant) -> core::time::Duration {
+    core::time::Duration::from_micros(instant.as_micros())
+}
+
+/// A duration as batches carry it.
+pub(crate) fn duration(duration: Duration) -> core::time::Duration {
+    core::time::Duration::from_micros(duration.as_micros())
+}
+
+/// Longest time to wait for the sensor to signal new data before giving up on it.
+pub(crate) fn data_timeout(period: Duration, sampling: Sampling) -> Duration {
+    let samples = match sampling {
+        Sampling::DataReady => 1,
+        Sampling::Fifo { watermark } => watermark as u32 + 1,
+    };
+    // Generous, since the only goal is to not hang forever on a missed edge
+    (period * samples * 4).max(Duration::from_millis(100))
+}
+
+/// Wait for the interrupt pin to signal new data.
+pub(crate) async fn wait_for_data<IRQ: Wait, E>(irq: &mut IRQ, timeout: Duration) -> Result<(), SampleError<E>> {
+    match with_timeout(timeout, irq.wait_for_high()).await {
+        Ok(_) => Ok(()),
+        Err(TimeoutError) => Err(SampleError::Timeout),
+    }
+}
+
+/// Reconstructs sample timestamps from when the FIFO was read.
+pub(crate) struct Timeline {
+    period: Duration,
+    next: Option<Instant>,
+}
+
+impl Timeline {
+    pub(crate) fn new(period: Duration) -> Self {
+        Self { period, next: None }
+    }
+
+    /// Start over, after the sensor was reconfigured or samples were lost.
+    pub(crate) fn reset(&mut self, period: Duration) {
+        self.period = period;
+        self.next = None;
+    }
+
+    /// When the first of `count` samples read at `now` was taken, as time since boot.
+    pub(crate) fn start(&mut self, now: Instant, count: usize) -> core::time::Duration {
+        // The newest sample was taken at most one period before the interrupt was serviced. Keep
+        // the timeline continuous with the previous batch unless we drifted by more than a period.
+        let elapsed = self.period * (count.max(1) as u32 - 1);
+        let estimate = now.checked_sub(elapsed).unwrap_or(now);
+        let start = match self.next {
+            Some(expected) if expected.max(estimate) - expected.min(estimate) < self.period => expected,
+            _ => estimate,
+        };
+        self.next = Some(start + self.period * count as u32);
+        since_boot(start)
+    }
+}
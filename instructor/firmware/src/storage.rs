@@ -0,0 +1,77 @@
+//! Sensor calibrations, persisted in the last flash sector.
+
+use embassy_stm32::flash::{self, Blocking, Flash, FLASH_SIZE};
+use embassy_stm32::peripherals::FLASH;
+use embassy_stm32::Peri;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use static_cell::StaticCell;
+use workshop_common::calibration::{Calibration, CALIBRATION_LEN};
+
+use crate::xl::MAX_SENSORS;
+
+// The firmware is far from filling the flash, so the last sector is free for our records
+const ERASE_SIZE: usize = <Flash<'static, Blocking> as NorFlash>::ERASE_SIZE;
+const SECTOR: u32 = (FLASH_SIZE - ERASE_SIZE) as u32;
+
+// One record per sensor: a marker, the calibration and padding to a multiple of the 16 byte
+// write size. Erased flash reads as 0xFF, so a missing record has no marker.
+const RECORD_MAGIC: [u8; 4] = *b"XLC1";
+const RECORD_LEN: usize = 32;
+
+pub struct Storage {
+    flash: Mutex<NoopRawMutex, Flash<'static, Blocking>>,
+}
+
+pub fn init(flash: Peri<'static, FLASH>) -> &'static Storage {
+    static STORAGE: StaticCell<Storage> = StaticCell::new();
+    STORAGE.init(Storage {
+        flash: Mutex::new(Flash::new_blocking(flash)),
+    })
+}
+
+impl Storage {
+    /// The stored calibration of a sensor, if it has been calibrated.
+    pub async fn load(&self, sensor: u8) -> Option<Calibration> {
+        let mut flash = self.flash.lock().await;
+        let mut record = [0u8; RECORD_LEN];
+        flash.read(record_offset(sensor), &mut record).ok()?;
+        decode_record(&record)
+    }
+
+    /// Store the calibration of a sensor, keeping those of the other sensors.
+    ///
+    /// Erasing the sector blocks the executor for a few tens of milliseconds, which is fine for
+    /// something done once per calibration.
+    pub async fn save(&self, sensor: u8, calibration: &Calibration) -> Result<(), flash::Error> {
+        let mut flash = self.flash.lock().await;
+        let mut records = [0xFFu8; RECORD_LEN * MAX_SENSORS];
+        flash.read(SECTOR, &mut records)?;
+        let start = sensor as usize * RECORD_LEN;
+        records[start..start + RECORD_LEN].copy_from_slice(&encode_record(calibration));
+
+        flash.erase(SECTOR, SECTOR + ERASE_SIZE as u32)?;
+        flash.write(SECTOR, &records)
+    }
+}
+
+fn record_offset(sensor: u8) -> u32 {
+    SECTOR + (sensor as usize * RECORD_LEN) as u32
+}
+
+fn encode_record(calibration: &Calibration) -> [u8; RECORD_LEN] {
+    let mut record = [0xFF; RECORD_LEN];
+    record[..4].copy_from_slice(&RECORD_MAGIC);
+    record[4..4 + CALIBRATION_LEN].copy_from_slice(&calibration.to_bytes());
+    record
+}
+
+fn decode_record(record: &[u8; RECORD_LEN]) -> Option<Calibration> {
+    if record[..4] != RECORD_MAGIC {
+        return None;
+    }
+    let calibration = Calibration::from_bytes(record[4..4 + CALIBRATION_LEN].try_into().ok()?);
+    // Guards against records half written when power was lost
+    calibration.is_plausible().then_some(calibration)
+}
@@ -1,147 +1,349 @@
-use embedded_hal_async::i2c::I2c;
-use embedded_hal_async::digital::Wait;
-use embedded_hal::digital::InputPin;
-use lis3dh_async::{Lis3dh, Lis3dhI2C, SlaveAddr, Configuration, Interrupt1, InterruptMode, Mode, DataRate, InterruptConfig, IrqPin1Config, Error};
-use crate::board::{XlResources, Irqs};
-use embassy_stm32::i2c::{I2c as I2cPeripheral, Master};
+use lis3dh_async::{Lis3dhI2C, Lis3dhSPI, SlaveAddr};
+use crate::bus::{RecoverableI2c, SharedSpiDevice};
+use crate::lis3dh::{self, Accel};
+use crate::lsm6ds::{self, Lsm6ds};
+use crate::sensor::{I2cRegisters, MotionSensor, Registers, SpiRegisters};
+use crate::storage::Storage;
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::Output;
 use embassy_stm32::mode::Async;
-use embassy_stm32::exti::{ExtiInput};
-use embassy_stm32::gpio::Pull;
-use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_stm32::spi::Spi;
+use embassy_stm32::flash;
+use embassy_embedded_hal::shared_bus::asynch::i2c::I2cDevice;
+use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice as SharedBusSpiDevice;
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, ThreadModeRawMutex};
 use embassy_sync::channel::{Channel, Sender, Receiver};
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
 use embassy_executor::Spawner;
-use defmt::warn;
+use embassy_time::Timer;
+use heapless::Vec;
+use static_cell::StaticCell;
+use defmt::{error, info, warn};
+use workshop_common::calibration::Calibration;
+use workshop_common::link;
+use workshop_common::sensor::Host;
 
-type I2cType = I2cPeripheral<'static, Async, Master>;
+pub use crate::sensor::Config;
+pub use workshop_common::health::{Health, SelfTest, SensorStatus};
+pub use workshop_common::motion::MotionEvent;
+pub use workshop_common::sensor::Message;
+
+type I2cBus = Mutex<NoopRawMutex, RecoverableI2c>;
+type I2cType = I2cDevice<'static, NoopRawMutex, RecoverableI2c>;
+type SpiBus = Mutex<NoopRawMutex, Spi<'static, Async>>;
+type SpiDeviceType = SharedBusSpiDevice<'static, NoopRawMutex, Spi<'static, Async>, Output<'static>>;
+type SpiType = SharedSpiDevice<SpiDeviceType>;
 type IrqType = ExtiInput<'static>;
 
-pub struct Accel<I: I2c, IRQ: Wait + InputPin> {
-    xl: Lis3dh<Lis3dhI2C<I>>,
-    irq: IRQ,
-    filter: LowpassFilter,
+/// Most sensor buses on a board.
+pub const MAX_BUSES: usize = 2;
+/// Most motion sensors on a board.
+pub const MAX_SENSORS: usize = 4;
+
+/// Which chip a sensor is.
+#[derive(Clone, Copy, defmt::Format)]
+pub enum SensorKind {
+    /// LIS3DH accelerometer, with motion events on INT2.
+    Lis3dh,
+    /// LSM6DSO accelerometer and gyroscope.
+    Lsm6dso,
 }
 
+/// How a sensor is connected.
+pub enum SensorBus {
+    /// On the I2C bus with this index in [`Resources::i2c_buses`], with SA0 low for
+    /// `SlaveAddr::Default` and high for `SlaveAddr::Alternate`.
+    I2c { bus: usize, address: SlaveAddr },
+    /// On the SPI bus with this index in [`Resources::spi_buses`], selected by `cs`.
+    Spi { bus: usize, cs: Output<'static> },
+}
 
-#[derive(Clone, Copy, defmt::Format)]
-pub struct Sample {
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
-}
-
-impl<I: I2c, IRQ: Wait + InputPin> Accel<I, IRQ> {
-    pub async fn new(i2c: I, irq: IRQ) -> Result<Self, Error<I::Error>> {
-        let config = Configuration {
-            mode: Mode::HighResolution,
-            datarate: DataRate::PowerDown,
-            ..Configuration::default()
-        };
+/// One motion sensor on the board.
+pub struct SensorResources {
+    pub kind: SensorKind,
+    pub bus: SensorBus,
+    /// Pin connected to INT1, used for data-ready and FIFO watermark interrupts.
+    pub irq: ExtiInput<'static>,
+    /// Pin connected to INT2, used for motion events. Motion detection is disabled without it.
+    pub irq2: Option<ExtiInput<'static>>,
+}
 
-        let mut xl = Lis3dh::new_i2c_with_config(i2c, SlaveAddr::Default, config).await?;
+/// The sensor buses and sensors on the board. Sensors sharing an I2C bus need different
+/// addresses.
+#[derive(Default)]
+pub struct Resources {
+    pub i2c_buses: Vec<RecoverableI2c, MAX_BUSES>,
+    pub spi_buses: Vec<Spi<'static, Async>, MAX_BUSES>,
+    pub sensors: Vec<SensorResources, MAX_SENSORS>,
+}
 
-        let dr = DataRate::Hz_100;
-        xl.set_datarate(dr).await?;
+// Set by `calibrate`, one per sensor
+static CALIBRATE: [Signal<ThreadModeRawMutex, ()>; MAX_SENSORS] = [const { Signal::new() }; MAX_SENSORS];
 
-        xl.configure_irq_src(
-            Interrupt1,
-            InterruptMode::Position,
-            InterruptConfig::high_and_low(),
-        ).await?;
+/// Start calibrating all sensors, or cancel the calibrations in progress.
+pub fn calibrate() {
+    for signal in &CALIBRATE {
+        signal.signal(());
+    }
+}
 
-        // Raise pin state if interrupt 1 is raised and there is movement
-        xl.configure_interrupt_pin(IrqPin1Config {
-            zyxda_en: true,
-            ..IrqPin1Config::default()
-        }).await?;
+/// Calibrate when the user button is pressed.
+#[embassy_executor::task]
+pub async fn calibrate_on_press(mut button: ExtiInput<'static>) {
+    loop {
+        button.wait_for_rising_edge().await;
+        info!("Button pressed, calibrating");
+        calibrate();
+        // Debounce
+        Timer::after_millis(200).await;
+        button.wait_for_low().await;
+    }
+}
 
+/// The messages of all sensor tasks, for the connection to the viewer.
+pub struct SampleStream(Receiver<'static, ThreadModeRawMutex, Message, 8>);
 
-        Ok(Self {
-            xl,
-            irq,
-            filter: LowpassFilter::new(0.1), // Lower value -> smoother but more delay
-        })
+impl link::Sensors for SampleStream {
+    async fn receive(&mut self) -> Message {
+        self.0.receive().await
     }
 
-    pub async fn sample(&mut self) -> Result<Sample, Error<I::Error>> {
-        let _ = self.irq.wait_for_high().await;
-        let raw_sample = self.xl.accel_norm().await?;
-        let raw_sample = Sample {
-            x: raw_sample.x,
-            y: raw_sample.y,
-            z: raw_sample.z,
-        };
-        let filtered_sample = self.filter.apply(raw_sample);
-        Ok(filtered_sample)
+    fn calibrate(&mut self) {
+        calibrate();
     }
 }
 
+type MessageSender = Sender<'static, ThreadModeRawMutex, Message, 8>;
+static STREAM: Channel<ThreadModeRawMutex, Message, 8> = Channel::new();
 
-struct LowpassFilter {
-    filter_state: Option<Sample>,
-    alpha: f32,
+/// A sensor could not be set up. The cause is logged.
+#[derive(defmt::Format)]
+pub struct InitError {
+    pub sensor: u8,
 }
 
-impl LowpassFilter {
-    fn new(alpha: f32) -> Self {
-        Self {
-            filter_state: None,
-            alpha,
+pub async fn init(r: Resources, storage: &'static Storage, config: Config, s: Spawner) -> Result<SampleStream, InitError> {
+    static I2C_BUSES: StaticCell<Vec<I2cBus, MAX_BUSES>> = StaticCell::new();
+    let i2c_buses: &'static Vec<I2cBus, MAX_BUSES> = I2C_BUSES.init(r.i2c_buses.into_iter().map(Mutex::new).collect());
+    static SPI_BUSES: StaticCell<Vec<SpiBus, MAX_BUSES>> = StaticCell::new();
+    let spi_buses: &'static Vec<SpiBus, MAX_BUSES> = SPI_BUSES.init(r.spi_buses.into_iter().map(Mutex::new).collect());
+    // The driver and the FIFO reads of a SPI sensor share its chip select
+    static SPI_DEVICES: [StaticCell<Mutex<NoopRawMutex, SpiDeviceType>>; MAX_SENSORS] =
+        [const { StaticCell::new() }; MAX_SENSORS];
+
+    for (index, sensor) in r.sensors.into_iter().enumerate() {
+        let index = index as u8;
+
+        let mut config = config;
+        let motion_irq = match (config.motion, sensor.kind) {
+            (Some(_), SensorKind::Lis3dh) => sensor.irq2,
+            (Some(_), kind) => {
+                warn!("xl{}: motion events are not supported on {}", index, kind);
+                None
+            }
+            (None, _) => None,
+        };
+        if motion_irq.is_none() {
+            config.motion = None;
         }
-    }
 
-    fn apply(&mut self, raw_sample: Sample) -> Sample {
-        match self.filter_state {
-            None => {
-                // First sample, initialize filter state
-                self.filter_state = Some(raw_sample);
-                raw_sample
+        match (sensor.kind, sensor.bus) {
+            (SensorKind::Lis3dh, SensorBus::I2c { bus, address }) => {
+                let bus = &i2c_buses[bus];
+                let mut xl = Accel::new_i2c(I2cDevice::new(bus), I2cDevice::new(bus), sensor.irq, address, config)
+                    .await
+                    .map_err(|e| init_failed(index, e))?;
+                let health = check_health(index, &mut xl).await?;
+
+                if let Some(irq2) = motion_irq {
+                    let registers = lis3dh::i2c_registers(I2cDevice::new(bus), address);
+                    s.must_spawn(detect_motion_i2c(index, registers, irq2, STREAM.sender()));
+                }
+                s.must_spawn(run_lis3dh_i2c(index, xl, bus, config, health, storage, STREAM.sender()));
+            }
+            (SensorKind::Lis3dh, SensorBus::Spi { bus, cs }) => {
+                let device: &'static _ = SPI_DEVICES[index as usize].init(Mutex::new(SharedBusSpiDevice::new(&spi_buses[bus], cs)));
+                let mut xl = Accel::new_spi(SharedSpiDevice::new(device), SharedSpiDevice::new(device), sensor.irq, config)
+                    .await
+                    .map_err(|e| init_failed(index, e))?;
+                let health = check_health(index, &mut xl).await?;
+
+                if let Some(irq2) = motion_irq {
+                    let registers = lis3dh::spi_registers(SharedSpiDevice::new(device));
+                    s.must_spawn(detect_motion_spi(index, registers, irq2, STREAM.sender()));
+                }
+                s.must_spawn(run_lis3dh_spi(index, xl, config, health, storage, STREAM.sender()));
             }
-            Some(prev) => {
-                // Apply exponential moving average: filtered = alpha * new + (1 - alpha) * prev
-                let filtered = Sample {
-                    x: self.alpha * raw_sample.x + (1.0 - self.alpha) * prev.x,
-                    y: self.alpha * raw_sample.y + (1.0 - self.alpha) * prev.y,
-                    z: self.alpha * raw_sample.z + (1.0 - self.alpha) * prev.z,
+            (SensorKind::Lsm6dso, SensorBus::I2c { bus, address }) => {
+                let address = match address {
+                    SlaveAddr::Default => lsm6ds::ADDRESS,
+                    SlaveAddr::Alternate => lsm6ds::ADDRESS_ALTERNATE,
                 };
-                self.filter_state = Some(filtered);
-                filtered
+                let bus = &i2c_buses[bus];
+                let mut imu = lsm6ds::i2c(I2cDevice::new(bus), address, sensor.irq, config);
+                imu.init().await.map_err(|e| init_failed(index, e))?;
+                let health = check_health(index, &mut imu).await?;
+                s.must_spawn(run_lsm6ds_i2c(index, imu, bus, config, health, storage, STREAM.sender()));
+            }
+            (SensorKind::Lsm6dso, SensorBus::Spi { bus, cs }) => {
+                let device: &'static _ = SPI_DEVICES[index as usize].init(Mutex::new(SharedBusSpiDevice::new(&spi_buses[bus], cs)));
+                let mut imu = lsm6ds::spi(SharedSpiDevice::new(device), sensor.irq, config);
+                imu.init().await.map_err(|e| init_failed(index, e))?;
+                let health = check_health(index, &mut imu).await?;
+                s.must_spawn(run_lsm6ds_spi(index, imu, config, health, storage, STREAM.sender()));
             }
         }
     }
+    Ok(SampleStream(STREAM.receiver()))
 }
 
+fn init_failed<E: defmt::Format>(sensor: u8, e: E) -> InitError {
+    error!("xl{} init failed: {:?}", sensor, e);
+    InitError { sensor }
+}
 
-pub type SampleStream = Receiver<'static, ThreadModeRawMutex, Sample, 10>;
-static STREAM: Channel<ThreadModeRawMutex, Sample, 10> = Channel::new();
+/// Run the self-test, for the health report the sensor task starts with.
+async fn check_health<S: MotionSensor>(sensor: u8, xl: &mut S) -> Result<Health, InitError> {
+    let self_test = xl.self_test().await.map_err(|e| {
+        error!("xl{} self-test failed to run: {:?}", sensor, e);
+        InitError { sensor }
+    })?;
+    report_self_test(sensor, &self_test);
+    Ok(Health { self_test, status: SensorStatus::Ok })
+}
 
-pub async fn init(p: XlResources, s: Spawner) -> Result<SampleStream, Error<embassy_stm32::i2c::Error>> {
-    let i2c = I2cPeripheral::new(
-        p.i2c1,
-        p.scl,
-        p.sda,
-        Irqs,
-        p.dma1,
-        p.dma2,
-        Default::default(),
-    );
+fn report_self_test(sensor: u8, result: &SelfTest) {
+    let st0 = result.axes_within_limits(&result.st0);
+    let st1 = result.axes_within_limits(&result.st1);
+    if result.passed() {
+        info!("xl{} self-test passed: WHO_AM_I {=u8:#x}, ST0 {} ST1 {}", sensor, result.who_am_i, result.st0, result.st1);
+    } else {
+        error!(
+            "xl{} self-test failed: WHO_AM_I {=u8:#x}, ST0 {} ok {}, ST1 {} ok {}",
+            sensor, result.who_am_i, result.st0, st0, result.st1, st1
+        );
+    }
+}
 
-    let input = ExtiInput::new(p.irq, p.exti, Pull::None, Irqs);
+#[embassy_executor::task(pool_size = MAX_SENSORS)]
+async fn run_lis3dh_i2c(sensor: u8, xl: Accel<Lis3dhI2C<I2cType>, I2cRegisters<I2cType>, IrqType>, bus: &'static I2cBus, config: Config, health: Health, storage: &'static Storage, sender: MessageSender) {
+    run(sensor, xl, Some(bus), config, health, storage, sender).await
+}
 
-    let xl = Accel::new(i2c, input).await?;
+#[embassy_executor::task(pool_size = MAX_SENSORS)]
+async fn run_lis3dh_spi(sensor: u8, xl: Accel<Lis3dhSPI<SpiType>, SpiRegisters<SpiType>, IrqType>, config: Config, health: Health, storage: &'static Storage, sender: MessageSender) {
+    run(sensor, xl, None, config, health, storage, sender).await
+}
 
-    s.must_spawn(run(xl, STREAM.sender()));
-    Ok(STREAM.receiver())
+#[embassy_executor::task(pool_size = MAX_SENSORS)]
+async fn run_lsm6ds_i2c(sensor: u8, imu: Lsm6ds<I2cRegisters<I2cType>, IrqType>, bus: &'static I2cBus, config: Config, health: Health, storage: &'static Storage, sender: MessageSender) {
+    run(sensor, imu, Some(bus), config, health, storage, sender).await
 }
 
-#[embassy_executor::task]
-async fn run(mut xl: Accel<I2cType, IrqType>, sender: Sender<'static, ThreadModeRawMutex, Sample, 10>) {
+#[embassy_executor::task(pool_size = MAX_SENSORS)]
+async fn run_lsm6ds_spi(sensor: u8, imu: Lsm6ds<SpiRegisters<SpiType>, IrqType>, config: Config, health: Health, storage: &'static Storage, sender: MessageSender) {
+    run(sensor, imu, None, config, health, storage, sender).await
+}
+
+/// Forward samples from the sensor, recovering it when it stops responding. Only I2C buses can
+/// get stuck, SPI sensors are just reconfigured. Calibrations are requested through
+/// [`calibrate`] and kept in `storage`.
+async fn run<S: MotionSensor>(
+    sensor: u8,
+    xl: S,
+    i2c_bus: Option<&'static I2cBus>,
+    config: Config,
+    health: Health,
+    storage: &'static Storage,
+    sender: MessageSender,
+) {
+    let mut host = SensorHost { sensor, i2c_bus, storage, sender };
+    // Sent first, so the health is known before any samples are forwarded
+    host.send(Message::Health { sensor, health }).await;
+    workshop_common::sensor::run(sensor, xl, host, config.recovery, config.output, health).await
+}
+
+/// What the sampling loop of a sensor needs from the board.
+struct SensorHost {
+    sensor: u8,
+    i2c_bus: Option<&'static I2cBus>,
+    storage: &'static Storage,
+    sender: MessageSender,
+}
+
+impl Host for SensorHost {
+    type StorageError = flash::Error;
+
+    async fn send(&mut self, message: Message) {
+        self.sender.send(message).await
+    }
+
+    async fn sleep(&mut self, duration: core::time::Duration) {
+        Timer::after_micros(duration.as_micros() as u64).await
+    }
+
+    fn calibration_requested(&mut self) -> bool {
+        CALIBRATE[self.sensor as usize].try_take().is_some()
+    }
+
+    async fn load_calibration(&mut self) -> Option<Calibration> {
+        self.storage.load(self.sensor).await
+    }
+
+    async fn store_calibration(&mut self, calibration: &Calibration) -> Result<(), flash::Error> {
+        self.storage.save(self.sensor, calibration).await
+    }
+
+    async fn recover_bus(&mut self) {
+        if let Some(bus) = self.i2c_bus {
+            bus.lock().await.recover().await;
+        }
+    }
+}
+
+#[embassy_executor::task(pool_size = MAX_SENSORS)]
+async fn detect_motion_i2c(sensor: u8, registers: I2cRegisters<I2cType>, irq: IrqType, sender: MessageSender) {
+    detect_motion(sensor, registers, irq, sender).await
+}
+
+#[embassy_executor::task(pool_size = MAX_SENSORS)]
+async fn detect_motion_spi(sensor: u8, registers: SpiRegisters<SpiType>, irq: IrqType, sender: MessageSender) {
+    detect_motion(sensor, registers, irq, sender).await
+}
+
+async fn detect_motion<R>(sensor: u8, mut registers: R, mut irq: IrqType, sender: MessageSender)
+where
+    R: Registers,
+    R::Error: defmt::Format,
+{
+    let mut inactive = false;
     loop {
-        match xl.sample().await {
-            Ok(sample) => {
-                sender.send(sample).await;
+        if inactive {
+            // INT2 stays high for as long as the sensor is inactive
+            irq.wait_for_low().await;
+            inactive = false;
+            sender.send(Message::Motion { sensor, event: MotionEvent::Activity }).await;
+            continue;
+        }
+
+        irq.wait_for_rising_edge().await;
+        match lis3dh::read_motion_events(&mut registers).await {
+            Ok(events) if events.is_empty() => {
+                // Neither click nor free-fall, so it must be the inactivity signal
+                if irq.is_high() {
+                    inactive = true;
+                    sender.send(Message::Motion { sensor, event: MotionEvent::Inactivity }).await;
+                }
+            }
+            Ok(events) => {
+                for event in events {
+                    info!("xl{} motion event: {:?}", sensor, event);
+                    sender.send(Message::Motion { sensor, event }).await;
+                }
             }
             Err(e) => {
-                warn!("Error sampling xl: {:?}", e);
+                warn!("Error reading xl{} motion events: {:?}", sensor, e);
             }
         }
     }
@@ -0,0 +1,184 @@
+//! The simulated LIS3DH as a [`MotionSensor`], sampled in FIFO mode like the firmware.
+//!
+//! Configuration, self-test and FIFO reads are the firmware's own, from
+//! [`workshop_common::lis3dh`]. Only the waiting is done here, on tokio timers in place of
+//! embassy's.
+
+use std::sync::OnceLock;
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+use tokio::time::{timeout, Duration, Instant};
+use workshop_common::calibration::Calibration;
+use workshop_common::filter::{Filter, LowpassFilter};
+use workshop_common::health::SelfTest;
+use workshop_common::lis3dh::{self, Output, RESOLUTION};
+use workshop_common::raw::RawFormat;
+use workshop_common::registers::Registers;
+use workshop_common::sensor::{Batch, MotionSensor, RawBatch};
+use workshop_common::Sample;
+
+/// How the sensor is sampled.
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Output data rate in Hz, rounded up to a rate the sensor supports.
+    pub rate: f32,
+    /// Full scale in g, rounded up to a range the sensor supports.
+    pub range: f32,
+    /// Samples queued in the FIFO before they are read.
+    pub watermark: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rate: 400.0,
+            range: 2.0,
+            watermark: 16,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Bus(E),
+    /// The sensor did not signal new data in time.
+    Timeout,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Bus(e)
+    }
+}
+
+/// A LIS3DH accessed through `registers`, signalling the FIFO watermark on `irq`.
+pub struct Accel<R, IRQ> {
+    registers: R,
+    irq: IRQ,
+    config: Config,
+    output: Output,
+    calibration: Calibration,
+    filter: LowpassFilter,
+}
+
+impl<R: Registers, IRQ: Wait> Accel<R, IRQ> {
+    pub async fn new(registers: R, irq: IRQ, config: Config) -> Result<Self, R::Error> {
+        let mut accel = Self {
+            registers,
+            irq,
+            config,
+            output: Output::new(config.rate, config.range),
+            calibration: Calibration::IDENTITY,
+            filter: LowpassFilter::new(0.1), // Lower value -> smoother but more delay
+        };
+        accel.configure().await?;
+        Ok(accel)
+    }
+
+    /// Write the complete sensor configuration.
+    async fn configure(&mut self) -> Result<(), R::Error> {
+        self.output = Output::new(self.config.rate, self.config.range);
+        lis3dh::configure(&mut self.registers, self.output).await?;
+        lis3dh::enable_fifo(&mut self.registers, self.config.watermark).await
+    }
+
+    /// Wait for the watermark and read all samples pending in the FIFO, in counts.
+    async fn read_fifo(&mut self) -> Result<RawBatch, Error<R::Error>> {
+        let period = self.output.period();
+        // Generous, since the only goal is to not hang forever on a missed edge
+        let limit = (period * (self.config.watermark as u32 + 1) * 4).max(Duration::from_millis(100));
+        if timeout(limit, self.irq.wait_for_high()).await.is_err() {
+            return Err(Error::Timeout);
+        }
+        let now = uptime();
+
+        let fifo = lis3dh::read_fifo(&mut self.registers).await?;
+        Ok(RawBatch {
+            // The newest sample was taken just before the watermark was signalled
+            start: now.saturating_sub(period * (fifo.samples.len().max(1) as u32 - 1)),
+            period,
+            samples: fifo.samples.into_iter().collect(),
+        })
+    }
+}
+
+impl<R, IRQ> MotionSensor for Accel<R, IRQ>
+where
+    R: Registers,
+    R::Error: std::fmt::Debug,
+    IRQ: Wait,
+{
+    type Error = Error<R::Error>;
+
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        Ok(self.configure().await?)
+    }
+
+    async fn set_rate(&mut self, hz: f32) -> Result<f32, Self::Error> {
+        self.config.rate = hz;
+        self.configure().await?;
+        Ok(self.output.rate())
+    }
+
+    async fn set_range(&mut self, g: f32) -> Result<f32, Self::Error> {
+        self.config.range = g;
+        self.configure().await?;
+        Ok(self.output.full_scale())
+    }
+
+    /// The FIFO stays on, so this is the newest sample of the next batch.
+    async fn sample(&mut self) -> Result<Sample, Self::Error> {
+        loop {
+            if let Some(sample) = self.batch().await?.samples.last() {
+                return Ok(*sample);
+            }
+        }
+    }
+
+    async fn batch(&mut self) -> Result<Batch, Self::Error> {
+        let raw = self.read_fifo().await?;
+        let format = self.raw_format();
+        Ok(Batch {
+            start: raw.start,
+            period: raw.period,
+            samples: raw.samples.iter().map(|s| self.filter.apply(format.to_sample(s))).collect(),
+        })
+    }
+
+    async fn raw_batch(&mut self) -> Result<RawBatch, Self::Error> {
+        self.read_fifo().await
+    }
+
+    fn raw_format(&self) -> RawFormat {
+        RawFormat {
+            scale: self.output.scale(),
+            resolution: RESOLUTION,
+            calibration: self.calibration,
+        }
+    }
+
+    async fn self_test(&mut self) -> Result<SelfTest, Self::Error> {
+        Ok(lis3dh::self_test(&mut self.registers, &mut Sleep).await?)
+    }
+
+    fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+        self.filter.reset();
+    }
+}
+
+/// Delays on the tokio timer.
+struct Sleep;
+
+impl DelayNs for Sleep {
+    async fn delay_ns(&mut self, ns: u32) {
+        tokio::time::sleep(Duration::from_nanos(ns.into())).await
+    }
+}
+
+/// Time since the simulator started, which the batches are timestamped with.
+fn uptime() -> Duration {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed()
+}
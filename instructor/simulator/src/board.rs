@@ -0,0 +1,217 @@
+//! One simulated board: its sensors, and the connection to the viewer.
+//!
+//! Mirrors the firmware, with a task per sensor like `firmware/src/xl.rs` feeding the
+//! connection like `firmware/src/app.rs`. Both run the same [`workshop_common::sensor::run`] and
+//! [`workshop_common::link::forward`] as the firmware, over the same
+//! [`workshop_common::lis3dh`] register code.
+
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep, Duration, Instant};
+use workshop_common::calibration::Calibration;
+use workshop_common::health::{Health, SensorStatus};
+use workshop_common::link::{self, Cache, Connection, Sensors};
+use workshop_common::lis3dh;
+use workshop_common::registers::Registers;
+use workshop_common::sensor::{self, Host, Message, MotionSensor, OutputMode, RecoveryPolicy};
+
+use crate::accel::{self, Accel};
+use crate::chip::{self, Imperfections, Int1, Lis3dh};
+use crate::motion::{Motion, Path};
+
+/// Most motion sensors on a board, as on the real one.
+pub const MAX_SENSORS: usize = 4;
+
+/// How a sensor is connected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    /// On I2C, with SA0 at this level.
+    I2c { sa0: bool },
+    Spi,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Where the viewer listens.
+    pub server: SocketAddr,
+    pub sensors: Vec<Transport>,
+    pub sensor: accel::Config,
+    pub recovery: RecoveryPolicy,
+    pub output: OutputMode,
+    pub path: Path,
+}
+
+/// A board the viewer tells apart from the others by `address`, the local address its
+/// connections are made from.
+pub struct Board {
+    pub index: usize,
+    pub address: Option<IpAddr>,
+    pub config: Config,
+}
+
+/// Set up the sensors of the board and forward their frames, reconnecting whenever the
+/// connection fails. Runs on a `LocalSet`, like the firmware on its single-threaded executor.
+pub async fn run(board: Board) {
+    let Board { index, mut address, config } = board;
+    let motion = Arc::new(Mutex::new(Motion::new(config.path, index as f32 * 1.7)));
+    let (calibrate, _) = watch::channel(());
+    let (sender, messages) = mpsc::channel(8);
+
+    for (sensor, transport) in config.sensors.iter().enumerate() {
+        let seed = (index * MAX_SENSORS + sensor) as u64;
+        let chip = Lis3dh::new(motion.clone(), Imperfections::random(seed), seed);
+        let irq = Int1::new(chip.clone());
+        let sensor = sensor as u8;
+        let host = SensorHost {
+            sender: sender.clone(),
+            calibrate: calibrate.subscribe(),
+            stored: None,
+        };
+        match *transport {
+            Transport::I2c { sa0 } => {
+                let registers = lis3dh::i2c_registers(chip::I2cBus::new(chip, sa0), sa0);
+                spawn_sensor(index, sensor, registers, irq, &config, host);
+            }
+            Transport::Spi => {
+                let registers = lis3dh::spi_registers(chip::SpiBus::new(chip));
+                spawn_sensor(index, sensor, registers, irq, &config, host);
+            }
+        }
+    }
+
+    let mut sensors = BoardSensors { messages, motion, calibrate };
+    let mut cache = Cache::<MAX_SENSORS>::default();
+    loop {
+        match connect(index, config.server, &mut address).await {
+            Ok(connection) => {
+                log::info!("Board {} connected to {}. Forwarding stream...", index, config.server);
+                if let Err(e) = link::forward(Viewer(connection), &mut sensors, &mut cache).await {
+                    log::warn!("Board {}: error while forwarding stream: {:?}", index, e);
+                }
+            }
+            Err(e) => {
+                log::warn!("Board {} failed connecting to {}: {}", index, config.server, e);
+                sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Connect from `address` if given. Some systems only have 127.0.0.1 on the loopback
+/// interface, so when binding fails the address is dropped and the system picks one instead.
+async fn connect(index: usize, server: SocketAddr, address: &mut Option<IpAddr>) -> std::io::Result<TcpStream> {
+    let socket = match server {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if let Some(ip) = *address {
+        if let Err(e) = socket.bind(SocketAddr::new(ip, 0)) {
+            log::warn!("Failed binding board {} to {}, connecting from the default address: {}", index, ip, e);
+            *address = None;
+        }
+    }
+    socket.connect(server).await
+}
+
+/// The connection to the viewer.
+struct Viewer(TcpStream);
+
+impl Connection for Viewer {
+    type Error = std::io::Error;
+
+    async fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf).await
+    }
+
+    async fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.0.write_all(buf).await
+    }
+}
+
+/// The sensor tasks of a board, and the motion of the board they sense.
+struct BoardSensors {
+    messages: mpsc::Receiver<Message>,
+    motion: Arc<Mutex<Motion>>,
+    calibrate: watch::Sender<()>,
+}
+
+impl Sensors for BoardSensors {
+    async fn receive(&mut self) -> Message {
+        // `run` holds a sender for as long as the board runs
+        self.messages.recv().await.expect("sensor tasks never stop")
+    }
+
+    fn calibrate(&mut self) {
+        // Where the firmware waits for the user to turn the board, turn it ourselves
+        self.motion.lock().unwrap().pose(Instant::now());
+        self.calibrate.send_replace(());
+    }
+}
+
+/// What the sampling loop of a sensor needs from the board.
+struct SensorHost {
+    sender: mpsc::Sender<Message>,
+    calibrate: watch::Receiver<()>,
+    /// Stands in for the flash the firmware stores calibrations in, until the simulator exits.
+    stored: Option<Calibration>,
+}
+
+impl Host for SensorHost {
+    type StorageError = Infallible;
+
+    async fn send(&mut self, message: Message) {
+        let _ = self.sender.send(message).await;
+    }
+
+    async fn sleep(&mut self, duration: Duration) {
+        sleep(duration).await
+    }
+
+    fn calibration_requested(&mut self) -> bool {
+        let requested = self.calibrate.has_changed().unwrap_or(false);
+        self.calibrate.mark_unchanged();
+        requested
+    }
+
+    async fn load_calibration(&mut self) -> Option<Calibration> {
+        self.stored
+    }
+
+    async fn store_calibration(&mut self, calibration: &Calibration) -> Result<(), Infallible> {
+        self.stored = Some(*calibration);
+        Ok(())
+    }
+
+    /// The simulated buses never get stuck.
+    async fn recover_bus(&mut self) {}
+}
+
+fn spawn_sensor<R>(board: usize, sensor: u8, registers: R, irq: Int1, config: &Config, mut host: SensorHost)
+where
+    R: Registers + 'static,
+    R::Error: std::fmt::Debug,
+{
+    let (settings, policy, output) = (config.sensor, config.recovery, config.output);
+    tokio::task::spawn_local(async move {
+        let mut xl = match Accel::new(registers, irq, settings).await {
+            Ok(xl) => xl,
+            Err(e) => return log::error!("Board {} xl{} init failed: {:?}", board, sensor, e),
+        };
+        let self_test = match xl.self_test().await {
+            Ok(self_test) => self_test,
+            Err(e) => return log::error!("Board {} xl{} self-test failed to run: {:?}", board, sensor, e),
+        };
+        if !self_test.passed() {
+            log::error!("Board {} xl{} self-test failed: {:?}", board, sensor, self_test);
+        }
+        let health = Health { self_test, status: SensorStatus::Ok };
+        // Sent first, so the health is known before any samples are forwarded
+        host.send(Message::Health { sensor, health }).await;
+        sensor::run(sensor, xl, host, policy, output, health).await;
+    });
+}
@@ -0,0 +1,545 @@
+//! A simulated LIS3DH, down to its registers.
+//!
+//! Models what the firmware uses: the output data rate, full scale and resolution, the output
+//! registers, the FIFO in bypass and stream mode, data-ready and watermark interrupts on INT1,
+//! self-test and WHO_AM_I. Motion detection is not modelled, its sources always read zero.
+//!
+//! The chip is reached through [`I2cBus`] and [`SpiBus`], which decode transfers the way the
+//! part does, so register access with the wrong framing reads the wrong registers, as it would
+//! on a board. Readings come from the [`Motion`] of the board, with the offset and gain error
+//! of the part and some noise.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::i2c::{self, I2c, NoAcknowledgeSource};
+use embedded_hal_async::spi::{self, SpiDevice};
+use tokio::time::{sleep_until, Duration, Instant};
+use workshop_common::registers::SPI_READ;
+
+use crate::motion::Motion;
+
+/// Register addresses, see the LIS3DH datasheet section 7.
+pub mod register {
+    pub const WHO_AM_I: u8 = 0x0F;
+    pub const CTRL1: u8 = 0x20;
+    pub const CTRL3: u8 = 0x22;
+    pub const CTRL4: u8 = 0x23;
+    pub const CTRL5: u8 = 0x24;
+    pub const STATUS: u8 = 0x27;
+    pub const OUT_X_L: u8 = 0x28;
+    pub const OUT_Z_H: u8 = 0x2D;
+    pub const FIFO_CTRL: u8 = 0x2E;
+    pub const FIFO_SRC: u8 = 0x2F;
+    pub const INT1_SRC: u8 = 0x31;
+    pub const INT2_SRC: u8 = 0x35;
+    pub const CLICK_SRC: u8 = 0x39;
+}
+
+/// Value of the WHO_AM_I register.
+pub const WHO_AM_I: u8 = 0x33;
+
+/// I2C address with SA0 low, the address with SA0 high is one more.
+pub const ADDRESS: u8 = 0x18;
+/// Set in the register address of I2C transfers to auto-increment.
+pub const AUTO_INCREMENT: u8 = 0x80;
+/// Set in the first byte of SPI transfers to auto-increment.
+pub const SPI_AUTO_INCREMENT: u8 = 0x40;
+
+/// Number of samples the FIFO holds.
+pub const FIFO_DEPTH: usize = 32;
+
+const CTRL1_LPEN: u8 = 1 << 3;
+const CTRL3_I1_ZYXDA: u8 = 1 << 4;
+const CTRL3_I1_WTM: u8 = 1 << 2;
+const CTRL3_I1_OVERRUN: u8 = 1 << 1;
+const CTRL4_HR: u8 = 1 << 3;
+const CTRL4_ST_MASK: u8 = 0b11 << 1;
+const CTRL5_FIFO_EN: u8 = 1 << 6;
+const STATUS_ZYXOR: u8 = 1 << 7;
+const STATUS_ZYXDA: u8 = 1 << 3;
+const FIFO_CTRL_MODE_MASK: u8 = 0b11 << 6;
+const FIFO_CTRL_MODE_BYPASS: u8 = 0;
+const FIFO_CTRL_MODE_FIFO: u8 = 0b01 << 6;
+const FIFO_CTRL_FTH_MASK: u8 = 0x1F;
+const FIFO_SRC_WTM: u8 = 1 << 7;
+const FIFO_SRC_OVRN: u8 = 1 << 6;
+const FIFO_SRC_EMPTY: u8 = 1 << 5;
+
+/// Output data rates selected by CTRL1 ODR[3:0], in Hz. 9 is 1344 Hz outside of low-power mode.
+const DATA_RATES: [f32; 10] = [0.0, 1.0, 10.0, 25.0, 50.0, 100.0, 200.0, 400.0, 1600.0, 1344.0];
+
+/// Deflection of each axis in self-test 0, in g. Self-test 1 deflects the other way.
+const SELF_TEST_DEFLECTION: [f32; 3] = [0.28, 0.31, 0.55];
+
+/// Most samples taken in one go when the simulation falls behind, older ones are skipped.
+const MAX_CATCH_UP: u32 = 2 * FIFO_DEPTH as u32;
+
+/// How a part deviates from an ideal sensor.
+#[derive(Clone, Copy, Debug)]
+pub struct Imperfections {
+    /// Zero-g offset of each axis, in g.
+    pub offset: [f32; 3],
+    /// Gain of each axis.
+    pub gain: [f32; 3],
+    /// Amplitude of the noise, in g.
+    pub noise: f32,
+}
+
+impl Imperfections {
+    /// Plausible errors, different for each `seed`.
+    pub fn random(seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        Self {
+            offset: [(); 3].map(|_| 0.04 * rng.next()),
+            gain: [(); 3].map(|_| 1.0 + 0.03 * rng.next()),
+            noise: 0.004,
+        }
+    }
+}
+
+/// The state of the part, shared by its bus and interrupt pins.
+pub struct Lis3dh {
+    registers: [u8; 0x40],
+    motion: Arc<Mutex<Motion>>,
+    imperfections: Imperfections,
+    rng: Rng,
+    /// Latest sample, shown in the output registers outside of FIFO mode.
+    output: [i16; 3],
+    data_ready: bool,
+    overwritten: bool,
+    fifo: VecDeque<[i16; 3]>,
+    /// When the next sample is taken, if the part is not powered down.
+    next: Option<Instant>,
+}
+
+impl Lis3dh {
+    /// A part in its power-on state, on a board moving as `motion`.
+    pub fn new(motion: Arc<Mutex<Motion>>, imperfections: Imperfections, seed: u64) -> Chip {
+        let mut registers = [0; 0x40];
+        // Power-on defaults of the datasheet, everything else is zero
+        registers[register::CTRL1 as usize] = 0x07;
+        Arc::new(Mutex::new(Self {
+            registers,
+            motion,
+            imperfections,
+            rng: Rng::new(seed),
+            output: [0; 3],
+            data_ready: false,
+            overwritten: false,
+            fifo: VecDeque::with_capacity(FIFO_DEPTH),
+            next: None,
+        }))
+    }
+
+    fn register(&self, register: u8) -> u8 {
+        self.registers[register as usize]
+    }
+
+    fn period(&self) -> Option<Duration> {
+        let odr = (self.register(register::CTRL1) >> 4) as usize;
+        let mut hz = *DATA_RATES.get(odr)?;
+        if odr == 9 && self.register(register::CTRL1) & CTRL1_LPEN != 0 {
+            hz = 5376.0;
+        }
+        (hz > 0.0).then(|| Duration::from_secs_f32(1.0 / hz))
+    }
+
+    fn fifo_mode(&self) -> u8 {
+        if self.register(register::CTRL5) & CTRL5_FIFO_EN == 0 {
+            return FIFO_CTRL_MODE_BYPASS;
+        }
+        self.register(register::FIFO_CTRL) & FIFO_CTRL_MODE_MASK
+    }
+
+    /// Take the samples due by `now`.
+    pub fn advance(&mut self, now: Instant) {
+        let Some(period) = self.period() else {
+            self.next = None;
+            return;
+        };
+        let mut next = self.next.unwrap_or(now + period);
+        if now.saturating_duration_since(next) > period * MAX_CATCH_UP {
+            next = now - period * MAX_CATCH_UP;
+        }
+        while next <= now {
+            self.sample(next);
+            next += period;
+        }
+        self.next = Some(next);
+    }
+
+    /// When INT1 may change next, if it can change without a register access.
+    fn next_event(&self) -> Option<Instant> {
+        self.next
+    }
+
+    fn sample(&mut self, t: Instant) {
+        let gravity = self.motion.lock().unwrap().gravity(t);
+        let ctrl1 = self.register(register::CTRL1);
+        let ctrl4 = self.register(register::CTRL4);
+        // Sensitivity in g per 12-bit digit, which is not the range over 2048 at ±16 g
+        let sensitivity = [0.001, 0.002, 0.004, 0.012][((ctrl4 >> 4) & 0b11) as usize];
+        let bits = match (ctrl1 & CTRL1_LPEN != 0, ctrl4 & CTRL4_HR != 0) {
+            (true, _) => 8,
+            (false, true) => 12,
+            (false, false) => 10,
+        };
+        let deflection = match (ctrl4 & CTRL4_ST_MASK) >> 1 {
+            0b01 => 1.0,
+            0b10 => -1.0,
+            _ => 0.0,
+        };
+
+        let mut counts = [0; 3];
+        for (axis, count) in counts.iter_mut().enumerate() {
+            let imperfections = &self.imperfections;
+            let g = gravity[axis] * imperfections.gain[axis]
+                + imperfections.offset[axis]
+                + imperfections.noise * self.rng.next()
+                + deflection * SELF_TEST_DEFLECTION[axis];
+            let value = (g / sensitivity * 16.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            // Left-justified, with the bits below the resolution zero
+            *count = value & !((1 << (16 - bits)) - 1);
+        }
+
+        self.overwritten = self.data_ready;
+        self.data_ready = true;
+        self.output = counts;
+        match self.fifo_mode() {
+            FIFO_CTRL_MODE_BYPASS => {}
+            // FIFO mode stops collecting once full, stream mode drops the oldest sample
+            FIFO_CTRL_MODE_FIFO if self.fifo.len() == FIFO_DEPTH => {}
+            _ => {
+                if self.fifo.len() == FIFO_DEPTH {
+                    self.fifo.pop_front();
+                }
+                self.fifo.push_back(counts);
+            }
+        }
+    }
+
+    fn int1(&self) -> bool {
+        let ctrl3 = self.register(register::CTRL3);
+        let pending = self.fifo.len();
+        let watermark = (self.register(register::FIFO_CTRL) & FIFO_CTRL_FTH_MASK) as usize;
+        (ctrl3 & CTRL3_I1_ZYXDA != 0 && self.data_ready)
+            || (ctrl3 & CTRL3_I1_WTM != 0 && pending > watermark)
+            || (ctrl3 & CTRL3_I1_OVERRUN != 0 && pending == FIFO_DEPTH)
+    }
+
+    /// Read consecutive registers from `start`, returning the address after the last one read.
+    fn read(&mut self, start: u8, increment: bool, buf: &mut [u8]) -> u8 {
+        let mut address = start;
+        for byte in buf {
+            *byte = self.read_register(address);
+            if increment {
+                address = match address {
+                    // In FIFO mode the address wraps around the output registers, so the whole
+                    // FIFO can be read in one burst
+                    register::OUT_Z_H if self.fifo_mode() != FIFO_CTRL_MODE_BYPASS => register::OUT_X_L,
+                    _ => (address + 1) & 0x3F,
+                };
+            }
+        }
+        address
+    }
+
+    fn read_register(&mut self, address: u8) -> u8 {
+        match address {
+            register::WHO_AM_I => WHO_AM_I,
+            register::STATUS => {
+                let mut status = 0;
+                if self.data_ready {
+                    status |= STATUS_ZYXDA | 0b111;
+                }
+                if self.overwritten {
+                    status |= STATUS_ZYXOR | 0b111 << 4;
+                }
+                status
+            }
+            register::OUT_X_L..=register::OUT_Z_H => {
+                let fifo = self.fifo_mode() != FIFO_CTRL_MODE_BYPASS;
+                let sample = match fifo {
+                    true => self.fifo.front().copied().unwrap_or(self.output),
+                    false => self.output,
+                };
+                let offset = address - register::OUT_X_L;
+                let byte = sample[offset as usize / 2].to_le_bytes()[offset as usize % 2];
+                // Reading the last output register pops the sample
+                if address == register::OUT_Z_H {
+                    if fifo {
+                        self.fifo.pop_front();
+                    }
+                    self.data_ready = false;
+                    self.overwritten = false;
+                }
+                byte
+            }
+            register::FIFO_SRC => {
+                let pending = self.fifo.len();
+                let watermark = (self.register(register::FIFO_CTRL) & FIFO_CTRL_FTH_MASK) as usize;
+                let mut src = (pending as u8) & 0x1F;
+                if pending > watermark {
+                    src |= FIFO_SRC_WTM;
+                }
+                if pending == FIFO_DEPTH {
+                    src |= FIFO_SRC_OVRN;
+                }
+                if pending == 0 {
+                    src |= FIFO_SRC_EMPTY;
+                }
+                src
+            }
+            // Latched motion sources, of which there are none
+            register::INT1_SRC | register::INT2_SRC | register::CLICK_SRC => 0,
+            _ => self.register(address),
+        }
+    }
+
+    fn write(&mut self, start: u8, increment: bool, data: &[u8]) {
+        let mut address = start;
+        for &value in data {
+            self.write_register(address, value);
+            if increment {
+                address = (address + 1) & 0x3F;
+            }
+        }
+    }
+
+    fn write_register(&mut self, address: u8, value: u8) {
+        match address {
+            // Read-only
+            register::WHO_AM_I | register::STATUS..=register::OUT_Z_H | register::FIFO_SRC => return,
+            register::FIFO_CTRL if value & FIFO_CTRL_MODE_MASK == FIFO_CTRL_MODE_BYPASS => self.fifo.clear(),
+            register::CTRL1 => self.next = None,
+            _ => {}
+        }
+        self.registers[address as usize] = value;
+    }
+}
+
+/// A part shared by its bus and interrupt pins.
+pub type Chip = Arc<Mutex<Lis3dh>>;
+
+/// Errors of the simulated buses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusError {
+    /// No part answered the address.
+    NoAcknowledge,
+    /// A transfer the part does not understand, such as a read without a register address.
+    Framing,
+}
+
+impl i2c::Error for BusError {
+    fn kind(&self) -> i2c::ErrorKind {
+        match self {
+            BusError::NoAcknowledge => i2c::ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address),
+            BusError::Framing => i2c::ErrorKind::Other,
+        }
+    }
+}
+
+impl spi::Error for BusError {
+    fn kind(&self) -> spi::ErrorKind {
+        spi::ErrorKind::Other
+    }
+}
+
+/// The part on an I2C bus of its own.
+pub struct I2cBus {
+    chip: Chip,
+    address: u8,
+}
+
+impl I2cBus {
+    /// `sa0` is the level of the SA0 pin, which selects the address.
+    pub fn new(chip: Chip, sa0: bool) -> Self {
+        Self {
+            chip,
+            address: ADDRESS + sa0 as u8,
+        }
+    }
+}
+
+impl i2c::ErrorType for I2cBus {
+    type Error = BusError;
+}
+
+impl I2c for I2cBus {
+    async fn transaction(&mut self, address: u8, operations: &mut [i2c::Operation<'_>]) -> Result<(), Self::Error> {
+        if address != self.address {
+            return Err(BusError::NoAcknowledge);
+        }
+        let mut chip = self.chip.lock().unwrap();
+        chip.advance(Instant::now());
+
+        // A write starts with the register address, with the MSB set to auto-increment. Reads
+        // continue from the register address of the preceding write.
+        let mut pointer = None;
+        for operation in operations {
+            match operation {
+                i2c::Operation::Write(bytes) => {
+                    let Some((&first, data)) = bytes.split_first() else {
+                        continue;
+                    };
+                    let increment = first & AUTO_INCREMENT != 0;
+                    let start = first & !AUTO_INCREMENT;
+                    chip.write(start, increment, data);
+                    let written = if increment { data.len() as u8 } else { 0 };
+                    pointer = Some((start.wrapping_add(written) & 0x3F, increment));
+                }
+                i2c::Operation::Read(buf) => {
+                    let (start, increment) = pointer.ok_or(BusError::Framing)?;
+                    let next = chip.read(start, increment, buf);
+                    pointer = Some((next, increment));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The part on a SPI bus of its own, selected for the duration of each transaction.
+pub struct SpiBus {
+    chip: Chip,
+}
+
+impl SpiBus {
+    pub fn new(chip: Chip) -> Self {
+        Self { chip }
+    }
+}
+
+impl spi::ErrorType for SpiBus {
+    type Error = BusError;
+}
+
+/// What the first byte of a SPI transaction asked for.
+#[derive(Clone, Copy)]
+struct Command {
+    read: bool,
+    increment: bool,
+    address: u8,
+}
+
+impl SpiDevice for SpiBus {
+    async fn transaction(&mut self, operations: &mut [spi::Operation<'_, u8>]) -> Result<(), Self::Error> {
+        let mut chip = self.chip.lock().unwrap();
+        chip.advance(Instant::now());
+
+        // The first byte holds the read bit, the auto-increment bit and the register address
+        let mut command: Option<Command> = None;
+        for operation in operations {
+            match operation {
+                spi::Operation::Write(bytes) => {
+                    for &byte in bytes.iter() {
+                        match &mut command {
+                            None => {
+                                command = Some(Command {
+                                    read: byte & SPI_READ != 0,
+                                    increment: byte & SPI_AUTO_INCREMENT != 0,
+                                    address: byte & 0x3F,
+                                });
+                            }
+                            Some(c) if !c.read => {
+                                chip.write(c.address, false, &[byte]);
+                                if c.increment {
+                                    c.address = (c.address + 1) & 0x3F;
+                                }
+                            }
+                            // Clocking out a read while writing discards what the part sent
+                            Some(c) => c.address = chip.read(c.address, c.increment, &mut [0]),
+                        }
+                    }
+                }
+                spi::Operation::Read(buf) => match &mut command {
+                    Some(c) if c.read => c.address = chip.read(c.address, c.increment, buf),
+                    _ => return Err(BusError::Framing),
+                },
+                spi::Operation::DelayNs(_) => {}
+                spi::Operation::Transfer(..) | spi::Operation::TransferInPlace(_) => return Err(BusError::Framing),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The INT1 pin of the part.
+pub struct Int1 {
+    chip: Chip,
+}
+
+impl Int1 {
+    pub fn new(chip: Chip) -> Self {
+        Self { chip }
+    }
+
+    async fn wait_for(&mut self, level: bool) {
+        loop {
+            let next = {
+                let mut chip = self.chip.lock().unwrap();
+                let now = Instant::now();
+                chip.advance(now);
+                if chip.int1() == level {
+                    return;
+                }
+                // Only a sample can change the level without a register access, poll otherwise
+                chip.next_event().unwrap_or(now + Duration::from_millis(10))
+            };
+            sleep_until(next).await;
+        }
+    }
+}
+
+impl embedded_hal::digital::ErrorType for Int1 {
+    type Error = std::convert::Infallible;
+}
+
+impl Wait for Int1 {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(true).await;
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(false).await;
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(false).await;
+        self.wait_for(true).await;
+        Ok(())
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(true).await;
+        self.wait_for(false).await;
+        Ok(())
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        let level = self.chip.lock().unwrap().int1();
+        self.wait_for(!level).await;
+        Ok(())
+    }
+}
+
+/// Xorshift, roughly uniform in [-1, 1).
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift never leaves zero
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
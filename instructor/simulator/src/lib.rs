@@ -0,0 +1,7 @@
+//! Simulated workshop boards, for the `xl-simulator` binary and for testing the viewer and the
+//! shared firmware code on the host.
+
+pub mod accel;
+pub mod board;
+pub mod chip;
+pub mod motion;
@@ -0,0 +1,103 @@
+//! Simulated workshop boards, streaming to `tcp-3d-viewer` like the firmware does.
+//!
+//! Each board runs the firmware's sampling and forwarding logic on a simulated LIS3DH, down to
+//! the I2C and SPI register transfers, and connects to the viewer on its own. The viewer tells
+//! boards apart by their IP address, so boards connecting to a viewer on the loopback interface
+//! each connect from their own loopback address, 127.0.1.1 and up.
+//!
+//! ```text
+//! xl-simulator [--server 127.0.0.1:8080] [--boards 1] [--sensors i2c,spi]
+//!              [--output vectors|attitude|raw|compressed] [--motion still|wobble|tumble]
+//!              [--rate 400] [--range 2] [--watermark 16]
+//! ```
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+use workshop_common::sensor::{OutputMode, RecoveryPolicy};
+use workshop_simulator::accel;
+use workshop_simulator::board::{self, Board, Config, Transport, MAX_SENSORS};
+use workshop_simulator::motion::Path;
+
+/// First of the loopback addresses boards connect from.
+const FIRST_ADDRESS: Ipv4Addr = Ipv4Addr::new(127, 0, 1, 1);
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    env_logger::init();
+
+    let boards = option("--boards", 1usize);
+    let config = Config {
+        server: option("--server", SocketAddr::from(([127, 0, 0, 1], 8080))),
+        sensors: sensors(&option("--sensors", String::from("i2c,spi"))),
+        sensor: accel::Config {
+            rate: option("--rate", 400.0),
+            range: option("--range", 2.0),
+            watermark: option("--watermark", 16),
+        },
+        recovery: RecoveryPolicy::default(),
+        output: option("--output", OutputMode::Vectors),
+        path: option("--motion", Path::Wobble),
+    };
+
+    log::info!(
+        "Simulating {} boards with sensors {:?}, sending {:?} to {}",
+        boards,
+        config.sensors,
+        config.output,
+        config.server
+    );
+    let local = tokio::task::LocalSet::new();
+    for index in 0..boards {
+        let address = match config.server.ip() {
+            IpAddr::V4(ip) if ip.is_loopback() => Some(IpAddr::V4(Ipv4Addr::from(u32::from(FIRST_ADDRESS) + index as u32))),
+            _ => None,
+        };
+        local.spawn_local(board::run(Board {
+            index,
+            address,
+            config: config.clone(),
+        }));
+    }
+    local.await;
+}
+
+/// The value following `name` on the command line, or `default` if it is not given. Exits on
+/// values that do not parse.
+fn option<T: FromStr>(name: &str, default: T) -> T
+where
+    T::Err: std::fmt::Display,
+{
+    match std::env::args().skip_while(|arg| arg != name).nth(1) {
+        Some(value) => value.parse().unwrap_or_else(|e| {
+            eprintln!("Invalid {} '{}': {}", name, value, e);
+            std::process::exit(2);
+        }),
+        None => default,
+    }
+}
+
+/// The transports of the sensors listed as `i2c` or `spi`. Sensors on I2C alternate between
+/// the two addresses, as sensors sharing a bus would.
+fn sensors(list: &str) -> Vec<Transport> {
+    let mut i2c = 0;
+    let sensors: Vec<_> = list
+        .split(',')
+        .map(|name| match name {
+            "i2c" => {
+                i2c += 1;
+                Transport::I2c { sa0: i2c % 2 == 0 }
+            }
+            "spi" => Transport::Spi,
+            _ => {
+                eprintln!("Unknown sensor transport '{}', expected i2c or spi", name);
+                std::process::exit(2);
+            }
+        })
+        .collect();
+    if sensors.len() > MAX_SENSORS {
+        eprintln!("At most {} sensors per board", MAX_SENSORS);
+        std::process::exit(2);
+    }
+    sensors
+}
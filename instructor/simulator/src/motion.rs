@@ -0,0 +1,101 @@
+//! How a simulated board moves.
+
+use std::f32::consts::PI;
+
+use tokio::time::{Duration, Instant};
+use workshop_common::calibration::{orientation, ORIENTATIONS};
+use workshop_common::motion::{Axis, Sign};
+
+/// How long each orientation is held while calibrating, well over the calibration window.
+const POSE_DURATION: Duration = Duration::from_millis(1500);
+
+/// Path the board follows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Path {
+    /// Lying flat, perfectly still.
+    Still,
+    /// Gently rocking about x and y, like a board held in a hand.
+    Wobble,
+    /// Slowly turning over about all axes.
+    Tumble,
+}
+
+impl std::str::FromStr for Path {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "still" => Ok(Path::Still),
+            "wobble" => Ok(Path::Wobble),
+            "tumble" => Ok(Path::Tumble),
+            _ => Err(format!("unknown motion '{}', expected still, wobble or tumble", s)),
+        }
+    }
+}
+
+/// Orientation of a board over time, as the gravity its sensors measure.
+pub struct Motion {
+    path: Path,
+    /// Offset into the path, so boards started together do not move in lockstep.
+    phase: f32,
+    start: Instant,
+    /// When the board started going through the calibration orientations.
+    posing: Option<Instant>,
+}
+
+impl Motion {
+    pub fn new(path: Path, phase: f32) -> Self {
+        Self {
+            path,
+            phase,
+            start: Instant::now(),
+            posing: None,
+        }
+    }
+
+    /// Hold the board still in each calibration orientation in turn, as a user would. Called
+    /// again while posing, the board goes back to its path, like a cancelled calibration.
+    pub fn pose(&mut self, now: Instant) {
+        self.posing = match self.posing {
+            Some(_) => None,
+            None => Some(now),
+        };
+    }
+
+    /// Acceleration in g at `t`, in board coordinates.
+    pub fn gravity(&mut self, t: Instant) -> [f32; 3] {
+        if let Some(start) = self.posing {
+            let pose = (t.saturating_duration_since(start).as_millis() / POSE_DURATION.as_millis()) as usize;
+            if pose < ORIENTATIONS {
+                return pose_gravity(orientation(pose));
+            }
+            self.posing = None;
+        }
+
+        let t = t.saturating_duration_since(self.start).as_secs_f32() + self.phase;
+        let (roll, pitch) = match self.path {
+            Path::Still => (0.0, 0.0),
+            Path::Wobble => (0.4 * (0.7 * t).sin(), 0.3 * (0.45 * t).sin()),
+            Path::Tumble => ((0.5 * t) % (2.0 * PI), 0.35 * t % (2.0 * PI)),
+        };
+        // Gravity points down the z axis of a level board, rolled about x and then pitched about y
+        let (sr, cr) = roll.sin_cos();
+        let (sp, cp) = pitch.sin_cos();
+        [-sp, sr * cp, cr * cp]
+    }
+}
+
+/// Gravity with the axis of the orientation pointing up or down.
+fn pose_gravity((axis, sign): (Axis, Sign)) -> [f32; 3] {
+    let mut g = [0.0; 3];
+    let i = match axis {
+        Axis::X => 0,
+        Axis::Y => 1,
+        Axis::Z => 2,
+    };
+    g[i] = match sign {
+        Sign::Positive => 1.0,
+        Sign::Negative => -1.0,
+    };
+    g
+}
@@ -0,0 +1,80 @@
+//! The shared LIS3DH register code driving the simulated part over each transport, as the
+//! firmware drives the real one.
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+use tokio::time::{timeout, Duration};
+use workshop_common::lis3dh::{self, Output, FIFO_DEPTH};
+use workshop_common::registers::Registers;
+use workshop_simulator::chip::{self, Chip, Imperfections, Int1, Lis3dh};
+use workshop_simulator::motion::{Motion, Path};
+
+const WATERMARK: u8 = 16;
+
+/// A part without offset, gain error or noise, lying flat.
+fn ideal_chip() -> Chip {
+    let motion = Arc::new(Mutex::new(Motion::new(Path::Still, 0.0)));
+    let imperfections = Imperfections { offset: [0.0; 3], gain: [1.0; 3], noise: 0.0 };
+    Lis3dh::new(motion, imperfections, 0)
+}
+
+struct Sleep;
+
+impl DelayNs for Sleep {
+    async fn delay_ns(&mut self, ns: u32) {
+        tokio::time::sleep(Duration::from_nanos(ns.into())).await
+    }
+}
+
+/// Configure the part through `registers`, run its self-test and read a batch from its FIFO.
+async fn sample<R: Registers>(mut registers: R, chip: Chip)
+where
+    R::Error: Debug,
+{
+    let output = Output::new(400.0, 2.0);
+    lis3dh::configure(&mut registers, output).await.unwrap();
+    lis3dh::enable_fifo(&mut registers, WATERMARK).await.unwrap();
+
+    let self_test = lis3dh::self_test(&mut registers, &mut Sleep).await.unwrap();
+    assert_eq!(self_test.who_am_i, chip::WHO_AM_I);
+    assert!(self_test.passed(), "{:?}", self_test);
+
+    // The self-test emptied the FIFO and restored streaming, so the watermark comes again
+    let mut irq = Int1::new(chip);
+    timeout(Duration::from_secs(1), irq.wait_for_high()).await.expect("no watermark").unwrap();
+    let fifo = lis3dh::read_fifo(&mut registers).await.unwrap();
+    assert!(!fifo.overrun);
+    assert!(fifo.samples.len() > WATERMARK as usize && fifo.samples.len() <= FIFO_DEPTH, "{} samples", fifo.samples.len());
+    for sample in &fifo.samples {
+        let g = [sample.x, sample.y, sample.z].map(|count| count as f32 * output.scale());
+        assert_eq!(g, [0.0, 0.0, 1.0], "{:?}", sample);
+    }
+}
+
+#[tokio::test]
+async fn i2c() {
+    let chip = ideal_chip();
+    sample(lis3dh::i2c_registers(chip::I2cBus::new(chip.clone(), false), false), chip).await;
+}
+
+#[tokio::test]
+async fn i2c_alternate_address() {
+    let chip = ideal_chip();
+    sample(lis3dh::i2c_registers(chip::I2cBus::new(chip.clone(), true), true), chip).await;
+}
+
+#[tokio::test]
+async fn spi() {
+    let chip = ideal_chip();
+    sample(lis3dh::spi_registers(chip::SpiBus::new(chip.clone())), chip).await;
+}
+
+#[tokio::test]
+async fn i2c_wrong_address() {
+    let chip = ideal_chip();
+    let mut registers = lis3dh::i2c_registers(chip::I2cBus::new(chip, true), false);
+    assert_eq!(lis3dh::configure(&mut registers, Output::new(400.0, 2.0)).await, Err(chip::BusError::NoAcknowledge));
+}
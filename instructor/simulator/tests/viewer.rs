@@ -0,0 +1,89 @@
+//! Simulated boards streaming to the viewer's ingest server, through the shared sampling and
+//! forwarding loops and the shared LIS3DH register code.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use tcp_3d_viewer::ingest::{self, ClientState, Clients};
+use tokio::net::TcpListener;
+use tokio::task::{spawn_local, LocalSet};
+use tokio::time::{timeout, Duration};
+use workshop_common::health::SensorStatus;
+use workshop_common::sensor::{OutputMode, RecoveryPolicy};
+use workshop_simulator::accel;
+use workshop_simulator::board::{self, Board, Config, Transport};
+use workshop_simulator::motion::Path;
+
+/// Frames each sensor has to get through before the board counts as streaming.
+const FRAMES: u64 = 20;
+
+/// Run a still board with `sensors` against the viewer until each sensor passed its self-test
+/// and streamed a while, returning what the viewer made of it.
+async fn stream(sensors: Vec<Transport>, output: OutputMode) -> Arc<ClientState> {
+    let local = LocalSet::new();
+    local
+        .run_until(async {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let count = sensors.len();
+            let config = Config {
+                server: listener.local_addr().unwrap(),
+                sensors,
+                sensor: accel::Config::default(),
+                recovery: RecoveryPolicy::default(),
+                output,
+                path: Path::Still,
+            };
+            let clients = Arc::new(Clients::default());
+            spawn_local(ingest::serve(listener, clients.clone(), None));
+            spawn_local(board::run(Board { index: 0, address: None, config }));
+
+            let streaming = async {
+                loop {
+                    if let Some(client) = clients.get(&IpAddr::V4(Ipv4Addr::LOCALHOST)) {
+                        let state = client.state();
+                        let healthy = state.sensors.values().filter(|sensor| sensor.health.is_some()).count();
+                        if healthy == count && client.stats.frames.load(Ordering::Relaxed) >= FRAMES * count as u64 {
+                            assert_eq!(client.stats.invalid.load(Ordering::Relaxed), 0);
+                            return state;
+                        }
+                    }
+                    clients.changed().await;
+                }
+            };
+            timeout(Duration::from_secs(10), streaming).await.expect("board did not stream")
+        })
+        .await
+}
+
+/// Assert each sensor passed its self-test and is seen lying flat.
+fn assert_flat(state: &ClientState, count: usize) {
+    assert_eq!(state.sensors.len(), count);
+    for (index, sensor) in &state.sensors {
+        let health = sensor.health.as_ref().unwrap();
+        assert!(health.self_test.passed(), "sensor {} failed its self-test: {:?}", index, health);
+        assert_eq!(health.status, SensorStatus::Ok);
+        // Off by the offset and gain error of the part
+        let rotation = &sensor.rotation;
+        assert!(rotation.x.abs() < 0.15 && rotation.z.abs() < 0.15, "sensor {} is not flat: {:?}", index, rotation);
+    }
+}
+
+#[tokio::test]
+async fn board_streams_vectors() {
+    let state = stream(vec![Transport::I2c { sa0: false }], OutputMode::Vectors).await;
+    assert_flat(&state, 1);
+}
+
+#[tokio::test]
+async fn board_streams_compressed_raw_samples() {
+    let state = stream(vec![Transport::I2c { sa0: false }], OutputMode::Raw { compressed: true }).await;
+    assert_flat(&state, 1);
+}
+
+#[tokio::test]
+async fn board_streams_from_every_transport() {
+    let sensors = vec![Transport::I2c { sa0: false }, Transport::I2c { sa0: true }, Transport::Spi];
+    let state = stream(sensors, OutputMode::Vectors).await;
+    assert_flat(&state, 3);
+}
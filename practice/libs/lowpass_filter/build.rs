@@ -3,8 +3,20 @@ use std::path::PathBuf;
 
 fn main() {
     let target = env::var("TARGET").unwrap();
+    let bare_metal = env::var("CARGO_CFG_TARGET_OS").unwrap() == "none";
 
-    let bindings = bindgen::Builder::default()
+    // Compile the C source with the C compiler for the target: the host compiler for tests, and
+    // arm-none-eabi-gcc for the board (override with CC_<target>, e.g. CC=clang). The cc crate
+    // adds the matching -mthumb, -march and float ABI flags and tells cargo to link the result.
+    cc::Build::new()
+        .file("src/lowpass_filter.c")
+        .include("include")
+        .flag_if_supported("-std=c11")
+        .warnings(true)
+        .extra_warnings(true)
+        .compile("lowpass_filter");
+
+    let mut builder = bindgen::Builder::default()
         .header("include/lowpass_filter.h")
         .use_core() // no_std
         .ctypes_prefix("core::ffi")
@@ -12,12 +24,14 @@ fn main() {
         .derive_debug(false)
         .derive_default(false)
         .layout_tests(false) // IMPORTANT for no_std
-        .clang_arg("--target=arm-none-eabi")
-        .clang_arg("-march=armv8-m.main")
-        .clang_arg("-mfloat-abi=hard")
-        .clang_arg("-mfpu=fpv5-sp-d16")
-        .generate()
-        .expect("Unable to generate bindings");
+        // Parse the header as the C compiler sees it, so type sizes and layouts match
+        .clang_arg(format!("--target={}", target))
+        .clang_arg("-Iinclude");
+    if bare_metal {
+        // No libc for the target, stdint.h comes with clang
+        builder = builder.clang_arg("-ffreestanding");
+    }
+    let bindings = builder.generate().expect("Unable to generate bindings");
 
     let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
     bindings
@@ -25,10 +39,5 @@ fn main() {
         .expect("Couldn't write bindings!");
 
     println!("cargo:rerun-if-changed=include/lowpass_filter.h");
-
-    let mut lib_path = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
-    lib_path.push("lib");
-
-    println!("cargo:rustc-link-search={}", lib_path.to_str().unwrap());
-    println!("cargo:rustc-link-lib=static=lowpass_filter");
+    println!("cargo:rerun-if-changed=src/lowpass_filter.c");
 }
@@ -1,6 +1,7 @@
 #ifndef LOWPASS_FILTER_H
 #define LOWPASS_FILTER_H
 
+#include <stddef.h>
 #include <stdint.h>
 
 #ifdef __cplusplus
@@ -38,6 +39,72 @@ void lowpass_filter_init(LowpassFilter *filter, float alpha);
  */
 Sample lowpass_filter_apply(LowpassFilter *filter, Sample raw_sample);
 
+/**
+ * Apply the low-pass filter to consecutive samples.
+ *
+ * @param filter Pointer to filter instance
+ * @param input  Samples to filter
+ * @param output Filtered samples, may be the same array as input
+ * @param n      Number of samples
+ */
+void lowpass_filter_apply_n(LowpassFilter *filter, const Sample *input, Sample *output, size_t n);
+
+/**
+ * Forget the previous samples, the next sample initializes the filter again.
+ *
+ * @param filter Pointer to filter instance
+ */
+void lowpass_filter_reset(LowpassFilter *filter);
+
+/* Second order low-pass section, in transposed direct form II */
+typedef struct {
+    float b0;
+    float b1;
+    float b2;
+    float a1;
+    float a2;
+    uint8_t has_state;
+    Sample s1;
+    Sample s2;
+} Biquad;
+
+/**
+ * Initialize a biquad low-pass filter.
+ *
+ * @param filter      Pointer to filter instance
+ * @param sample_rate Sample rate in Hz
+ * @param cutoff      Cutoff frequency in Hz, below half the sample rate
+ * @param q           Quality factor, 0.7071 for a Butterworth response
+ * @return            0 on success, -1 if a parameter is out of range
+ */
+int biquad_init(Biquad *filter, float sample_rate, float cutoff, float q);
+
+/**
+ * Apply the biquad filter to a sample.
+ *
+ * @param filter     Pointer to filter instance
+ * @param raw_sample New input sample
+ * @return           Filtered sample
+ */
+Sample biquad_apply(Biquad *filter, Sample raw_sample);
+
+/**
+ * Apply the biquad filter to consecutive samples.
+ *
+ * @param filter Pointer to filter instance
+ * @param input  Samples to filter
+ * @param output Filtered samples, may be the same array as input
+ * @param n      Number of samples
+ */
+void biquad_apply_n(Biquad *filter, const Sample *input, Sample *output, size_t n);
+
+/**
+ * Forget the previous samples, the next sample initializes the filter again.
+ *
+ * @param filter Pointer to filter instance
+ */
+void biquad_reset(Biquad *filter);
+
 #ifdef __cplusplus
 }
 #endif
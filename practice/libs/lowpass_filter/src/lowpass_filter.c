@@ -35,3 +35,110 @@ Sample lowpass_filter_apply(LowpassFilter *filter, Sample raw_sample)
     filter->state = filtered;
     return filtered;
 }
+
+void lowpass_filter_apply_n(LowpassFilter *filter, const Sample *input, Sample *output, size_t n)
+{
+    if (!input || !output) {
+        return;
+    }
+
+    for (size_t i = 0; i < n; i++) {
+        output[i] = lowpass_filter_apply(filter, input[i]);
+    }
+}
+
+void lowpass_filter_reset(LowpassFilter *filter)
+{
+    if (!filter) {
+        return;
+    }
+
+    filter->has_state = 0;
+}
+
+#define PI_F 3.14159265358979f
+
+/* sin(x) for x in [-pi/2, pi/2], keeping the library free of libm */
+static float sin_poly(float x)
+{
+    float x2 = x * x;
+    return x * (1.0f - x2 / 6.0f * (1.0f - x2 / 20.0f * (1.0f - x2 / 42.0f * (1.0f - x2 / 72.0f * (1.0f - x2 / 110.0f)))));
+}
+
+int biquad_init(Biquad *filter, float sample_rate, float cutoff, float q)
+{
+    /* Written so that NaN fails the checks */
+    if (!filter || !(sample_rate > 0.0f) || !(cutoff > 0.0f) || !(cutoff < sample_rate / 2.0f) || !(q > 0.0f)) {
+        return -1;
+    }
+
+    /* Low-pass from the Audio EQ Cookbook, w0 is within (0, pi) */
+    float w0 = 2.0f * PI_F * cutoff / sample_rate;
+    float sin_w0 = sin_poly(w0 < PI_F / 2.0f ? w0 : PI_F - w0);
+    float cos_w0 = sin_poly(PI_F / 2.0f - w0);
+    float alpha = sin_w0 / (2.0f * q);
+    float a0 = 1.0f + alpha;
+
+    filter->b0 = (1.0f - cos_w0) / 2.0f / a0;
+    filter->b1 = (1.0f - cos_w0) / a0;
+    filter->b2 = filter->b0;
+    filter->a1 = -2.0f * cos_w0 / a0;
+    filter->a2 = (1.0f - alpha) / a0;
+    biquad_reset(filter);
+    return 0;
+}
+
+/* y = b0 * x + s1, s1 = b1 * x - a1 * y + s2, s2 = b2 * x - a2 * y */
+static float biquad_step(const Biquad *filter, float x, float *s1, float *s2)
+{
+    float y = filter->b0 * x + *s1;
+    *s1 = filter->b1 * x - filter->a1 * y + *s2;
+    *s2 = filter->b2 * x - filter->a2 * y;
+    return y;
+}
+
+Sample biquad_apply(Biquad *filter, Sample raw_sample)
+{
+    if (!filter) {
+        return raw_sample;
+    }
+
+    if (filter->has_state == 0) {
+        /* Start as if the first sample had always been the input, the gain at 0 Hz is 1 */
+        float s1 = 1.0f - filter->b0;
+        float s2 = filter->b2 - filter->a2;
+        filter->s1.x = s1 * raw_sample.x;
+        filter->s1.y = s1 * raw_sample.y;
+        filter->s1.z = s1 * raw_sample.z;
+        filter->s2.x = s2 * raw_sample.x;
+        filter->s2.y = s2 * raw_sample.y;
+        filter->s2.z = s2 * raw_sample.z;
+        filter->has_state = 1;
+    }
+
+    Sample filtered;
+    filtered.x = biquad_step(filter, raw_sample.x, &filter->s1.x, &filter->s2.x);
+    filtered.y = biquad_step(filter, raw_sample.y, &filter->s1.y, &filter->s2.y);
+    filtered.z = biquad_step(filter, raw_sample.z, &filter->s1.z, &filter->s2.z);
+    return filtered;
+}
+
+void biquad_apply_n(Biquad *filter, const Sample *input, Sample *output, size_t n)
+{
+    if (!input || !output) {
+        return;
+    }
+
+    for (size_t i = 0; i < n; i++) {
+        output[i] = biquad_apply(filter, input[i]);
+    }
+}
+
+void biquad_reset(Biquad *filter)
+{
+    if (!filter) {
+        return;
+    }
+
+    filter->has_state = 0;
+}
@@ -0,0 +1,63 @@
+//! The C and Rust filters on the same workload: a second of 400 Hz samples, one at a time and in
+//! batches.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use lowpass_filter::{Biquad as CBiquad, Filter, LowpassFilter as CFilter};
+use workshop_common::filter::{Biquad as RustBiquad, LowpassFilter as RustFilter};
+use workshop_common::Sample;
+
+const RATE: f32 = 400.0;
+const ALPHA: f32 = 0.1;
+const CUTOFF: f32 = 20.0;
+// Butterworth
+const Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// A slow tilt with some vibration on top.
+fn workload() -> Vec<Sample> {
+    (0..RATE as usize)
+        .map(|i| {
+            let t = i as f32 / RATE;
+            let vibration = 0.05 * (2.0 * std::f32::consts::PI * 120.0 * t).sin();
+            Sample::new(vibration, t.sin() + vibration, t.cos() - vibration)
+        })
+        .collect()
+}
+
+fn bench<F: Filter>(c: &mut Criterion, group: &str, name: &str, filter: impl Fn() -> F) {
+    let samples = workload();
+    let mut group = c.benchmark_group(group);
+    group.throughput(Throughput::Elements(samples.len() as u64));
+
+    group.bench_function(BenchmarkId::new(name, "apply"), |b| {
+        b.iter_batched_ref(
+            || (filter(), samples.clone()),
+            |(filter, samples)| {
+                for sample in samples.iter_mut() {
+                    *sample = filter.apply(*sample);
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function(BenchmarkId::new(name, "apply_n"), |b| {
+        b.iter_batched_ref(
+            || (filter(), samples.clone()),
+            |(filter, samples)| filter.apply_n(samples),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn lowpass(c: &mut Criterion) {
+    bench(c, "lowpass", "c", || CFilter::new(ALPHA).unwrap());
+    bench(c, "lowpass", "rust", || RustFilter::new(ALPHA));
+}
+
+fn biquad(c: &mut Criterion) {
+    bench(c, "biquad", "c", || CBiquad::new(RATE, CUTOFF, Q).unwrap());
+    bench(c, "biquad", "rust", || RustBiquad::new(RATE, CUTOFF, Q).unwrap());
+}
+
+criterion_group!(benches, lowpass, biquad);
+criterion_main!(benches);
@@ -0,0 +1,179 @@
+//! Safe wrapper around the C low-pass filter.
+//!
+//! [`LowpassFilter`] and [`Biquad`] own an initialized C filter and work on the firmware
+//! [`Sample`], so they can take the place of the pure-Rust filters in
+//! [`workshop_common::filter`]:
+//!
+//! ```ignore
+//! use lowpass_filter::LowpassFilter;
+//! use workshop_common::filter::Filter;
+//!
+//! let mut filter = LowpassFilter::new(0.2)?;
+//! let filtered = filter.apply(sample);
+//! ```
+#![no_std]
+
+use core::fmt;
+
+pub use lowpass_filter_sys as sys;
+pub use workshop_common::filter::Filter;
+use workshop_common::Sample;
+
+/// The alpha passed to [`LowpassFilter::new`] is not a number between 0 and 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidAlpha(pub f32);
+
+impl fmt::Display for InvalidAlpha {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "alpha {} is not within 0.0..=1.0", self.0)
+    }
+}
+
+impl core::error::Error for InvalidAlpha {}
+
+/// The parameters passed to [`Biquad::new`] do not give a stable low-pass filter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidBiquad;
+
+impl fmt::Display for InvalidBiquad {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cutoff has to be below half the sample rate, and all parameters positive")
+    }
+}
+
+impl core::error::Error for InvalidBiquad {}
+
+// Samples converted at a time by the batch functions, on the stack
+const CHUNK: usize = 32;
+
+/// Run a C batch function over `samples`, converting them in chunks.
+fn apply_chunked(samples: &mut [Sample], mut apply_n: impl FnMut(&mut [sys::Sample])) {
+    let mut buf = [sys::Sample { x: 0.0, y: 0.0, z: 0.0 }; CHUNK];
+    for chunk in samples.chunks_mut(CHUNK) {
+        let buf = &mut buf[..chunk.len()];
+        for (c, sample) in buf.iter_mut().zip(chunk.iter()) {
+            *c = to_c(sample);
+        }
+        apply_n(buf);
+        for (sample, c) in chunk.iter_mut().zip(buf.iter()) {
+            *sample = Sample { gyro: sample.gyro, ..from_c(*c) };
+        }
+    }
+}
+
+/// Exponential moving average over the acceleration, computed by the C library. The angular rate
+/// is passed through, since the C sample has no room for it.
+pub struct LowpassFilter {
+    raw: sys::LowpassFilter,
+}
+
+impl LowpassFilter {
+    /// `alpha` is the weight of each new sample, lower values are smoother but add more delay.
+    pub fn new(alpha: f32) -> Result<Self, InvalidAlpha> {
+        // Also rejects NaN
+        if !(0.0..=1.0).contains(&alpha) {
+            return Err(InvalidAlpha(alpha));
+        }
+        // Every field is set here, so the filter is valid whatever the C code leaves untouched
+        let mut raw = sys::LowpassFilter {
+            has_state: 0,
+            state: sys::Sample { x: 0.0, y: 0.0, z: 0.0 },
+            alpha,
+        };
+        // SAFETY: `raw` is a valid, exclusively borrowed filter for the duration of the call
+        unsafe { sys::lowpass_filter_init(&mut raw, alpha) };
+        Ok(Self { raw })
+    }
+
+    /// The weight of each new sample.
+    pub fn alpha(&self) -> f32 {
+        self.raw.alpha
+    }
+}
+
+impl Filter for LowpassFilter {
+    fn apply(&mut self, sample: Sample) -> Sample {
+        // SAFETY: `self.raw` was initialized in `new` and is exclusively borrowed
+        let filtered = unsafe { sys::lowpass_filter_apply(&mut self.raw, to_c(&sample)) };
+        Sample { gyro: sample.gyro, ..from_c(filtered) }
+    }
+
+    fn apply_n(&mut self, samples: &mut [Sample]) {
+        apply_chunked(samples, |buf| {
+            let samples = buf.as_mut_ptr();
+            // SAFETY: input and output are the same valid array of `buf.len()` samples, which the
+            // C code allows
+            unsafe { sys::lowpass_filter_apply_n(&mut self.raw, samples, samples, buf.len()) }
+        });
+    }
+
+    fn reset(&mut self) {
+        // SAFETY: `self.raw` was initialized in `new` and is exclusively borrowed
+        unsafe { sys::lowpass_filter_reset(&mut self.raw) };
+    }
+}
+
+/// Second order low-pass over the acceleration, computed by the C library. The angular rate is
+/// passed through.
+pub struct Biquad {
+    raw: sys::Biquad,
+}
+
+impl Biquad {
+    /// A low-pass with the given `cutoff` in Hz, below half the `sample_rate`. A `q` of 0.7071
+    /// gives the flattest pass band.
+    pub fn new(sample_rate: f32, cutoff: f32, q: f32) -> Result<Self, InvalidBiquad> {
+        let zero = sys::Sample { x: 0.0, y: 0.0, z: 0.0 };
+        let mut raw = sys::Biquad {
+            b0: 0.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            has_state: 0,
+            s1: zero,
+            s2: zero,
+        };
+        // SAFETY: `raw` is a valid, exclusively borrowed filter for the duration of the call
+        match unsafe { sys::biquad_init(&mut raw, sample_rate, cutoff, q) } {
+            0 => Ok(Self { raw }),
+            _ => Err(InvalidBiquad),
+        }
+    }
+}
+
+impl Filter for Biquad {
+    fn apply(&mut self, sample: Sample) -> Sample {
+        // SAFETY: `self.raw` was initialized in `new` and is exclusively borrowed
+        let filtered = unsafe { sys::biquad_apply(&mut self.raw, to_c(&sample)) };
+        Sample { gyro: sample.gyro, ..from_c(filtered) }
+    }
+
+    fn apply_n(&mut self, samples: &mut [Sample]) {
+        apply_chunked(samples, |buf| {
+            let samples = buf.as_mut_ptr();
+            // SAFETY: input and output are the same valid array of `buf.len()` samples, which the
+            // C code allows
+            unsafe { sys::biquad_apply_n(&mut self.raw, samples, samples, buf.len()) }
+        });
+    }
+
+    fn reset(&mut self) {
+        // SAFETY: `self.raw` was initialized in `new` and is exclusively borrowed
+        unsafe { sys::biquad_reset(&mut self.raw) };
+    }
+}
+
+/// The acceleration of a sample, as the C library takes it.
+pub fn to_c(sample: &Sample) -> sys::Sample {
+    sys::Sample {
+        x: sample.x,
+        y: sample.y,
+        z: sample.z,
+    }
+}
+
+/// A sample from the C library, without angular rate.
+pub fn from_c(sample: sys::Sample) -> Sample {
+    Sample::new(sample.x, sample.y, sample.z)
+}
@@ -0,0 +1,158 @@
+//! The C filters and the pure-Rust filters have to give the same output for the same input.
+//!
+//! Both compute `alpha * new + (1 - alpha) * previous` in f32, but the C compiler may fuse the
+//! multiply and add, so results are compared with a small tolerance.
+
+use lowpass_filter::{Biquad as CBiquad, Filter, LowpassFilter as CFilter};
+use proptest::prelude::*;
+use workshop_common::filter::{Biquad as RustBiquad, LowpassFilter as RustFilter};
+use workshop_common::{AngularRate, Sample};
+
+// Relative to the magnitude of the values, with a floor for values near zero
+const TOLERANCE: f32 = 1e-5;
+
+fn close(a: f32, b: f32) -> bool {
+    if a.is_nan() || b.is_nan() {
+        return a.is_nan() && b.is_nan();
+    }
+    a == b || (a - b).abs() <= TOLERANCE * a.abs().max(b.abs()).max(1.0)
+}
+
+fn assert_close(c: &Sample, rust: &Sample, step: usize) {
+    assert!(
+        close(c.x, rust.x) && close(c.y, rust.y) && close(c.z, rust.z),
+        "step {}: C {:?} Rust {:?}",
+        step,
+        c,
+        rust
+    );
+    assert_eq!(c.gyro, rust.gyro, "step {}", step);
+}
+
+/// Run both low-pass filters over the samples, checking every output.
+fn run_both(alpha: f32, samples: &[Sample]) -> Vec<Sample> {
+    compare(CFilter::new(alpha).unwrap(), RustFilter::new(alpha), samples)
+}
+
+fn compare(mut c: impl Filter, mut rust: impl Filter, samples: &[Sample]) -> Vec<Sample> {
+    samples
+        .iter()
+        .enumerate()
+        .map(|(step, sample)| {
+            let (c, rust) = (c.apply(*sample), rust.apply(*sample));
+            assert_close(&c, &rust, step);
+            rust
+        })
+        .collect()
+}
+
+fn sample() -> impl Strategy<Value = Sample> {
+    // Beyond the ±16 g of any sensor, some with angular rate
+    let axis = -64.0f32..64.0;
+    let rate = proptest::option::of((-2000.0f32..2000.0, -2000.0f32..2000.0, -2000.0f32..2000.0));
+    (axis.clone(), axis.clone(), axis, rate).prop_map(|(x, y, z, rate)| Sample {
+        gyro: rate.map(|(x, y, z)| AngularRate { x, y, z }),
+        ..Sample::new(x, y, z)
+    })
+}
+
+fn samples() -> impl Strategy<Value = Vec<Sample>> {
+    proptest::collection::vec(sample(), 1..200)
+}
+
+fn alpha() -> impl Strategy<Value = f32> {
+    prop_oneof![
+        0.0f32..=1.0,
+        Just(0.0),
+        Just(1.0),
+        Just(f32::EPSILON),
+        Just(1.0 - f32::EPSILON),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn outputs_agree(alpha in alpha(), samples in samples()) {
+        run_both(alpha, &samples);
+    }
+
+    #[test]
+    fn first_sample_passes_through(alpha in alpha(), sample in sample()) {
+        let mut c = CFilter::new(alpha).unwrap();
+        prop_assert_eq!(c.apply(sample), sample);
+        prop_assert_eq!(RustFilter::new(alpha).apply(sample), sample);
+    }
+
+    #[test]
+    fn alpha_zero_holds_first_sample(samples in samples()) {
+        for (step, output) in run_both(0.0, &samples).iter().enumerate() {
+            let first = samples[0];
+            prop_assert_eq!((output.x, output.y, output.z), (first.x, first.y, first.z), "step {}", step);
+        }
+    }
+
+    #[test]
+    fn alpha_one_follows_input(samples in samples()) {
+        for (output, sample) in run_both(1.0, &samples).iter().zip(&samples) {
+            prop_assert_eq!(output, sample);
+        }
+    }
+
+    #[test]
+    fn nan_propagates_alike(alpha in 0.0f32..=1.0, mut samples in samples(), at in any::<prop::sample::Index>(), axis in 0..3usize) {
+        let at = at.index(samples.len());
+        let nan = &mut samples[at];
+        *[&mut nan.x, &mut nan.y, &mut nan.z][axis] = f32::NAN;
+        // `close` treats NaN as equal only to NaN, so this also checks that both filters keep
+        // the NaN in their state for the following samples
+        run_both(alpha, &samples);
+    }
+
+    #[test]
+    fn batches_match_single_samples(alpha in alpha(), samples in samples(), reset_at in any::<prop::sample::Index>()) {
+        let reset_at = reset_at.index(samples.len());
+        let mut expected = samples.clone();
+        let mut rust = RustFilter::new(alpha);
+        for (i, sample) in expected.iter_mut().enumerate() {
+            if i == reset_at {
+                rust.reset();
+            }
+            *sample = rust.apply(*sample);
+        }
+
+        let mut c = CFilter::new(alpha).unwrap();
+        let mut batch = samples.clone();
+        let (before, after) = batch.split_at_mut(reset_at);
+        c.apply_n(before);
+        c.reset();
+        c.apply_n(after);
+        for (step, (c, rust)) in batch.iter().zip(&expected).enumerate() {
+            assert_close(c, rust, step);
+        }
+    }
+
+    #[test]
+    fn biquads_agree(rate in 10.0f32..7000.0, cutoff in 0.01f32..0.49, q in 0.3f32..5.0, samples in samples()) {
+        let cutoff = cutoff * rate;
+        compare(CBiquad::new(rate, cutoff, q).unwrap(), RustBiquad::new(rate, cutoff, q).unwrap(), &samples);
+    }
+
+    #[test]
+    fn invalid_alpha_is_rejected(alpha in prop_oneof![
+        Just(f32::NAN),
+        Just(f32::INFINITY),
+        Just(f32::NEG_INFINITY),
+        -1e6f32..-f32::MIN_POSITIVE,
+        1.0f32 + f32::EPSILON..1e6,
+    ]) {
+        prop_assert!(CFilter::new(alpha).is_err());
+    }
+}
+
+#[test]
+fn invalid_biquad_is_rejected() {
+    for (rate, cutoff, q) in [(100.0, 50.0, 0.7), (100.0, 0.0, 0.7), (100.0, 10.0, 0.0), (0.0, 10.0, 0.7), (f32::NAN, 10.0, 0.7), (100.0, f32::NAN, 0.7)] {
+        assert!(CBiquad::new(rate, cutoff, q).is_err(), "{} {} {}", rate, cutoff, q);
+        assert!(RustBiquad::new(rate, cutoff, q).is_err(), "{} {} {}", rate, cutoff, q);
+    }
+}